@@ -1,6 +1,12 @@
 //! The Bambu MQTT client.
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    sync::{
+        atomic::{AtomicBool, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
 
 use anyhow::{Context, Result};
 use dashmap::DashMap;
@@ -11,10 +17,17 @@ use crate::{
     message::{Message, Print, PushStatus},
     parser::parse_message,
     sequence_id::SequenceId,
+    tls::TlsConfig,
 };
 
-const MQTT_PORT: u16 = 8883;
+/// The port printers serve MQTT on.
+pub const MQTT_PORT: u16 = 8883;
 const MAX_PACKET_SIZE: usize = 1024 * 1024;
+/// How many status reports are kept for subscribers that fall behind.
+const STATUS_CHANNEL_CAPACITY: usize = 16;
+
+/// Makes sure the warning about not verifying certificates is only logged once.
+static INSECURE_TLS_WARNING: std::sync::Once = std::sync::Once::new();
 
 /// The Bambu MQTT client.
 #[derive(Clone)]
@@ -25,100 +38,208 @@ pub struct Client {
     pub access_code: String,
     /// The serial number.
     pub serial: String,
-
+    /// The MQTT client id.
+    pub client_id: String,
+    /// The MQTT port.
+    pub port: u16,
+    /// How the printer's certificate is checked.
+    pub tls: TlsConfig,
+
+    ssl_config: Arc<rustls::ClientConfig>,
     topic_device_request: String,
     topic_device_report: String,
 
-    client: Arc<rumqttc::AsyncClient>,
-    event_loop: Arc<Mutex<rumqttc::EventLoop>>,
+    connection: Arc<std::sync::RwLock<Connection>>,
 
     responses: Arc<DashMap<SequenceId, Message>>,
+    last_report: Arc<std::sync::Mutex<std::time::Instant>>,
+    auth_refused: Arc<AtomicBool>,
+    statuses: tokio::sync::broadcast::Sender<PushStatus>,
+}
+
+/// The MQTT connection to the printer. It is shared by every clone of a client, so replacing it
+/// on a reconnect reaches them all.
+struct Connection {
+    client: rumqttc::AsyncClient,
+    event_loop: Arc<Mutex<rumqttc::EventLoop>>,
+}
+
+impl Connection {
+    fn new(opts: rumqttc::MqttOptions) -> Self {
+        let (client, event_loop) = rumqttc::AsyncClient::new(opts, 25);
+
+        Self {
+            client,
+            event_loop: Arc::new(Mutex::new(event_loop)),
+        }
+    }
 }
 
 impl Client {
     /// Creates a new Bambu printer MQTT client.
     pub fn new<S: Into<String> + Clone>(ip: S, access_code: S, serial: S) -> Result<Self> {
+        Self::new_with_client_id(ip, access_code, serial, format!("bambu-api-{}", nanoid::nanoid!(8)))
+    }
+
+    /// Creates a new Bambu printer MQTT client with the given MQTT client id.
+    /// The printer disconnects clients that share an id, so it must be unique across every
+    /// process connecting to the same printer.
+    pub fn new_with_client_id<S: Into<String> + Clone>(
+        ip: S,
+        access_code: S,
+        serial: S,
+        client_id: impl Into<String>,
+    ) -> Result<Self> {
         let access_code = access_code.into();
         let ip = ip.into();
         let serial = serial.into();
+        let client_id = client_id.into();
 
-        let opts = Self::get_config(&ip, &access_code)?;
-        let (client, event_loop) = rumqttc::AsyncClient::new(opts, 25);
+        let tls = TlsConfig::default();
+        let ssl_config = Arc::new(tls.client_config()?);
+        let opts = Self::get_config(&ip, MQTT_PORT, &access_code, &client_id, ssl_config.clone());
 
         Ok(Self {
             ip,
             access_code,
+            tls,
+            ssl_config,
             topic_device_request: format!("device/{}/request", &serial),
             topic_device_report: format!("device/{}/report", &serial),
             serial,
-            client: Arc::new(client),
-            event_loop: Arc::new(Mutex::new(event_loop)),
+            client_id,
+            port: MQTT_PORT,
+            connection: Arc::new(std::sync::RwLock::new(Connection::new(opts))),
             responses: Arc::new(DashMap::new()),
+            last_report: Arc::new(std::sync::Mutex::new(std::time::Instant::now())),
+            auth_refused: Arc::new(AtomicBool::new(false)),
+            statuses: tokio::sync::broadcast::channel(STATUS_CHANNEL_CAPACITY).0,
         })
     }
 
-    fn get_config(ip: &str, access_code: &str) -> Result<rumqttc::MqttOptions> {
-        let client_id = format!("bambu-api-{}", nanoid::nanoid!(8));
+    /// Connect to the printer on a port other than the default MQTT port.
+    pub fn with_port(mut self, port: u16) -> Result<Self> {
+        self.port = port;
+        self.connection = Arc::new(std::sync::RwLock::new(Connection::new(self.mqtt_options())));
+
+        Ok(self)
+    }
+
+    /// Check the printer's certificate a different way.
+    ///
+    /// # Errors
+    ///
+    /// Returns an error if the pinned certificate can't be read or the system roots can't be
+    /// loaded.
+    pub fn with_tls(mut self, tls: TlsConfig) -> Result<Self> {
+        self.ssl_config = Arc::new(tls.client_config()?);
+        self.tls = tls;
+        self.connection = Arc::new(std::sync::RwLock::new(Connection::new(self.mqtt_options())));
+
+        Ok(self)
+    }
+
+    /// The options to connect to the printer with.
+    fn mqtt_options(&self) -> rumqttc::MqttOptions {
+        Self::get_config(
+            &self.ip,
+            self.port,
+            &self.access_code,
+            &self.client_id,
+            self.ssl_config.clone(),
+        )
+    }
+
+    /// The current MQTT client, replaced by `reconnect`.
+    fn mqtt_client(&self) -> rumqttc::AsyncClient {
+        self.connection.read().unwrap_or_else(|e| e.into_inner()).client.clone()
+    }
 
-        let ssl_config = rustls::ClientConfig::builder()
-            .dangerous()
-            .with_custom_certificate_verifier(Arc::new(crate::no_auth::NoAuth::new()))
-            .with_no_client_auth();
+    /// Replace the connection to the printer with a new one, for this client and every clone of
+    /// it. The next `run` connects it and subscribes to the reports again.
+    pub fn reconnect(&self) {
+        *self.connection.write().unwrap_or_else(|e| e.into_inner()) = Connection::new(self.mqtt_options());
+    }
 
-        let mut opts = rumqttc::MqttOptions::new(client_id, ip, MQTT_PORT);
+    fn get_config(
+        ip: &str,
+        port: u16,
+        access_code: &str,
+        client_id: &str,
+        ssl_config: Arc<rustls::ClientConfig>,
+    ) -> rumqttc::MqttOptions {
+        let mut opts = rumqttc::MqttOptions::new(client_id, ip, port);
         opts.set_max_packet_size(MAX_PACKET_SIZE, MAX_PACKET_SIZE);
         opts.set_keep_alive(Duration::from_secs(5));
         opts.set_credentials("bblp", access_code);
-        opts.set_transport(rumqttc::Transport::Tls(rumqttc::TlsConfiguration::Rustls(Arc::new(
-            ssl_config,
-        ))));
+        opts.set_transport(rumqttc::Transport::Tls(rumqttc::TlsConfiguration::Rustls(ssl_config)));
 
-        Ok(opts)
+        opts
     }
 
     /// Polls for a message from the MQTT event loop.
     /// You need to poll periodically to receive messages
     /// and to keep the connection alive.
-    /// This function also handles reconnects.
     ///
     /// **NOTE** Don't block this while iterating
     ///
     /// # Errors
     ///
-    /// Returns an error if there was a problem polling for a message or parsing the event.
-    async fn poll(&mut self) -> Result<()> {
-        let mut ep = self.event_loop.lock().await;
-        let msg_opt = match ep.poll().await {
+    /// Returns an error if the connection failed or dropped, after which the client needs to
+    /// `reconnect`.
+    async fn poll(&self) -> Result<()> {
+        let event_loop = self
+            .connection
+            .read()
+            .unwrap_or_else(|e| e.into_inner())
+            .event_loop
+            .clone();
+        let msg_opt = match event_loop.lock().await.poll().await {
             Ok(msg_opt) => msg_opt,
             Err(err) => {
-                if let rumqttc::ConnectionError::MqttState(rumqttc::StateError::Io(err)) = err {
-                    tracing::error!("Error polling for message: {:?}", err);
-                    tracing::warn!("Reconnecting...");
-                    // We are in a bad state and should reconnect.
-                    let opts = Self::get_config(&self.ip, &self.access_code)?;
-                    let (client, event_loop) = rumqttc::AsyncClient::new(opts, 25);
-                    drop(ep);
-                    self.client = Arc::new(client);
-                    self.event_loop = Arc::new(Mutex::new(event_loop));
-                    tracing::warn!("Reconnected.");
-                    return Ok(());
+                if let rumqttc::ConnectionError::ConnectionRefused(
+                    rumqttc::ConnectReturnCode::BadUserNamePassword | rumqttc::ConnectReturnCode::NotAuthorized,
+                ) = err
+                {
+                    self.auth_refused.store(true, Ordering::SeqCst);
                 }
 
-                tracing::error!("Error polling for message: {:?}", err);
-                return Ok(());
+                return Err(err).context("The connection to the printer failed");
             }
         };
 
+        if let rumqttc::Event::Incoming(rumqttc::Incoming::ConnAck(_)) = &msg_opt {
+            self.auth_refused.store(false, Ordering::SeqCst);
+        }
+
         let message = parse_message(&msg_opt);
 
+        if let rumqttc::Event::Incoming(rumqttc::Incoming::Publish(_)) = &msg_opt {
+            if let Ok(mut last_report) = self.last_report.lock() {
+                *last_report = std::time::Instant::now();
+            }
+        }
+
         if let Some(sequence_id) = message.sequence_id() {
             // If the message is a push status, make the sequence id "status".
-            if let Message::Print(Print::PushStatus(_)) = &message {
-                self.responses.insert(SequenceId::status(), message);
+            if let Message::Print(Print::PushStatus(status)) = message {
+                // It's fine if nobody is listening.
+                let _ = self.statuses.send(status.clone());
+
+                // Reports only hold what changed, so keep the last known value of everything else.
+                let status = match self.get_status()? {
+                    Some(mut last_known) => {
+                        last_known.merge(status);
+                        last_known
+                    }
+                    None => status,
+                };
+                self.responses
+                    .insert(SequenceId::status(), Message::Print(Print::PushStatus(status)));
                 return Ok(());
             }
 
-            self.responses.insert(sequence_id, message);
+            self.responses.insert(sequence_id.normalize(), message);
             return Ok(());
         }
 
@@ -131,7 +252,7 @@ impl Client {
         Ok(())
     }
 
-    /// Get the latest status of the printer.
+    /// Get the last known status of the printer, from every report it has sent.
     pub fn get_status(&self) -> Result<Option<PushStatus>> {
         let response = self.responses.get(&SequenceId::status());
         if let Some(response) = response {
@@ -143,8 +264,28 @@ impl Client {
         Ok(None)
     }
 
+    /// Subscribe to the status reports of the printer as they arrive, each with only what changed.
+    /// Only the latest reports are kept for subscribers; one that falls too far behind skips the
+    /// oldest and gets a [`tokio::sync::broadcast::error::RecvError::Lagged`].
+    pub fn subscribe_status(&self) -> tokio::sync::broadcast::Receiver<PushStatus> {
+        self.statuses.subscribe()
+    }
+
+    /// Get when the printer last sent a report, or when the client was created if it hasn't yet.
+    pub fn last_report(&self) -> std::time::Instant {
+        self.last_report
+            .lock()
+            .map(|last_report| *last_report)
+            .unwrap_or_else(|e| *e.into_inner())
+    }
+
+    /// Whether the printer refused the access code the last time the client connected.
+    pub fn auth_refused(&self) -> bool {
+        self.auth_refused.load(Ordering::SeqCst)
+    }
+
     async fn subscribe_to_device_report(&self) -> Result<()> {
-        self.client
+        self.mqtt_client()
             .subscribe(&self.topic_device_report, rumqttc::mqttbytes::QoS::AtMostOnce)
             .await?;
 
@@ -156,9 +297,16 @@ impl Client {
     ///
     /// # Errors
     ///
-    /// Returns an error if there was a problem connecting to the MQTT broker
-    /// or subscribing to the device report topic.
+    /// Returns an error if there was a problem connecting to the MQTT broker,
+    /// subscribing to the device report topic or the connection dropped. Call
+    /// `reconnect` before running the client again.
     pub async fn run(&mut self) -> Result<()> {
+        if self.tls == TlsConfig::InsecureSkipVerify {
+            INSECURE_TLS_WARNING.call_once(|| {
+                tracing::warn!("Not verifying printer certificates, pin them with a tls config to verify them");
+            });
+        }
+
         self.subscribe_to_device_report().await?;
 
         loop {
@@ -170,25 +318,31 @@ impl Client {
     ///
     /// # Errors
     ///
-    /// Returns an error if there was a problem publishing the command.
+    /// Returns an error if there was a problem publishing the command, or the printer doesn't
+    /// answer it. Send those with [`Client::publish_without_response`].
     pub async fn publish(&self, command: Command) -> Result<Message> {
-        let sequence_id = command.sequence_id();
-        let payload = serde_json::to_string(&command)?;
+        // Otherwise this would wait out the timeout for an answer that never comes.
+        if !command.has_response() {
+            anyhow::bail!("The printer doesn't answer {:?}, use publish_without_response", command);
+        }
 
-        self.client
-            .publish(
-                &self.topic_device_request,
-                rumqttc::mqttbytes::QoS::AtMostOnce,
-                false,
-                payload,
-            )
-            .await?;
+        let sequence_id = command.sequence_id().clone().normalize();
+
+        // Clear out any stale response with the same sequence id.
+        self.responses.remove(&sequence_id);
+
+        self.publish_without_response(&command).await?;
 
         // Wait for the response.
         let current_time = std::time::Instant::now();
         while current_time.elapsed().as_secs() < 60 {
-            if let Some(response) = self.responses.get(sequence_id) {
-                return Ok(response.value().clone());
+            // Other clients can use the same sequence ids, so make sure the response is actually
+            // for this command.
+            if let Some((_, response)) = self
+                .responses
+                .remove_if(&sequence_id, |_, response| command.is_response(response))
+            {
+                return Ok(response);
             }
             // This sleep is important since it frees up the thread.
             tokio::time::sleep(Duration::from_secs(1)).await;
@@ -197,9 +351,35 @@ impl Client {
         anyhow::bail!("Timeout waiting for response to command: {:?}", command)
     }
 
+    /// Publish a command without waiting for a response, for commands the printer never
+    /// answers (like a reboot).
+    pub async fn publish_without_response(&self, command: &Command) -> Result<()> {
+        let payload = serde_json::to_string(command)?;
+
+        self.mqtt_client()
+            .publish(
+                &self.topic_device_request,
+                rumqttc::mqttbytes::QoS::AtMostOnce,
+                false,
+                payload,
+            )
+            .await?;
+
+        Ok(())
+    }
+
+    /// The printer's host as it goes in a URL, with IPv6 addresses in brackets.
+    /// The MQTT connection takes the bare address, it's only URLs that need them.
+    pub fn url_host(&self) -> String {
+        match self.ip.parse::<std::net::IpAddr>() {
+            Ok(std::net::IpAddr::V6(ip)) => format!("[{}]", ip),
+            _ => self.ip.clone(),
+        }
+    }
+
     /// Upload a file.
     pub async fn upload_file(&self, path: &std::path::Path) -> Result<()> {
-        let host_url = url::Url::parse(&format!("mqtts://{}:{}", self.ip, MQTT_PORT))?;
+        let host_url = url::Url::parse(&format!("mqtts://{}:{}", self.url_host(), MQTT_PORT))?;
         let host = host_url
             .host_str()
             .ok_or(anyhow::anyhow!("not a valid hostname"))?
@@ -238,4 +418,288 @@ impl Client {
 
         Ok(())
     }
+
+    /// Get the size in bytes of a file on the printer, or `None` if the printer did not report it.
+    pub async fn file_size(&self, filename: &str) -> Result<Option<u64>> {
+        let args: Vec<String> = vec![
+            "--silent".to_string(),
+            "--head".to_string(),
+            "--ftp-pasv".to_string(),
+            "--insecure".to_string(),
+            format!("ftps://{}/{}", self.url_host(), filename),
+            "--user".to_string(),
+            format!("bblp:{}", self.access_code),
+        ];
+        let output = tokio::process::Command::new("curl")
+            .args(&args)
+            .output()
+            .await
+            .context("Failed to get file size")?;
+
+        if !output.status.success() {
+            let stderr = std::str::from_utf8(&output.stderr)?;
+            anyhow::bail!(
+                "Failed to get size of {}: {:?}\nstderr:{}",
+                filename,
+                output.status,
+                stderr
+            );
+        }
+
+        Ok(parse_content_length(std::str::from_utf8(&output.stdout)?))
+    }
+
+    /// List the files on the printer, where uploads go. Directories are left out.
+    pub async fn list_files(&self) -> Result<Vec<StoredFile>> {
+        let args: Vec<String> = vec![
+            "--silent".to_string(),
+            "--show-error".to_string(),
+            "--ftp-pasv".to_string(),
+            "--insecure".to_string(),
+            format!("ftps://{}/", self.url_host()),
+            "--user".to_string(),
+            format!("bblp:{}", self.access_code),
+        ];
+        let output = tokio::process::Command::new("curl")
+            .args(&args)
+            .output()
+            .await
+            .context("Failed to list files")?;
+
+        if !output.status.success() {
+            return Err(ftp_error("list files", &output));
+        }
+
+        Ok(parse_file_listing(
+            &String::from_utf8_lossy(&output.stdout),
+            std::time::SystemTime::now().into(),
+        ))
+    }
+
+    /// Delete a file on the printer.
+    pub async fn delete_file(&self, filename: &str) -> Result<()> {
+        // The name goes into an FTP command, where a line break would start another one.
+        if filename.is_empty() || filename.contains(['/', '\r', '\n']) {
+            anyhow::bail!("Bad filename: {:?}", filename);
+        }
+
+        let args: Vec<String> = vec![
+            "--silent".to_string(),
+            "--show-error".to_string(),
+            "--list-only".to_string(),
+            "--ftp-pasv".to_string(),
+            "--insecure".to_string(),
+            "--quote".to_string(),
+            format!("DELE {}", filename),
+            format!("ftps://{}/", self.url_host()),
+            "--user".to_string(),
+            format!("bblp:{}", self.access_code),
+        ];
+        let output = tokio::process::Command::new("curl")
+            .args(&args)
+            .output()
+            .await
+            .context("Failed to delete file")?;
+
+        if !output.status.success() {
+            return Err(ftp_error(&format!("delete {}", filename), &output));
+        }
+
+        Ok(())
+    }
+}
+
+/// A file stored on the printer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StoredFile {
+    /// The name of the file.
+    pub name: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// When the file was last modified, if the printer said.
+    pub modified: Option<std::time::SystemTime>,
+}
+
+/// Describe why curl failed to talk to the printer's FTP server.
+fn ftp_error(action: &str, output: &std::process::Output) -> anyhow::Error {
+    let reason = match output.status.code() {
+        Some(6 | 7 | 28) => "the printer could not be reached".to_string(),
+        Some(35) => "the TLS connection to the printer failed".to_string(),
+        Some(67) => "the printer rejected the access code".to_string(),
+        Some(21) => "the printer refused, is the file there?".to_string(),
+        _ => format!(
+            "{:?}: {}",
+            output.status,
+            String::from_utf8_lossy(&output.stderr).trim()
+        ),
+    };
+
+    anyhow::anyhow!("Failed to {}: {}", action, reason)
+}
+
+/// Parse the `ls -l` style listing the printer's FTP server sends, e.g.
+/// `-rw-rw-rw- 1 root root 1234 Oct 01 10:00 benchy.3mf`.
+/// Times are taken as UTC. Like `ls`, the year is left out for recent files, so it's the year
+/// of `now` unless that would put the file in the future.
+fn parse_file_listing(listing: &str, now: chrono::DateTime<chrono::Utc>) -> Vec<StoredFile> {
+    listing
+        .lines()
+        .filter_map(|line| {
+            // The name is everything after the 8th field, and can have spaces in it.
+            let mut rest = line;
+            let mut fields = Vec::with_capacity(8);
+            for _ in 0..8 {
+                rest = rest.trim_start();
+                let end = rest.find(char::is_whitespace)?;
+                fields.push(&rest[..end]);
+                rest = &rest[end..];
+            }
+            let name = rest.trim();
+
+            // Only regular files, not directories or links.
+            if !fields[0].starts_with('-') || name.is_empty() {
+                return None;
+            }
+
+            Some(StoredFile {
+                name: name.to_string(),
+                size: fields[4].parse().ok()?,
+                modified: parse_listing_time(fields[5], fields[6], fields[7], now),
+            })
+        })
+        .collect()
+}
+
+/// Parse the time of a file in an `ls -l` style listing, either `Oct 01 10:00` or `Oct 01 2023`.
+fn parse_listing_time(
+    month: &str,
+    day: &str,
+    time_or_year: &str,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Option<std::time::SystemTime> {
+    use chrono::Datelike;
+
+    const MONTHS: [&str; 12] = [
+        "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+    ];
+    let month = MONTHS.iter().position(|m| m.eq_ignore_ascii_case(month))? as u32 + 1;
+    let day = day.parse().ok()?;
+
+    let modified = match time_or_year.split_once(':') {
+        Some((hour, minute)) => {
+            let (hour, minute) = (hour.parse().ok()?, minute.parse().ok()?);
+            let this_year = chrono::NaiveDate::from_ymd_opt(now.year(), month, day)?
+                .and_hms_opt(hour, minute, 0)?
+                .and_utc();
+            // Allow for the printer's clock being a little ahead.
+            if this_year > now + chrono::Duration::days(1) {
+                chrono::NaiveDate::from_ymd_opt(now.year() - 1, month, day)?
+                    .and_hms_opt(hour, minute, 0)?
+                    .and_utc()
+            } else {
+                this_year
+            }
+        }
+        None => chrono::NaiveDate::from_ymd_opt(time_or_year.parse().ok()?, month, day)?
+            .and_hms_opt(0, 0, 0)?
+            .and_utc(),
+    };
+
+    Some(modified.into())
+}
+
+/// Parse the `Content-Length` header curl prints for `--head` on an ftp url.
+fn parse_content_length(headers: &str) -> Option<u64> {
+    headers.lines().find_map(|line| {
+        let (name, value) = line.split_once(':')?;
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            value.trim().parse().ok()
+        } else {
+            None
+        }
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_content_length() {
+        assert_eq!(
+            parse_content_length(
+                "Last-Modified: Tue, 01 Oct 2024 10:00:00 GMT\r\nContent-Length: 1234\r\nAccept-ranges: bytes\r\n"
+            ),
+            Some(1234)
+        );
+        assert_eq!(parse_content_length("Accept-ranges: bytes\r\n"), None);
+    }
+
+    #[test]
+    fn test_parse_file_listing() {
+        let now = chrono::NaiveDate::from_ymd_opt(2024, 10, 15)
+            .unwrap()
+            .and_hms_opt(12, 0, 0)
+            .unwrap()
+            .and_utc();
+        let at = |y, m, d, h, min| -> Option<std::time::SystemTime> {
+            Some(
+                chrono::NaiveDate::from_ymd_opt(y, m, d)
+                    .unwrap()
+                    .and_hms_opt(h, min, 0)
+                    .unwrap()
+                    .and_utc()
+                    .into(),
+            )
+        };
+
+        let listing = "drwxrwxrwx    2 root     root         4096 Jan 01  1970 cache\r\n\
+            -rw-rw-rw-    1 root     root      1843921 Oct 01 10:00 benchy.gcode.3mf\r\n\
+            -rw-rw-rw-    1 root     root       512000 Dec 24 18:30 tree ornament.3mf\r\n\
+            -rw-rw-rw-    1 root     root        12345 Mar 05  2023 old.gcode\r\n\
+            lrwxrwxrwx    1 root     root            4 Oct 01 10:00 link -> cache\r\n";
+
+        assert_eq!(
+            parse_file_listing(listing, now),
+            [
+                StoredFile {
+                    name: "benchy.gcode.3mf".to_string(),
+                    size: 1843921,
+                    modified: at(2024, 10, 1, 10, 0),
+                },
+                // In the future this year, so it's from last year.
+                StoredFile {
+                    name: "tree ornament.3mf".to_string(),
+                    size: 512000,
+                    modified: at(2023, 12, 24, 18, 30),
+                },
+                StoredFile {
+                    name: "old.gcode".to_string(),
+                    size: 12345,
+                    modified: at(2023, 3, 5, 0, 0),
+                },
+            ]
+        );
+
+        // Nothing on the printer.
+        assert!(parse_file_listing("", now).is_empty());
+
+        // A time that can't be parsed doesn't lose the file.
+        let odd = parse_file_listing("-rw-rw-rw- 1 root root 10 Foo 01 10:00 odd.3mf", now);
+        assert_eq!(odd[0].modified, None);
+    }
+
+    #[test]
+    fn test_url_host() {
+        let client = Client::new("192.0.2.10", "12345678", "00M09A350100123").unwrap();
+        assert_eq!(client.url_host(), "192.0.2.10");
+
+        let client = Client::new("2001:db8::10", "12345678", "00M09A350100123").unwrap();
+        assert_eq!(client.url_host(), "[2001:db8::10]");
+        // Connecting takes the address as it is.
+        assert_eq!(client.ip, "2001:db8::10");
+
+        let client = Client::new("printer.local", "12345678", "00M09A350100123").unwrap();
+        assert_eq!(client.url_host(), "printer.local");
+    }
 }
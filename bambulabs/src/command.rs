@@ -4,7 +4,11 @@ use parse_display::{Display, FromStr};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{sequence_id::SequenceId, speedprofile::SpeedProfile};
+use crate::{
+    message::{self, Message},
+    sequence_id::SequenceId,
+    speedprofile::SpeedProfile,
+};
 
 /// The commands that can be sent to the printer.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
@@ -31,6 +35,65 @@ impl Command {
         }
     }
 
+    /// Check if the printer answers this command. Pushing commands get reports rather than an
+    /// answer, and a rebooting printer goes away without one.
+    pub fn has_response(&self) -> bool {
+        !matches!(self, Command::Pushing(_) | Command::System(System::Reboot(_)))
+    }
+
+    /// Check if a message is the printer's response to this command.
+    pub fn is_response(&self, message: &Message) -> bool {
+        if message.sequence_id().map(SequenceId::normalize) != Some(self.sequence_id().clone().normalize()) {
+            return false;
+        }
+
+        matches!(
+            (self, message),
+            (
+                Command::Info(Info::GetVersion(_)),
+                Message::Info(message::Info::GetVersion(_))
+            ) | (
+                Command::Print(Print::Pause(_)),
+                Message::Print(message::Print::Pause(_))
+            ) | (
+                Command::Print(Print::Resume(_)),
+                Message::Print(message::Print::Resume(_))
+            ) | (Command::Print(Print::Stop(_)), Message::Print(message::Print::Stop(_)))
+                | (
+                    Command::Print(Print::PrintSpeed(_)),
+                    Message::Print(message::Print::PrintSpeed(_))
+                )
+                | (
+                    Command::Print(Print::GcodeLine(_)),
+                    Message::Print(message::Print::GcodeLine(_))
+                )
+                | (
+                    Command::Print(Print::ProjectFile(_)),
+                    Message::Print(message::Print::ProjectFile(_))
+                )
+                | (
+                    Command::Print(Print::Calibration(_)),
+                    Message::Print(message::Print::Calibration(_))
+                )
+                | (
+                    Command::Print(Print::CleanPrintError(_)),
+                    Message::Print(message::Print::CleanPrintError(_))
+                )
+                | (
+                    Command::Print(Print::SkipObjects(_)),
+                    Message::Print(message::Print::SkipObjects(_))
+                )
+                | (
+                    Command::System(System::Ledctrl(_)),
+                    Message::System(message::System::Ledctrl(_))
+                )
+                | (
+                    Command::System(System::GetAccessories(_)),
+                    Message::System(message::System::GetAccessories(_))
+                )
+        )
+    }
+
     /// Return a command to get the version of the printer.
     pub fn get_version() -> Self {
         Command::Info(Info::GetVersion(GetVersion {
@@ -82,6 +145,32 @@ impl Command {
         }))
     }
 
+    /// Return a command to start a calibration.
+    pub fn calibration(options: CalibrationOptions) -> Self {
+        Command::Print(Print::Calibration(Calibration {
+            sequence_id: SequenceId::new(),
+            option: options.bits(),
+        }))
+    }
+
+    /// Return a command to stop printing some objects of the current plate, leaving the rest to
+    /// finish.
+    pub fn skip_objects(object_ids: &[u32]) -> Self {
+        Command::Print(Print::SkipObjects(SkipObjects {
+            sequence_id: SequenceId::new(),
+            obj_list: object_ids.to_vec(),
+        }))
+    }
+
+    /// Return a command to clear the print error the printer is reporting.
+    pub fn clean_print_error(subtask_id: &str, print_error: i64) -> Self {
+        Command::Print(Print::CleanPrintError(CleanPrintError {
+            sequence_id: SequenceId::new(),
+            subtask_id: subtask_id.to_string(),
+            print_error,
+        }))
+    }
+
     /// Return a command to set the chamber light.
     pub fn set_chamber_light(led_mode: LedMode) -> Self {
         Command::System(System::Ledctrl(Ledctrl {
@@ -103,6 +192,14 @@ impl Command {
         }))
     }
 
+    /// Return a command to reboot the printer.
+    /// The printer drops the connection instead of responding to this one.
+    pub fn reboot() -> Self {
+        Command::System(System::Reboot(Reboot {
+            sequence_id: SequenceId::new(),
+        }))
+    }
+
     /// Return a command to print a file on the ftp server.
     pub fn print_file(job_name: &str, filename: &str, use_ams: bool) -> Self {
         Command::Print(Print::ProjectFile(ProjectFile {
@@ -160,6 +257,12 @@ pub enum Print {
     GcodeLine(GcodeLine),
     /// Start a print with a file on the ftp server.
     ProjectFile(ProjectFile),
+    /// Start a calibration.
+    Calibration(Calibration),
+    /// Clear the print error.
+    CleanPrintError(CleanPrintError),
+    /// Stop printing some objects.
+    SkipObjects(SkipObjects),
 }
 
 impl Print {
@@ -172,6 +275,9 @@ impl Print {
             Print::PrintSpeed(PrintSpeed { sequence_id, .. }) => sequence_id,
             Print::GcodeLine(GcodeLine { sequence_id, .. }) => sequence_id,
             Print::ProjectFile(ProjectFile { sequence_id, .. }) => sequence_id,
+            Print::Calibration(Calibration { sequence_id, .. }) => sequence_id,
+            Print::CleanPrintError(CleanPrintError { sequence_id, .. }) => sequence_id,
+            Print::SkipObjects(SkipObjects { sequence_id, .. }) => sequence_id,
         }
     }
 }
@@ -184,6 +290,8 @@ pub enum System {
     Ledctrl(Ledctrl),
     /// Get accessories.
     GetAccessories(GetAccessories),
+    /// Reboot the printer.
+    Reboot(Reboot),
 }
 
 impl System {
@@ -192,6 +300,7 @@ impl System {
         match self {
             System::Ledctrl(Ledctrl { sequence_id, .. }) => sequence_id,
             System::GetAccessories(GetAccessories { sequence_id, .. }) => sequence_id,
+            System::Reboot(Reboot { sequence_id }) => sequence_id,
         }
     }
 }
@@ -297,6 +406,58 @@ pub enum BedType {
     Pte,
 }
 
+/// The payload for starting a calibration.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Calibration {
+    /// The sequence ID.
+    pub sequence_id: SequenceId,
+    /// The bitmask of calibrations to run.
+    pub option: u32,
+}
+
+/// The payload for clearing the print error.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct CleanPrintError {
+    /// The sequence ID.
+    pub sequence_id: SequenceId,
+    /// The subtask the error belongs to.
+    pub subtask_id: String,
+    /// The print error code to clear.
+    pub print_error: i64,
+}
+
+/// The payload for skipping objects.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct SkipObjects {
+    /// The sequence ID.
+    pub sequence_id: SequenceId,
+    /// The IDs of the objects to skip, as given in the plate's slice info.
+    pub obj_list: Vec<u32>,
+}
+
+/// The calibrations to run.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct CalibrationOptions {
+    /// Calibrate the lidar (xcam).
+    pub lidar: bool,
+    /// Level the bed.
+    pub bed_leveling: bool,
+    /// Vibration compensation.
+    pub vibration_compensation: bool,
+    /// Motor noise cancellation.
+    pub motor_noise_cancellation: bool,
+}
+
+impl CalibrationOptions {
+    /// Get the bitmask the printer expects for these options.
+    pub fn bits(&self) -> u32 {
+        (self.lidar as u32)
+            | (self.bed_leveling as u32) << 1
+            | (self.vibration_compensation as u32) << 2
+            | (self.motor_noise_cancellation as u32) << 3
+    }
+}
+
 /// The payload for getting all device information.
 #[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
 pub struct Pushall {
@@ -391,6 +552,13 @@ pub struct GetAccessories {
     pub accessory_type: AccessoryType,
 }
 
+/// The payload for rebooting the printer.
+#[derive(Debug, Clone, Serialize, Deserialize, PartialEq, Eq)]
+pub struct Reboot {
+    /// The sequence ID.
+    pub sequence_id: SequenceId,
+}
+
 /// The type of accessory.
 #[derive(Debug, Clone, Serialize, Deserialize, Display, FromStr, PartialEq, Eq, JsonSchema)]
 #[display(style = "snake_case")]
@@ -512,7 +680,7 @@ mod tests {
     fn test_deserialize_print_speed() {
         let uid = SequenceId::new();
         let payload = format!(
-            r#"{{"print": {{"sequence_id": {uid}, "command": "print_speed", "param": "standard"}}}}"#,
+            r#"{{"print": {{"sequence_id": {uid}, "command": "print_speed", "param": "2"}}}}"#,
             uid = uid
         );
         let command: Command = serde_json::from_str(&payload).unwrap();
@@ -540,6 +708,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn test_deserialize_calibration() {
+        let uid = SequenceId::new();
+        let payload = format!(
+            r#"{{"print": {{"sequence_id": {uid}, "command": "calibration", "option": 2}}}}"#,
+            uid = uid
+        );
+        let command: Command = serde_json::from_str(&payload).unwrap();
+        if let Command::Print(Print::Calibration(Calibration { sequence_id, option })) = command {
+            assert_eq!(sequence_id, uid);
+            assert_eq!(option, 2);
+        } else {
+            panic!("Invalid command deserialized");
+        }
+    }
+
     #[test]
     fn test_deserialize_get_accessories() {
         let uid = SequenceId::new();
@@ -677,7 +861,7 @@ mod tests {
         assert_eq!(
             payload,
             format!(
-                r#"{{"print":{{"command":"print_speed","sequence_id":{uid},"param":"standard"}}}}"#,
+                r#"{{"print":{{"command":"print_speed","sequence_id":{uid},"param":"2"}}}}"#,
                 uid = uid
             )
         );
@@ -723,10 +907,24 @@ mod tests {
         let payload = serde_json::to_string(&command).unwrap();
         assert_eq!(
             payload,
-            r#"{"print":{"command":"print_speed","sequence_id":1,"param":"standard"}}"#
+            r#"{"print":{"command":"print_speed","sequence_id":1,"param":"2"}}"#
         );
     }
 
+    #[test]
+    fn test_speed_profile_wire_values() {
+        for (profile, level) in [
+            (SpeedProfile::Silent, 1),
+            (SpeedProfile::Standard, 2),
+            (SpeedProfile::Sport, 3),
+            (SpeedProfile::Ludicrous, 4),
+        ] {
+            assert_eq!(serde_json::to_string(&profile).unwrap(), format!(r#""{}""#, level));
+            assert_eq!(SpeedProfile::from_level(level), Some(profile));
+        }
+        assert_eq!(SpeedProfile::from_level(0), None);
+    }
+
     #[test]
     fn test_send_gcode_line() {
         let command = Command::send_gcode_line("G28");
@@ -737,6 +935,85 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_calibration() {
+        let command = Command::calibration(CalibrationOptions {
+            bed_leveling: true,
+            vibration_compensation: true,
+            ..Default::default()
+        });
+        let payload = serde_json::to_string(&command).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"print":{"command":"calibration","sequence_id":1,"option":6}}"#
+        );
+    }
+
+    #[test]
+    fn test_clean_print_error() {
+        let command = Command::clean_print_error("12345", 50348044);
+        let payload = serde_json::to_string(&command).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"print":{"command":"clean_print_error","sequence_id":1,"subtask_id":"12345","print_error":50348044}}"#
+        );
+    }
+
+    #[test]
+    fn test_skip_objects() {
+        let command = Command::skip_objects(&[181, 433]);
+        let payload = serde_json::to_string(&command).unwrap();
+        assert_eq!(
+            payload,
+            r#"{"print":{"command":"skip_objects","sequence_id":1,"obj_list":[181,433]}}"#
+        );
+
+        let response: Message = serde_json::from_str(
+            r#"{"print": {"command": "skip_objects", "sequence_id": "1", "obj_list": [181, 433], "result": "SUCCESS"}}"#,
+        )
+        .unwrap();
+        assert!(command.is_response(&response));
+    }
+
+    #[test]
+    fn test_is_response() {
+        let pause = Command::pause();
+        let version = Command::get_version();
+
+        let response: Message = serde_json::from_str(
+            r#"{"print": {"command": "pause", "sequence_id": "1", "reason": "SUCCESS", "result": "SUCCESS"}}"#,
+        )
+        .unwrap();
+
+        assert!(pause.is_response(&response));
+        assert!(!version.is_response(&response));
+    }
+
+    #[test]
+    fn test_has_response() {
+        assert!(Command::pause().has_response());
+        assert!(Command::get_accessories().has_response());
+
+        assert!(!Command::push_all().has_response());
+        assert!(!Command::Pushing(Pushing::Start(Start {
+            sequence_id: SequenceId::new(),
+        }))
+        .has_response());
+        assert!(!Command::reboot().has_response());
+    }
+
+    #[test]
+    fn test_is_response_other_sequence_id() {
+        let pause = Command::pause();
+
+        let response: Message = serde_json::from_str(
+            r#"{"print": {"command": "pause", "sequence_id": "2", "reason": "SUCCESS", "result": "SUCCESS"}}"#,
+        )
+        .unwrap();
+
+        assert!(!pause.is_response(&response));
+    }
+
     #[test]
     fn test_set_chamber_light() {
         let command = Command::set_chamber_light(LedMode::On);
@@ -757,6 +1034,13 @@ mod tests {
         );
     }
 
+    #[test]
+    fn test_reboot() {
+        let command = Command::reboot();
+        let payload = serde_json::to_string(&command).unwrap();
+        assert_eq!(payload, r#"{"system":{"command":"reboot","sequence_id":1}}"#);
+    }
+
     #[test]
     fn test_print_file() {
         let command = Command::print_file("myjob", "thing.3mf", true);
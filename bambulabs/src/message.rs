@@ -15,6 +15,7 @@ use crate::{
 /// A message from/to the printer.
 #[derive(Serialize, Deserialize, JsonSchema, Debug, Clone, PartialEq)]
 #[serde(rename_all = "snake_case")]
+#[allow(clippy::large_enum_variant)]
 pub enum Message {
     /// A print message.
     Print(Print),
@@ -45,6 +46,19 @@ impl Message {
             Message::Json(_) | Message::Unknown(_) => None,
         }
     }
+
+    /// Returns the message as JSON, in the form the printer sends it, for debugging.
+    /// Parsed messages are serialized again, so fields the parser doesn't know about are
+    /// missing. An unknown message that isn't JSON is returned as a string.
+    pub fn raw_json(&self) -> anyhow::Result<Value> {
+        match self {
+            Message::Json(value) => Ok(value.clone()),
+            Message::Unknown(Some(raw)) => Ok(serde_json::from_str(raw).unwrap_or_else(|_| Value::String(raw.clone()))),
+            Message::Unknown(None) => Ok(Value::Null),
+            // The other variants are tagged with the key the printer wraps them in.
+            message => Ok(serde_json::to_value(message)?),
+        }
+    }
 }
 
 impl From<Print> for Message {
@@ -197,6 +211,7 @@ pub enum Result {
 /// A print command.
 #[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
 #[serde(rename_all = "snake_case", tag = "command")]
+#[allow(clippy::large_enum_variant)]
 pub enum Print {
     /// Ams control.
     AmsControl(AmsControl),
@@ -204,6 +219,10 @@ pub enum Print {
     AmsChangeFilament(AmsChangeFilament),
     /// Calibration.
     Calibration(Calibration),
+    /// Clean print error.
+    CleanPrintError(CleanPrintError),
+    /// Skip objects.
+    SkipObjects(SkipObjects),
     /// The status of the print.
     PushStatus(PushStatus),
     /// The gcode line.
@@ -229,6 +248,8 @@ impl Print {
             Print::AmsControl(ams_ctrl) => ams_ctrl.sequence_id.clone(),
             Print::AmsChangeFilament(ams_change_filament) => ams_change_filament.sequence_id.clone(),
             Print::Calibration(calibration) => calibration.sequence_id.clone(),
+            Print::CleanPrintError(clean_print_error) => clean_print_error.sequence_id.clone(),
+            Print::SkipObjects(skip_objects) => skip_objects.sequence_id.clone(),
             Print::PushStatus(push_status) => push_status.sequence_id.clone(),
             Print::GcodeLine(gcode_line) => gcode_line.sequence_id.clone(),
             Print::ProjectFile(project_file) => project_file.sequence_id.clone(),
@@ -290,6 +311,32 @@ pub struct Calibration {
     other: BTreeMap<String, Value>,
 }
 
+/// A clean print error command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CleanPrintError {
+    /// The sequence id.
+    pub sequence_id: SequenceId,
+    /// The reason for the message.
+    pub reason: Option<Reason>,
+    /// The result of the command.
+    pub result: Result,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
+/// A skip objects command.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct SkipObjects {
+    /// The sequence id.
+    pub sequence_id: SequenceId,
+    /// The reason for the message.
+    pub reason: Option<Reason>,
+    /// The result of the command, not every firmware reports one.
+    pub result: Option<Result>,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
 /// A gcode line.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct GcodeLine {
@@ -500,6 +547,8 @@ pub struct PushStatus {
     pub ams: Option<PrintAms>,
     /// The ipcam.
     pub ipcam: Option<PrintIpcam>,
+    /// The xcam.
+    pub xcam: Option<PrintXcam>,
     /// The tray.
     pub vt_tray: Option<PrintTray>,
     /// The lights report.
@@ -512,6 +561,27 @@ pub struct PushStatus {
     other: BTreeMap<String, Value>,
 }
 
+impl PushStatus {
+    /// Merge a later report into this one. Printers only report what changed since their last
+    /// report, so anything the update doesn't have keeps its last known value.
+    pub fn merge(&mut self, update: PushStatus) {
+        let (Ok(Value::Object(mut merged)), Ok(Value::Object(changed))) =
+            (serde_json::to_value(&*self), serde_json::to_value(&update))
+        else {
+            *self = update;
+            return;
+        };
+
+        for (key, value) in changed {
+            if !value.is_null() {
+                merged.insert(key, value);
+            }
+        }
+
+        *self = serde_json::from_value(Value::Object(merged)).unwrap_or(update);
+    }
+}
+
 /// The print upload.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct PrintUpload {
@@ -646,6 +716,21 @@ pub struct PrintIpcam {
     other: BTreeMap<String, Value>,
 }
 
+/// The print xcam, the camera based print monitoring.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct PrintXcam {
+    /// Whether the first layer is inspected.
+    pub first_layer_inspector: Option<bool>,
+    /// Whether spaghetti detection is enabled.
+    pub spaghetti_detector: Option<bool>,
+    /// Whether the print is halted when a problem is detected.
+    pub print_halt: Option<bool>,
+    /// The halt print sensitivity.
+    pub halt_print_sensitivity: Option<String>,
+    #[serde(flatten)]
+    other: BTreeMap<String, Value>,
+}
+
 /// A print lights report.
 #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
 pub struct PrintLightsReport {
@@ -817,6 +902,24 @@ pub enum NozzleType {
 mod tests {
     use super::*;
 
+    #[test]
+    fn test_push_status_merge() {
+        let mut status: PushStatus = serde_json::from_str(
+            r#"{"sequence_id": "1", "gcode_state": "RUNNING", "layer_num": 3, "mc_remaining_time": 42}"#,
+        )
+        .unwrap();
+
+        // Only the layer changed.
+        status.merge(serde_json::from_str(r#"{"sequence_id": "2", "layer_num": 4}"#).unwrap());
+        assert_eq!(status.gcode_state.as_deref(), Some("RUNNING"));
+        assert_eq!(status.layer_num, Some(4));
+        assert_eq!(status.mc_remaining_time, Some(42));
+
+        status.merge(serde_json::from_str(r#"{"sequence_id": "3", "gcode_state": "FINISH"}"#).unwrap());
+        assert_eq!(status.gcode_state.as_deref(), Some("FINISH"));
+        assert_eq!(status.layer_num, Some(4));
+    }
+
     #[test]
     fn test_deserialize_message_json() {
         let message = r#"{ "hello": "world" }"#;
@@ -918,4 +1021,33 @@ mod tests {
         assert!(result.is_ok());
         assert!(matches!(result.unwrap(), Message::System(_)));
     }
+
+    #[test]
+    fn test_raw_json() {
+        let message = r#"{ "print": { "bed_temper": 17.40625, "wifi_signal": "-59dBm", "command": "push_status", "msg": 1, "sequence_id": 2 }}"#;
+        let message = serde_json::from_str::<Message>(message).unwrap();
+        assert!(matches!(message, Message::Print(Print::PushStatus(_))));
+
+        let raw = message.raw_json().unwrap();
+        let print = raw["print"].as_object().unwrap();
+        assert_eq!(raw.as_object().unwrap().len(), 1);
+        assert_eq!(print["command"], "push_status");
+        assert_eq!(print["sequence_id"], 2);
+        assert_eq!(print["bed_temper"], 17.40625);
+        assert_eq!(print["wifi_signal"], "-59dBm");
+
+        let json = serde_json::json!({ "hello": "world" });
+        assert_eq!(Message::Json(json.clone()).raw_json().unwrap(), json);
+        assert_eq!(
+            Message::Unknown(Some(r#"{"hello":"world"}"#.to_string()))
+                .raw_json()
+                .unwrap(),
+            json
+        );
+        assert_eq!(
+            Message::Unknown(Some("garbage".to_string())).raw_json().unwrap(),
+            "garbage"
+        );
+        assert_eq!(Message::Unknown(None).raw_json().unwrap(), Value::Null);
+    }
 }
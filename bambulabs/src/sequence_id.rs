@@ -38,6 +38,18 @@ impl SequenceId {
         Self::String("status".to_string())
     }
 
+    /// Normalize the sequence id so numeric ids compare equal however they were encoded.
+    /// The printer can echo back an integer sequence id as a string.
+    pub fn normalize(self) -> Self {
+        match self {
+            Self::String(s) => match s.parse() {
+                Ok(i) => Self::Integer(i),
+                Err(_) => Self::String(s),
+            },
+            Self::Integer(i) => Self::Integer(i),
+        }
+    }
+
     /// Get the sequence id as a u32.
     pub fn as_u32(&self) -> Result<u32> {
         match self {
@@ -4,16 +4,33 @@ use parse_display::{Display, FromStr};
 use serde::{Deserialize, Serialize};
 
 /// Speed profiles for the Bambu printers.
+/// On the wire, a profile is its speed level as a string, the same level reported in `spd_lvl`.
 #[derive(Debug, Clone, Copy, PartialEq, Eq, Display, FromStr, Serialize, Deserialize)]
 #[display(style = "snake_case")]
-#[serde(rename_all = "snake_case")]
 pub enum SpeedProfile {
     /// Silent mode.
+    #[serde(rename = "1")]
     Silent,
     /// Standard mode.
+    #[serde(rename = "2")]
     Standard,
     /// Sport mode.
+    #[serde(rename = "3")]
     Sport,
     /// Ludicrous mode.
+    #[serde(rename = "4")]
     Ludicrous,
 }
+
+impl SpeedProfile {
+    /// Get the profile for a speed level reported by the printer.
+    pub fn from_level(level: i64) -> Option<Self> {
+        match level {
+            1 => Some(SpeedProfile::Silent),
+            2 => Some(SpeedProfile::Standard),
+            3 => Some(SpeedProfile::Sport),
+            4 => Some(SpeedProfile::Ludicrous),
+            _ => None,
+        }
+    }
+}
@@ -0,0 +1,155 @@
+//! How the client checks the printer's TLS certificate.
+
+use std::{path::PathBuf, sync::Arc};
+
+use anyhow::{Context, Result};
+use serde::{Deserialize, Serialize};
+
+/// How to check the certificate the printer presents.
+/// Printers use a self-signed certificate, so by default it isn't checked at all.
+#[derive(Debug, Clone, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case", tag = "mode", content = "cert")]
+pub enum TlsConfig {
+    /// Accept any certificate.
+    #[default]
+    InsecureSkipVerify,
+    /// Only accept a certificate in this PEM file. The file can hold the certificates of several
+    /// printers.
+    PinnedCert(PathBuf),
+    /// Verify the certificate against the system's trusted roots.
+    SystemRoots,
+}
+
+impl TlsConfig {
+    /// Build the rustls config for this mode.
+    pub(crate) fn client_config(&self) -> Result<rustls::ClientConfig> {
+        let builder = rustls::ClientConfig::builder();
+
+        let config = match self {
+            TlsConfig::InsecureSkipVerify => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(crate::no_auth::NoAuth::new())),
+            TlsConfig::PinnedCert(path) => builder
+                .dangerous()
+                .with_custom_certificate_verifier(Arc::new(PinnedCert::from_pem_file(path)?)),
+            TlsConfig::SystemRoots => {
+                let mut roots = rustls::RootCertStore::empty();
+                let (added, ignored) = roots.add_parsable_certificates(
+                    rustls_native_certs::load_native_certs().context("Failed to load the system's trusted roots")?,
+                );
+                tracing::debug!("Loaded {} system roots, ignored {}", added, ignored);
+
+                builder.with_root_certificates(roots)
+            }
+        };
+
+        Ok(config.with_no_client_auth())
+    }
+}
+
+/// Accepts only the certificates it was given, whoever signed them.
+#[derive(Debug)]
+struct PinnedCert {
+    certs: Vec<rustls::pki_types::CertificateDer<'static>>,
+    algorithms: rustls::crypto::WebPkiSupportedAlgorithms,
+}
+
+impl PinnedCert {
+    fn new(certs: Vec<rustls::pki_types::CertificateDer<'static>>) -> Self {
+        Self {
+            certs,
+            algorithms: rustls::crypto::ring::default_provider().signature_verification_algorithms,
+        }
+    }
+
+    fn from_pem_file(path: &std::path::Path) -> Result<Self> {
+        let pem = std::fs::read(path).with_context(|| format!("Failed to read {}", path.display()))?;
+        let certs = rustls_pemfile::certs(&mut pem.as_slice())
+            .collect::<Result<Vec<_>, _>>()
+            .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        if certs.is_empty() {
+            anyhow::bail!("No certificates found in {}", path.display());
+        }
+
+        Ok(Self::new(certs))
+    }
+}
+
+impl rustls::client::danger::ServerCertVerifier for PinnedCert {
+    fn verify_server_cert(
+        &self,
+        end_entity: &rustls::pki_types::CertificateDer<'_>,
+        _intermediates: &[rustls::pki_types::CertificateDer<'_>],
+        _server_name: &rustls::pki_types::ServerName<'_>,
+        _ocsp_response: &[u8],
+        _now: rustls::pki_types::UnixTime,
+    ) -> Result<rustls::client::danger::ServerCertVerified, rustls::Error> {
+        if self.certs.iter().any(|cert| cert.as_ref() == end_entity.as_ref()) {
+            Ok(rustls::client::danger::ServerCertVerified::assertion())
+        } else {
+            Err(rustls::Error::InvalidCertificate(
+                rustls::CertificateError::ApplicationVerificationFailure,
+            ))
+        }
+    }
+
+    fn verify_tls12_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls12_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn verify_tls13_signature(
+        &self,
+        message: &[u8],
+        cert: &rustls::pki_types::CertificateDer<'_>,
+        dss: &rustls::DigitallySignedStruct,
+    ) -> Result<rustls::client::danger::HandshakeSignatureValid, rustls::Error> {
+        rustls::crypto::verify_tls13_signature(message, cert, dss, &self.algorithms)
+    }
+
+    fn supported_verify_schemes(&self) -> Vec<rustls::SignatureScheme> {
+        self.algorithms.supported_schemes()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use rustls::client::danger::ServerCertVerifier;
+
+    use super::*;
+
+    #[test]
+    fn test_pinned_cert() {
+        let pinned = rustls::pki_types::CertificateDer::from(vec![1, 2, 3]);
+        let other = rustls::pki_types::CertificateDer::from(vec![4, 5, 6]);
+        let verifier = PinnedCert::new(vec![pinned.clone()]);
+        let server_name = rustls::pki_types::ServerName::try_from("192.0.2.10").unwrap();
+
+        assert!(verifier
+            .verify_server_cert(&pinned, &[], &server_name, &[], rustls::pki_types::UnixTime::now())
+            .is_ok());
+        assert!(verifier
+            .verify_server_cert(&other, &[], &server_name, &[], rustls::pki_types::UnixTime::now())
+            .is_err());
+    }
+
+    #[test]
+    fn test_pinned_cert_missing_file() {
+        let config = TlsConfig::PinnedCert("/nonexistent/printer.pem".into());
+        assert!(config.client_config().is_err());
+    }
+
+    #[test]
+    fn test_tls_config_serde() {
+        let config: TlsConfig = serde_json::from_str(r#"{"mode": "pinned_cert", "cert": "printer.pem"}"#).unwrap();
+        assert_eq!(config, TlsConfig::PinnedCert("printer.pem".into()));
+
+        let config: TlsConfig = serde_json::from_str(r#"{"mode": "system_roots"}"#).unwrap();
+        assert_eq!(config, TlsConfig::SystemRoots);
+    }
+}
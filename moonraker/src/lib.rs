@@ -12,12 +12,14 @@
 
 mod metrics;
 mod print;
+mod status;
 mod upload;
 
 use anyhow::Result;
 
 pub use metrics::{ControlledTemperatureReadings, TemperatureReadings};
 pub use print::InfoResponse;
+pub use status::{DisplayStatus, HeaterStatus, PrintStats, PrinterStatus};
 pub use upload::{DeleteResponse, DeleteResponseItem, UploadResponse, UploadResponseItem};
 
 /// PrintManager is a moonraker instance which can accept gcode for printing.
@@ -33,4 +35,9 @@ impl PrintManager {
             url_base: url_base.to_owned(),
         })
     }
+
+    /// The base URL of the moonraker API.
+    pub fn url_base(&self) -> &str {
+        &self.url_base
+    }
 }
@@ -0,0 +1,93 @@
+use anyhow::Result;
+use serde::{Deserialize, Serialize};
+
+use super::PrintManager;
+
+/// The printer objects queried for the status, and the fields we want from each.
+const STATUS_QUERY: &str =
+    "print_stats=state,filename,print_duration&extruder=temperature,target&heater_bed=temperature,target&display_status=progress";
+
+/// State of the current print job, as tracked by klipper.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PrintStats {
+    /// slug defining what the job is doing, one of `standby`, `printing`,
+    /// `paused`, `complete`, `cancelled` or `error`.
+    pub state: String,
+
+    /// Name of the file being printed, empty if there is none.
+    pub filename: String,
+
+    /// Time spent printing so far, in seconds.
+    pub print_duration: f64,
+}
+
+/// Reading of a heater controlled by klipper.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct HeaterStatus {
+    /// Current temperature.
+    pub temperature: f64,
+
+    /// Target temperature, 0 when the heater is off.
+    pub target: f64,
+}
+
+/// Progress shown on the printer's display.
+#[derive(Clone, Copy, Debug, Deserialize, Serialize)]
+pub struct DisplayStatus {
+    /// Progress of the current print, from 0.0 to 1.0.
+    pub progress: f64,
+}
+
+/// Status of the printer objects we care about.
+#[derive(Clone, Debug, Deserialize, Serialize)]
+pub struct PrinterStatus {
+    /// State of the current print job.
+    pub print_stats: PrintStats,
+
+    /// Information about the 3D printer extruder head.
+    pub extruder: HeaterStatus,
+
+    /// Information about a heated bed, if present
+    pub heater_bed: Option<HeaterStatus>,
+
+    /// Progress shown on the display, if the printer has one configured.
+    pub display_status: Option<DisplayStatus>,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct PrinterStatusResult {
+    status: PrinterStatus,
+}
+
+#[derive(Clone, Debug, Deserialize, Serialize)]
+struct PrinterStatusWrapper {
+    result: PrinterStatusResult,
+}
+
+impl PrintManager {
+    /// Get the status of the printer and the current print job.
+    pub async fn status(&self) -> Result<PrinterStatus> {
+        let client = reqwest::Client::new();
+        let resp: PrinterStatusWrapper = client
+            .get(format!("{}/printer/objects/query?{}", self.url_base, STATUS_QUERY))
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(resp.result.status)
+    }
+
+    /// Run a gcode script, such as `SET_LED LED=chamber WHITE=1`.
+    /// Returns once klipper has finished running it.
+    pub async fn run_gcode(&self, script: &str) -> Result<()> {
+        let client = reqwest::Client::new();
+        client
+            .post(format!("{}/printer/gcode/script", self.url_base))
+            .form(&[("script", script)])
+            .send()
+            .await?
+            .error_for_status()?;
+        Ok(())
+    }
+}
@@ -1,8 +1,8 @@
 //! Code for the configuration of the application.
 
-use std::path::PathBuf;
+use std::path::{Path, PathBuf};
 
-use anyhow::Result;
+use anyhow::{Context, Result};
 use serde::{Deserialize, Serialize};
 
 /// The configuration of the application.
@@ -12,6 +12,12 @@ pub struct Config {
     pub bambulabs: Option<BambuLabsConfig>,
     /// The configuration for formlabs machines.
     pub formlabs: Option<FormLabsConfig>,
+    /// The configuration for klipper machines running moonraker.
+    pub moonraker: Option<MoonrakerConfig>,
+    /// The configuration for prusa machines running PrusaLink.
+    pub prusalink: Option<PrusaLinkConfig>,
+    /// The configuration for elegoo machines speaking SDCP.
+    pub elegoo: Option<ElegooConfig>,
 }
 
 impl Config {
@@ -28,26 +34,165 @@ impl Config {
 }
 
 /// The configuration for bambu labs machines.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct BambuLabsConfig {
     /// The machine ids and access codes for communication of LAN.
     pub machines: Vec<BambuLabsMachineConfig>,
+    /// The MQTT client id to connect to the printers with.
+    /// Printers disconnect clients that share an id, so this must be unique for each instance of
+    /// the server. If not given, a random id is generated.
+    pub client_id: Option<String>,
+    /// Also discover printers over mDNS, for networks that filter the SSDP broadcasts.
+    #[serde(default)]
+    pub mdns: bool,
+    /// How long, in seconds, a printer can go without reporting before it's considered offline
+    /// and removed. Defaults to 60 seconds.
+    pub liveness_ttl_secs: Option<u64>,
+    /// The address to listen for printer broadcasts on. Defaults to `0.0.0.0:2021`, any interface
+    /// on the port Bambu printers broadcast to, along with `[::]:2021` for printers on IPv6.
+    pub bind_addr: Option<std::net::SocketAddr>,
+    /// The OrcaSlicer executable to slice with. If not given, the `ORCA_SLICER_PATH` environment
+    /// variable, the default install location and then the `PATH` are checked.
+    pub orca_slicer_path: Option<PathBuf>,
+    /// How long, in seconds, to let the slicer run before giving up on it. Defaults to 5 minutes.
+    pub slicer_timeout_secs: Option<u64>,
+    /// How many prints can be sliced at once, others wait their turn. Defaults to the number of
+    /// physical cores.
+    pub max_concurrent_slices: Option<usize>,
+    /// How long, in seconds, to wait for a printer's first report after connecting before giving
+    /// up on it. It is tried again when it's next announced. Defaults to 10 seconds.
+    pub connect_timeout_secs: Option<u64>,
+    /// How to check the certificates printers present. Printers use self-signed certificates,
+    /// so by default they aren't checked.
+    #[serde(default)]
+    pub tls: bambulabs::tls::TlsConfig,
+    /// Let raw gcode sent to the printers span several lines. Off by default, so a single command
+    /// can't carry others along with it.
+    #[serde(default)]
+    pub allow_multiline_gcode: bool,
 }
 
 impl BambuLabsConfig {
+    /// Load the config from its own file, rather than the `bambulabs` section of the main config.
+    /// Files ending in `.yaml` or `.yml` are read as YAML, which needs the `yaml` feature, anything
+    /// else as TOML.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        let contents = std::fs::read_to_string(path).with_context(|| format!("Failed to read {}", path.display()))?;
+
+        let mut config = match path.extension().and_then(|ext| ext.to_str()) {
+            Some("yaml" | "yml") => Self::from_yaml(&contents),
+            _ => toml::from_str(&contents).map_err(anyhow::Error::from),
+        }
+        .with_context(|| format!("Failed to parse {}", path.display()))?;
+
+        // Access codes can come from the environment instead, so check after they're applied.
+        config.apply_env_overrides();
+        config.validate()?;
+
+        Ok(config)
+    }
+
+    #[cfg(feature = "yaml")]
+    fn from_yaml(contents: &str) -> Result<Self> {
+        Ok(serde_yaml::from_str(contents)?)
+    }
+
+    #[cfg(not(feature = "yaml"))]
+    fn from_yaml(_contents: &str) -> Result<Self> {
+        anyhow::bail!("YAML configs need machine-api to be built with the `yaml` feature")
+    }
+
+    /// Override machine settings with environment variables, which take precedence over the
+    /// config file. This lets secrets like access codes be kept out of the file.
+    /// The access code of a machine is read from `MACHINE_API__<ID>__ACCESS_CODE`, where `<ID>` is
+    /// the machine id uppercased with anything that isn't a letter or digit replaced by `_`.
+    pub fn apply_env_overrides(&mut self) {
+        for machine in &mut self.machines {
+            let var = format!("MACHINE_API__{}__ACCESS_CODE", env_var_name(&machine.id));
+            if let Ok(access_code) = std::env::var(&var) {
+                tracing::debug!("Using the access code for {} from {}", machine.id, var);
+                machine.access_code = access_code;
+            }
+        }
+    }
+
+    /// Check every machine has what it needs to be connected to and sliced for.
+    pub fn validate(&self) -> Result<()> {
+        for machine in &self.machines {
+            if machine.access_code.trim().is_empty() {
+                anyhow::bail!("Machine {} has an empty access code", machine.id);
+            }
+
+            if !machine.slicer_config.is_dir() {
+                anyhow::bail!(
+                    "Machine {} has a slicer config directory that doesn't exist: {}",
+                    machine.id,
+                    machine.slicer_config.display()
+                );
+            }
+        }
+
+        Ok(())
+    }
+
     /// Get the access code for a machine.
     pub fn get_access_code(&self, id: &str) -> Option<String> {
         self.machines.iter().find(|m| m.id == id).map(|m| m.access_code.clone())
     }
 
+    /// Get how long a printer can go without reporting before it's considered offline.
+    pub fn liveness_ttl(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.liveness_ttl_secs.unwrap_or(60))
+    }
+
+    /// Get how long to wait for a printer's first report after connecting.
+    pub fn connect_timeout(&self) -> std::time::Duration {
+        std::time::Duration::from_secs(self.connect_timeout_secs.unwrap_or(10))
+    }
+
+    /// Get how long to let the slicer run before giving up on it.
+    pub fn slicer_timeout(&self) -> std::time::Duration {
+        self.slicer_timeout_secs
+            .map(std::time::Duration::from_secs)
+            .unwrap_or(crate::slicer::orca::DEFAULT_SLICE_TIMEOUT)
+    }
+
+    /// Get the address to listen for printer broadcasts on.
+    pub fn bind_addr(&self) -> std::net::SocketAddr {
+        self.bind_addr
+            .unwrap_or_else(|| (std::net::Ipv4Addr::UNSPECIFIED, 2021).into())
+    }
+
+    /// The address to also listen for printer broadcasts on over IPv6, unless an address to
+    /// listen on was given.
+    pub fn bind_addr_v6(&self) -> Option<std::net::SocketAddr> {
+        match self.bind_addr {
+            Some(_) => None,
+            None => Some((std::net::Ipv6Addr::UNSPECIFIED, 2021).into()),
+        }
+    }
+
     // Get the machine config for the given id.
     pub fn get_machine_config(&self, id: &str) -> Option<&BambuLabsMachineConfig> {
         self.machines.iter().find(|m| m.id == id)
     }
 }
 
+/// Turn a machine id into the form used in environment variable names.
+fn env_var_name(id: &str) -> String {
+    id.chars()
+        .map(|c| {
+            if c.is_ascii_alphanumeric() {
+                c.to_ascii_uppercase()
+            } else {
+                '_'
+            }
+        })
+        .collect()
+}
+
 /// The configuration for a single bambu labs machine.
-#[derive(Debug, Clone, Deserialize, Serialize)]
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
 pub struct BambuLabsMachineConfig {
     /// The machine id.
     pub id: String,
@@ -55,12 +200,85 @@ pub struct BambuLabsMachineConfig {
     pub access_code: String,
     /// The slicer configuration for the machine.
     pub slicer_config: PathBuf,
+    /// The IP address of the machine, for networks where it can't be discovered.
+    /// The serial number must be given too.
+    pub ip: Option<std::net::IpAddr>,
+    /// The serial number of the machine.
+    pub serial: Option<String>,
+    /// The model of the machine, e.g. `P1S`, for machines that aren't discovered and so don't
+    /// say what they are. Without it only the features every model has are used.
+    pub model: Option<String>,
+    /// Extra arguments to pass to the slicer, e.g. `["--arrange", "1"]`.
+    #[serde(default)]
+    pub slicer_args: Vec<String>,
+    /// The filament configs to slice with, one for each filament slot in order, relative to the
+    /// slicer config directory. Defaults to just `filament.json`.
+    #[serde(default)]
+    pub filament_configs: Vec<PathBuf>,
 }
 
 /// The configuration for formlabs machines.
 #[derive(Debug, Clone, Deserialize, Serialize)]
 pub struct FormLabsConfig {}
 
+/// The configuration for klipper machines running moonraker.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MoonrakerConfig {
+    /// The moonraker instances to add without discovering them.
+    #[serde(default)]
+    pub machines: Vec<MoonrakerMachineConfig>,
+    /// Also discover instances advertising themselves over mDNS. Moonraker only does this with
+    /// its `zeroconf` component enabled.
+    #[serde(default)]
+    pub mdns: bool,
+    /// The klipper LED to switch with `set_led`, as named in `printer.cfg`. Printers without one
+    /// don't support `set_led`.
+    pub led: Option<String>,
+}
+
+/// The configuration for a single moonraker instance.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct MoonrakerMachineConfig {
+    /// The machine id.
+    pub id: String,
+    /// The base URL of the moonraker API, e.g. `http://voron.local:7125`.
+    pub url: String,
+}
+
+/// The configuration for prusa machines running PrusaLink.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PrusaLinkConfig {
+    /// The PrusaLink instances to add.
+    pub machines: Vec<PrusaLinkMachineConfig>,
+}
+
+/// The configuration for a single PrusaLink instance.
+#[derive(Debug, Clone, PartialEq, Deserialize, Serialize)]
+pub struct PrusaLinkMachineConfig {
+    /// The machine id.
+    pub id: String,
+    /// The base URL of the printer, e.g. `http://192.0.2.30`.
+    pub url: String,
+    /// The API key shown in the printer's network settings.
+    pub api_key: String,
+}
+
+/// The configuration for elegoo machines speaking SDCP.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize, Serialize)]
+pub struct ElegooConfig {
+    /// The address to send the discovery broadcast to. Defaults to `255.255.255.255`, use the
+    /// broadcast address of a subnet to only search that one.
+    pub broadcast_addr: Option<std::net::IpAddr>,
+}
+
+impl ElegooConfig {
+    /// Get the address to send the discovery broadcast to.
+    pub fn broadcast_addr(&self) -> std::net::IpAddr {
+        self.broadcast_addr
+            .unwrap_or(std::net::IpAddr::V4(std::net::Ipv4Addr::BROADCAST))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -82,6 +300,16 @@ mod tests {
         assert_eq!(bl.get_access_code("1").unwrap(), "1234");
         assert_eq!(bl.get_access_code("2").unwrap(), "5678");
         assert_eq!(bl.get_access_code("3"), None);
+        assert_eq!(bl.client_id, None);
+        assert!(!bl.mdns);
+        assert_eq!(bl.liveness_ttl(), std::time::Duration::from_secs(60));
+        assert_eq!(bl.bind_addr(), "0.0.0.0:2021".parse().unwrap());
+        assert_eq!(bl.bind_addr_v6(), Some("[::]:2021".parse().unwrap()));
+        assert_eq!(bl.orca_slicer_path, None);
+        assert_eq!(bl.slicer_timeout(), std::time::Duration::from_secs(5 * 60));
+        assert_eq!(bl.connect_timeout(), std::time::Duration::from_secs(10));
+        assert_eq!(bl.tls, bambulabs::tls::TlsConfig::InsecureSkipVerify);
+        assert!(!bl.allow_multiline_gcode);
 
         assert!(config.formlabs.is_none());
     }
@@ -106,4 +334,191 @@ machines = [
         assert_eq!(bl.get_access_code("3"), None);
         assert!(config.formlabs.is_some());
     }
+
+    #[test]
+    fn test_config_from_str_with_moonraker() {
+        let config = r#"
+            [moonraker]
+            machines = [
+                { id = "voron", url = "http://192.0.2.20:7125" },
+            ]
+            led = "chamber"
+        "#;
+        let config = Config::from_str(config).unwrap();
+        assert!(config.bambulabs.is_none());
+        let moonraker = config.moonraker.unwrap();
+        assert_eq!(
+            moonraker.machines,
+            vec![MoonrakerMachineConfig {
+                id: "voron".to_string(),
+                url: "http://192.0.2.20:7125".to_string(),
+            }]
+        );
+        assert!(!moonraker.mdns);
+        assert_eq!(moonraker.led.as_deref(), Some("chamber"));
+    }
+
+    #[test]
+    fn test_config_from_str_with_prusalink() {
+        let config = r#"
+            [prusalink]
+            machines = [
+                { id = "mk4", url = "http://192.0.2.30", api_key = "abcdef123456" },
+            ]
+        "#;
+        let config = Config::from_str(config).unwrap();
+        let prusalink = config.prusalink.unwrap();
+        assert_eq!(prusalink.machines.len(), 1);
+        assert_eq!(prusalink.machines[0].id, "mk4");
+        assert_eq!(prusalink.machines[0].api_key, "abcdef123456");
+    }
+
+    #[test]
+    fn test_config_from_str_with_elegoo() {
+        let config = Config::from_str("[elegoo]").unwrap();
+        assert_eq!(
+            config.elegoo.unwrap().broadcast_addr(),
+            "255.255.255.255".parse::<std::net::IpAddr>().unwrap()
+        );
+
+        let config = Config::from_str("[elegoo]\nbroadcast_addr = \"192.0.2.255\"").unwrap();
+        assert_eq!(
+            config.elegoo.unwrap().broadcast_addr(),
+            "192.0.2.255".parse::<std::net::IpAddr>().unwrap()
+        );
+    }
+
+    fn fixture(name: &str) -> PathBuf {
+        Path::new(env!("CARGO_MANIFEST_DIR")).join("testdata").join(name)
+    }
+
+    #[test]
+    fn test_bambulabs_config_from_path() {
+        let config = BambuLabsConfig::from_path(&fixture("bambulabs.toml")).unwrap();
+        assert_eq!(config.client_id.as_deref(), Some("machine-api-workshop"));
+        assert_eq!(config.liveness_ttl(), std::time::Duration::from_secs(30));
+        assert_eq!(config.machines.len(), 2);
+        assert_eq!(config.get_access_code("office-x1c").unwrap(), "87654321");
+        assert_eq!(
+            config.get_machine_config("office-x1c").unwrap().ip,
+            Some("192.0.2.20".parse().unwrap())
+        );
+        assert_eq!(
+            config.get_machine_config("office-x1c").unwrap().model.as_deref(),
+            Some("X1 Carbon")
+        );
+        assert_eq!(config.get_machine_config("workshop-x1c").unwrap().model, None);
+
+        // Writing it back out and reading it again gives the same config.
+        let dir = tempdir::TempDir::new("config").unwrap();
+        let path = dir.path().join("bambulabs.toml");
+        std::fs::write(&path, toml::to_string(&config).unwrap()).unwrap();
+        assert_eq!(BambuLabsConfig::from_path(&path).unwrap(), config);
+    }
+
+    #[cfg(feature = "yaml")]
+    #[test]
+    fn test_bambulabs_config_from_path_yaml() {
+        assert_eq!(
+            BambuLabsConfig::from_path(&fixture("bambulabs.yaml")).unwrap(),
+            BambuLabsConfig::from_path(&fixture("bambulabs.toml")).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_bambulabs_config_env_overrides() {
+        assert_eq!(env_var_name("garage-p1s.2"), "GARAGE_P1S_2");
+
+        let mut config: BambuLabsConfig = toml::from_str(
+            r#"
+            [[machines]]
+            id = "env-override-p1s"
+            access_code = ""
+            slicer_config = "config/bambu"
+
+            [[machines]]
+            id = "garage-a1"
+            access_code = "11111111"
+            slicer_config = "config/bambu"
+            "#,
+        )
+        .unwrap();
+
+        // The machine id is only used here, so no other test picks the variable up, and it's
+        // removed before anything can fail.
+        std::env::set_var("MACHINE_API__ENV_OVERRIDE_P1S__ACCESS_CODE", "24681357");
+        config.apply_env_overrides();
+        std::env::remove_var("MACHINE_API__ENV_OVERRIDE_P1S__ACCESS_CODE");
+
+        assert_eq!(config.get_access_code("env-override-p1s").unwrap(), "24681357");
+        assert_eq!(config.get_access_code("garage-a1").unwrap(), "11111111");
+        config.validate().unwrap();
+    }
+
+    #[test]
+    fn test_bambulabs_config_validate() {
+        let config: BambuLabsConfig = toml::from_str(
+            r#"
+            [[machines]]
+            id = "workshop-x1c"
+            access_code = " "
+            slicer_config = "config/bambu"
+            "#,
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(
+            err.to_string().contains("workshop-x1c has an empty access code"),
+            "{}",
+            err
+        );
+
+        let config: BambuLabsConfig = toml::from_str(
+            r#"
+            [[machines]]
+            id = "office-x1c"
+            access_code = "87654321"
+            slicer_config = "config/missing"
+            "#,
+        )
+        .unwrap();
+        let err = config.validate().unwrap_err();
+        assert!(err.to_string().contains("office-x1c"), "{}", err);
+        assert!(err.to_string().contains("config/missing"), "{}", err);
+    }
+
+    #[test]
+    fn test_config_from_str_tls() {
+        let config = r#"
+            [bambulabs]
+            machines = []
+            tls = { mode = "pinned_cert", cert = "/etc/machine-api/printers.pem" }
+        "#;
+        let config = Config::from_str(config).unwrap();
+        assert_eq!(
+            config.bambulabs.unwrap().tls,
+            bambulabs::tls::TlsConfig::PinnedCert("/etc/machine-api/printers.pem".into())
+        );
+    }
+
+    #[test]
+    fn test_config_from_str_bind_addr() {
+        let config = r#"
+            [bambulabs]
+            machines = []
+            bind_addr = "192.168.1.10:12021"
+        "#;
+        let config = Config::from_str(config).unwrap();
+        assert_eq!(
+            config.bambulabs.unwrap().bind_addr(),
+            "192.168.1.10:12021".parse().unwrap()
+        );
+
+        let config = r#"
+            [bambulabs]
+            machines = []
+            bind_addr = "not an address"
+        "#;
+        assert!(Config::from_str(config).is_err());
+    }
 }
@@ -4,6 +4,8 @@
 
 mod config;
 mod machine;
+#[cfg(feature = "metrics")]
+mod metrics;
 mod network_printer;
 mod print_manager;
 mod server;
@@ -18,6 +20,7 @@ use anyhow::{bail, Result};
 use clap::Parser;
 use config::Config;
 use machine::MachineHandle;
+use network_printer::NetworkPrinters;
 use opentelemetry::{trace::TracerProvider, KeyValue};
 use opentelemetry_otlp::WithExportConfig;
 use opentelemetry_sdk::Resource;
@@ -47,9 +50,29 @@ pub struct Opts {
     /// Path to config file.
     #[clap(short, long, default_value = "machine-api.toml")]
     pub config: std::path::PathBuf,
+
+    /// Path to a file with the Bambu Lab config, used instead of the `bambulabs` section of the
+    /// config file. TOML, or YAML if it ends in `.yaml` or `.yml`.
+    #[clap(long)]
+    pub bambu_config: Option<std::path::PathBuf>,
 }
 
 impl Opts {
+    /// Load the config file, and the Bambu Lab config file if there is one.
+    pub async fn load_config(&self) -> Result<Config> {
+        let mut config = Config::from_file(&self.config).await?;
+
+        if let Some(path) = &self.bambu_config {
+            config.bambulabs = Some(config::BambuLabsConfig::from_path(path)?);
+        }
+
+        if let Some(bambulabs) = &mut config.bambulabs {
+            bambulabs.apply_env_overrides();
+        }
+
+        Ok(config)
+    }
+
     /// Setup our logger.
     pub fn create_logger(&self, app: &str) -> slog::Logger {
         if self.json {
@@ -86,7 +109,19 @@ pub enum SubCommand {
     Server(Server),
 
     /// List all available machines on the network or over USB.
-    ListMachines,
+    ListMachines {
+        /// Only list network printers made by this manufacturer.
+        #[clap(long)]
+        manufacturer: Option<crate::network_printer::NetworkPrinterManufacturer>,
+
+        /// Only list network printers whose model contains this.
+        #[clap(long)]
+        model: Option<String>,
+
+        /// Only list network printers that are connected.
+        #[clap(long)]
+        online: bool,
+    },
 
     /// Print the given `file` with config from `config_file`
     PrintFile {
@@ -102,6 +137,37 @@ pub enum SubCommand {
         job_name: Option<String>,
     },
 
+    /// Extract the slicer config from an OrcaSlicer project into a directory usable as a
+    /// machine's `slicer_config`.
+    ExtractSlicerConfig {
+        /// The OrcaSlicer project 3mf.
+        project: std::path::PathBuf,
+
+        /// The directory to write the config to.
+        out_dir: std::path::PathBuf,
+    },
+
+    /// Slice a file to a .3mf or plain .gcode file.
+    SliceFile {
+        /// The directory with the OrcaSlicer config to slice with.
+        slicer_config: std::path::PathBuf,
+
+        /// File path to slice
+        file: std::path::PathBuf,
+
+        /// Where to write the sliced file, its extension picks the format.
+        output: std::path::PathBuf,
+    },
+
+    /// Slice a file and print the estimated print time and filament use.
+    EstimateFile {
+        /// The directory with the OrcaSlicer config to slice with.
+        slicer_config: std::path::PathBuf,
+
+        /// File path to slice
+        file: std::path::PathBuf,
+    },
+
     /// Get machine status.
     GetStatus {
         /// Id for a machine
@@ -120,6 +186,12 @@ pub enum SubCommand {
         machine_id: String,
     },
 
+    /// Get the URL of the machine's camera stream.
+    GetCameraUrl {
+        /// Id for a machine
+        machine_id: String,
+    },
+
     /// Turn off/on the machine led.
     LedControl {
         /// Id for a machine
@@ -149,6 +221,11 @@ pub struct Server {
     /// IP address and port that the server should listen
     #[clap(short, long, default_value = "0.0.0.0:8080")]
     pub address: String,
+
+    /// A file to remember the printers found in, so they're reconnected to straight away on the
+    /// next start rather than once they're discovered again.
+    #[clap(long)]
+    pub printer_cache: Option<std::path::PathBuf>,
 }
 
 #[tokio::main]
@@ -221,7 +298,7 @@ async fn main() -> Result<()> {
         delouse::init()?;
     }
 
-    let config = config::Config::from_file(&opts.config).await?;
+    let config = opts.load_config().await?;
 
     if let Err(err) = run_cmd(&opts, &config).await {
         bail!("running cmd `{:?}` failed: {:?}", &opts.subcmd, err);
@@ -235,16 +312,33 @@ async fn run_cmd(opts: &Opts, config: &Config) -> Result<()> {
         SubCommand::Server(s) => {
             crate::server::server(s, opts, config).await?;
         }
-        SubCommand::ListMachines => {
+        SubCommand::ListMachines {
+            manufacturer,
+            model,
+            online,
+        } => {
             // Now connect to first printer we find over serial port
             //
             let api_context = Arc::new(Context::new(config, Default::default(), opts.create_logger("print")).await?);
 
             discovery(api_context.clone(), TIMEOUT_DURATION).await?;
 
-            let machines = api_context.list_machines()?;
-            for (id, machine) in machines.iter() {
-                println!("{}: {:#?}\n", id, machine);
+            let filter = crate::network_printer::PrinterFilter {
+                manufacturer: manufacturer.clone(),
+                model_contains: model.clone(),
+                online_only: *online,
+            };
+            if filter == Default::default() {
+                let machines = api_context.list_machines()?;
+                for (id, machine) in machines.iter() {
+                    println!("{}: {:#?}\n", id, machine);
+                }
+            } else {
+                // USB printers don't have these details, so only network printers can match.
+                for info in api_context.network_printers.list_filtered(&filter)? {
+                    let id = info.hostname.clone().unwrap_or_else(|| info.ip.to_string());
+                    println!("{}: {:#?}\n", id, info);
+                }
             }
         }
         SubCommand::PrintFile {
@@ -272,6 +366,42 @@ async fn run_cmd(opts: &Opts, config: &Config) -> Result<()> {
             let result = machine.slice_and_print(job_name, file).await?;
             println!("{:#?}", result);
         }
+        SubCommand::ExtractSlicerConfig { project, out_dir } => {
+            crate::slicer::orca::extract_slicer_config(project, out_dir)?;
+
+            println!("Wrote slicer config to {}", out_dir.display());
+        }
+        SubCommand::SliceFile {
+            slicer_config,
+            file,
+            output,
+        } => {
+            let orca_slicer_path = config
+                .bambulabs
+                .as_ref()
+                .and_then(|bambulabs| bambulabs.orca_slicer_path.clone());
+            let slicer = crate::slicer::orca::OrcaSlicer::new(slicer_config.clone(), orca_slicer_path)
+                .with_output_format(crate::slicer::OutputFormat::from_path(output)?);
+
+            crate::slicer::Slicer::slice_to(&slicer, file, output).await?;
+
+            println!("Sliced {} to {}", file.display(), output.display());
+        }
+        SubCommand::EstimateFile { slicer_config, file } => {
+            let orca_slicer_path = config
+                .bambulabs
+                .as_ref()
+                .and_then(|bambulabs| bambulabs.orca_slicer_path.clone());
+            let slicer = crate::slicer::orca::OrcaSlicer::new(slicer_config.clone(), orca_slicer_path);
+
+            let sliced = crate::slicer::Slicer::slice_detailed(&slicer, file).await?;
+
+            println!(
+                "estimated time: {:?}",
+                sliced.estimated_secs.map(std::time::Duration::from_secs)
+            );
+            println!("filament: {:?} g, {:?} mm", sliced.filament_grams, sliced.filament_mm);
+        }
         SubCommand::GetStatus { machine_id } => {
             // Now connect to first printer we find over serial port
             //
@@ -310,6 +440,24 @@ async fn run_cmd(opts: &Opts, config: &Config) -> Result<()> {
 
             println!("{:#?}", status);
         }
+        SubCommand::GetCameraUrl { machine_id } => {
+            let api_context = Arc::new(Context::new(config, Default::default(), opts.create_logger("print")).await?);
+
+            discovery(api_context.clone(), TIMEOUT_DURATION).await?;
+
+            let machine = api_context
+                .find_machine_handle_by_id(machine_id)?
+                .expect("Printer not found by given ID");
+
+            let MachineHandle::NetworkPrinter(machine) = machine else {
+                bail!("usb printers not yet supported");
+            };
+
+            match machine.client.camera_url()? {
+                Some(url) => println!("{}", url),
+                None => println!("This machine has no camera"),
+            }
+        }
         SubCommand::LedControl { machine_id, on } => {
             // Now connect to first printer we find over serial port
             //
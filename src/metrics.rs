@@ -0,0 +1,208 @@
+//! Prometheus metrics for network printer telemetry.
+
+use std::sync::{Arc, Mutex};
+
+use anyhow::Result;
+use prometheus::{Encoder, GaugeVec, IntGauge, Opts, Registry, TextEncoder};
+
+use crate::network_printer::{manager::PrinterManager, NetworkPrinters};
+
+/// The labels every per-printer metric is keyed by.
+const PRINTER_LABELS: [&str; 2] = ["serial", "model"];
+
+/// Gauges for the printers we know about, refreshed by polling each printer.
+pub struct PrinterMetrics {
+    registry: Registry,
+    nozzle_temp: GaugeVec,
+    bed_temp: GaugeVec,
+    progress: GaugeVec,
+    online: IntGauge,
+    offline: IntGauge,
+    /// The task polling the printers, once spawned.
+    poller: Mutex<Option<tokio::task::JoinHandle<()>>>,
+}
+
+/// The values read from a single printer during a poll.
+struct PrinterSample {
+    serial: String,
+    model: String,
+    temperatures: Option<(f64, f64)>,
+    progress: Option<u8>,
+}
+
+impl PrinterMetrics {
+    /// Create the gauges and register them with a fresh registry.
+    pub fn new() -> Result<Self> {
+        let registry = Registry::new();
+
+        let nozzle_temp = GaugeVec::new(
+            Opts::new("machine_nozzle_temp_celsius", "The nozzle temperature in Celsius."),
+            &PRINTER_LABELS,
+        )?;
+        let bed_temp = GaugeVec::new(
+            Opts::new("machine_bed_temp_celsius", "The bed temperature in Celsius."),
+            &PRINTER_LABELS,
+        )?;
+        let progress = GaugeVec::new(
+            Opts::new(
+                "machine_print_progress_percent",
+                "How much of the current print is done.",
+            ),
+            &PRINTER_LABELS,
+        )?;
+        let online = IntGauge::new("machine_printers_online", "The number of printers that are online.")?;
+        let offline = IntGauge::new("machine_printers_offline", "The number of printers that are offline.")?;
+
+        registry.register(Box::new(nozzle_temp.clone()))?;
+        registry.register(Box::new(bed_temp.clone()))?;
+        registry.register(Box::new(progress.clone()))?;
+        registry.register(Box::new(online.clone()))?;
+        registry.register(Box::new(offline.clone()))?;
+
+        Ok(Self {
+            registry,
+            nozzle_temp,
+            bed_temp,
+            progress,
+            online,
+            offline,
+            poller: Mutex::new(None),
+        })
+    }
+
+    /// Poll every printer and refresh the gauges.
+    /// Printers that have gone away are dropped from the per-printer gauges.
+    pub async fn update(&self, printers: &dyn NetworkPrinters) -> Result<()> {
+        let mut samples = Vec::new();
+        let mut offline = 0;
+        for handle in printers.list_handles()? {
+            if !handle.client.is_ready() {
+                offline += 1;
+                continue;
+            }
+
+            let temperatures = match handle.client.get_temperatures().await {
+                Ok(temps) => Some((temps.nozzle, temps.bed)),
+                Err(e) => {
+                    tracing::debug!("failed to read temperatures for metrics: {:?}", e);
+                    None
+                }
+            };
+            let progress = match handle.client.progress().await {
+                Ok(progress) => Some(progress.percent),
+                Err(e) => {
+                    tracing::debug!("failed to read progress for metrics: {:?}", e);
+                    None
+                }
+            };
+
+            samples.push(PrinterSample {
+                serial: handle.info.serial.clone().unwrap_or_else(|| handle.info.ip.to_string()),
+                model: handle.info.model.clone().unwrap_or_default(),
+                temperatures,
+                progress,
+            });
+        }
+
+        // Swap the values in only once every printer has been polled, so a scrape never sees a
+        // half-updated set.
+        self.nozzle_temp.reset();
+        self.bed_temp.reset();
+        self.progress.reset();
+        for sample in &samples {
+            let labels = [sample.serial.as_str(), sample.model.as_str()];
+            if let Some((nozzle, bed)) = sample.temperatures {
+                self.nozzle_temp.with_label_values(&labels).set(nozzle);
+                self.bed_temp.with_label_values(&labels).set(bed);
+            }
+            if let Some(percent) = sample.progress {
+                self.progress.with_label_values(&labels).set(percent as f64);
+            }
+        }
+        self.online.set(samples.len() as i64);
+        self.offline.set(offline);
+
+        Ok(())
+    }
+
+    /// Poll the printers every `interval` in the background, until `shutdown` is called or the
+    /// metrics are dropped.
+    pub fn spawn(self: Arc<Self>, printers: Arc<PrinterManager>, interval: std::time::Duration) {
+        // Holding on to the metrics would keep them from ever being dropped.
+        let metrics = Arc::downgrade(&self);
+        let poller = tokio::spawn(async move {
+            let mut ticker = tokio::time::interval(interval);
+            loop {
+                ticker.tick().await;
+                let Some(metrics) = metrics.upgrade() else {
+                    return;
+                };
+                if let Err(e) = metrics.update(printers.as_ref()).await {
+                    tracing::warn!("failed to update printer metrics: {:?}", e);
+                }
+            }
+        });
+
+        if let Some(previous) = self.poller.lock().unwrap_or_else(|e| e.into_inner()).replace(poller) {
+            previous.abort();
+        }
+    }
+
+    /// Stop polling the printers.
+    pub fn shutdown(&self) {
+        if let Some(poller) = self.poller.lock().unwrap_or_else(|e| e.into_inner()).take() {
+            poller.abort();
+        }
+    }
+
+    /// Encode the current metrics in the Prometheus text exposition format.
+    pub fn encode(&self) -> Result<String> {
+        let mut buffer = Vec::new();
+        TextEncoder::new().encode(&self.registry.gather(), &mut buffer)?;
+        Ok(String::from_utf8(buffer)?)
+    }
+}
+
+impl Drop for PrinterMetrics {
+    fn drop(&mut self) {
+        self.shutdown();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_printer_metrics_encode() {
+        let metrics = PrinterMetrics::new().unwrap();
+        metrics.update(&PrinterManager::new(vec![])).await.unwrap();
+
+        let text = metrics.encode().unwrap();
+        assert!(text.contains("machine_printers_online 0"));
+        assert!(text.contains("machine_printers_offline 0"));
+    }
+
+    #[tokio::test]
+    async fn test_printer_metrics_shutdown() {
+        let printers = Arc::new(PrinterManager::new(vec![]));
+        let poller = |metrics: &PrinterMetrics| metrics.poller.lock().unwrap().as_ref().unwrap().abort_handle();
+
+        let metrics = Arc::new(PrinterMetrics::new().unwrap());
+        metrics
+            .clone()
+            .spawn(printers.clone(), std::time::Duration::from_secs(60));
+        let running = poller(&metrics);
+        metrics.shutdown();
+        tokio::task::yield_now().await;
+        assert!(running.is_finished());
+
+        // Dropping the metrics stops it too.
+        let metrics = Arc::new(PrinterMetrics::new().unwrap());
+        metrics.clone().spawn(printers, std::time::Duration::from_secs(60));
+        let running = poller(&metrics);
+        drop(metrics);
+        tokio::task::yield_now().await;
+        assert!(running.is_finished());
+    }
+}
@@ -0,0 +1,565 @@
+//! Discovery shared by the Bambu backends for the [`crate::network_printer::NetworkPrinter`] trait.
+
+use std::net::{IpAddr, SocketAddr};
+
+/// A Bambu printer announced over SSDP.
+/// Every field is optional since nothing stops a device sending us a partial announcement, it's up
+/// to the backend to decide what it needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiscoveredBambu {
+    /// The URN of the device type (`NT`).
+    pub urn: Option<String>,
+    /// The model code of the printer (`DevModel.bambu.com`), e.g. `3DPrinter-X1-Carbon`.
+    pub model_code: Option<String>,
+    /// The name the printer was given (`DevName.bambu.com`).
+    pub name: Option<String>,
+    /// The IP address of the printer (`Location`).
+    pub ip: Option<IpAddr>,
+    /// The serial number of the printer (`USN`).
+    pub serial: Option<String>,
+    /// The MQTT port of the printer, if it announced one.
+    pub port: Option<u16>,
+}
+
+/// A model of Bambu printer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum BambuModel {
+    /// The X1 Carbon.
+    X1Carbon,
+    /// The X1.
+    X1,
+    /// The X1E.
+    X1E,
+    /// The P1P.
+    P1P,
+    /// The P1S.
+    P1S,
+    /// The A1.
+    A1,
+    /// The A1 Mini.
+    A1Mini,
+    /// The H2D.
+    H2D,
+    /// A model we don't know yet, with the code it announced.
+    Unknown(String),
+}
+
+impl Default for BambuModel {
+    /// Printers that don't say what they are could be any model, so only the features every
+    /// model has are used.
+    fn default() -> Self {
+        BambuModel::Unknown("unknown".to_string())
+    }
+}
+
+/// The models we know, for looking them up by name.
+const KNOWN_MODELS: [BambuModel; 8] = [
+    BambuModel::X1Carbon,
+    BambuModel::X1,
+    BambuModel::X1E,
+    BambuModel::P1P,
+    BambuModel::P1S,
+    BambuModel::A1,
+    BambuModel::A1Mini,
+    BambuModel::H2D,
+];
+
+impl BambuModel {
+    /// Get the model from the code printers announce over SSDP (`DevModel.bambu.com`).
+    /// Models we don't know keep their code, so it can still be shown.
+    pub fn from_code(code: &str) -> Self {
+        match code {
+            "3DPrinter-X1-Carbon" | "BL-P001" => BambuModel::X1Carbon,
+            "3DPrinter-X1" | "BL-P002" => BambuModel::X1,
+            "C13" => BambuModel::X1E,
+            "C11" => BambuModel::P1P,
+            "C12" => BambuModel::P1S,
+            "N2S" => BambuModel::A1,
+            "N1" => BambuModel::A1Mini,
+            "O1D" => BambuModel::H2D,
+            _ => BambuModel::Unknown(code.to_string()),
+        }
+    }
+
+    /// The code printers of the model announce. Older X1 firmware announces a longer form,
+    /// e.g. `3DPrinter-X1-Carbon`.
+    pub fn code(&self) -> &str {
+        match self {
+            BambuModel::X1Carbon => "BL-P001",
+            BambuModel::X1 => "BL-P002",
+            BambuModel::X1E => "C13",
+            BambuModel::P1P => "C11",
+            BambuModel::P1S => "C12",
+            BambuModel::A1 => "N2S",
+            BambuModel::A1Mini => "N1",
+            BambuModel::H2D => "O1D",
+            BambuModel::Unknown(code) => code,
+        }
+    }
+
+    /// Get the model from its name, as shown by `Display`.
+    pub fn from_name(name: &str) -> Option<Self> {
+        if let Some(model) = KNOWN_MODELS.into_iter().find(|model| model.to_string() == name) {
+            return Some(model);
+        }
+
+        name.strip_prefix("Bambu Lab model ")
+            .map(|code| BambuModel::Unknown(code.to_string()))
+    }
+
+    /// Get the model from a config file, where it can be given by name, with or without the
+    /// `Bambu Lab` prefix, or by code, e.g. `X1 Carbon` or `C12`.
+    pub fn from_config(model: &str) -> Self {
+        Self::from_name(model)
+            .or_else(|| Self::from_name(&format!("Bambu Lab {}", model)))
+            .unwrap_or_else(|| Self::from_code(model))
+    }
+
+    /// Check if the model is one of the X1 series.
+    fn is_x1(&self) -> bool {
+        matches!(self, BambuModel::X1Carbon | BambuModel::X1 | BambuModel::X1E)
+    }
+
+    /// Check if the model has a chamber temperature sensor.
+    pub fn supports_chamber_temp(&self) -> bool {
+        self.is_x1() || *self == BambuModel::H2D
+    }
+
+    /// Check if the model usually ships with an AMS, for when the printer hasn't reported whether
+    /// one is connected.
+    pub fn default_has_ams(&self) -> bool {
+        self.is_x1()
+    }
+
+    /// Check if the model has a camera streaming over RTSP. The other models' cameras use a
+    /// protocol of their own.
+    pub fn has_camera(&self) -> bool {
+        self.is_x1() || *self == BambuModel::H2D
+    }
+
+    /// Check if the model has a micro lidar, used to calibrate the flow rate.
+    pub fn has_lidar(&self) -> bool {
+        self.is_x1()
+    }
+
+    /// Check if the model has a sensor on the enclosure door.
+    pub fn has_door_sensor(&self) -> bool {
+        self.is_x1() || *self == BambuModel::H2D
+    }
+
+    /// Check if the model has a chamber light.
+    pub fn has_chamber_light(&self) -> bool {
+        !matches!(self, BambuModel::A1 | BambuModel::A1Mini | BambuModel::Unknown(_))
+    }
+
+    /// The hottest the nozzle can safely be set to, in celsius.
+    pub fn max_nozzle_temp(&self) -> u16 {
+        match self {
+            BambuModel::H2D => 350,
+            BambuModel::X1E => 320,
+            _ => 300,
+        }
+    }
+}
+
+impl std::fmt::Display for BambuModel {
+    /// The name of the model, e.g. `Bambu Lab X1 Carbon`.
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            BambuModel::X1Carbon => write!(f, "Bambu Lab X1 Carbon"),
+            BambuModel::X1 => write!(f, "Bambu Lab X1"),
+            BambuModel::X1E => write!(f, "Bambu Lab X1E"),
+            BambuModel::P1P => write!(f, "Bambu Lab P1P"),
+            BambuModel::P1S => write!(f, "Bambu Lab P1S"),
+            BambuModel::A1 => write!(f, "Bambu Lab A1"),
+            BambuModel::A1Mini => write!(f, "Bambu Lab A1 Mini"),
+            BambuModel::H2D => write!(f, "Bambu Lab H2D"),
+            BambuModel::Unknown(code) => write!(f, "Bambu Lab model {}", code),
+        }
+    }
+}
+
+/// Bind a UDP socket to listen for SSDP broadcasts on.
+/// The address is reusable, so several listeners can share the discovery port, and a restarted
+/// server doesn't have to wait for the old socket to be released.
+pub fn bind_reusable(addr: SocketAddr) -> std::io::Result<tokio::net::UdpSocket> {
+    let socket = socket2::Socket::new(
+        socket2::Domain::for_address(addr),
+        socket2::Type::DGRAM,
+        Some(socket2::Protocol::UDP),
+    )?;
+
+    // Otherwise an IPv6 listener would also take the IPv4 broadcasts, which have their own.
+    if addr.is_ipv6() {
+        socket.set_only_v6(true)?;
+    }
+    socket.set_reuse_address(true)?;
+    #[cfg(all(unix, not(any(target_os = "solaris", target_os = "illumos"))))]
+    socket.set_reuse_port(true)?;
+    socket.set_broadcast(true)?;
+    socket.set_nonblocking(true)?;
+    socket.bind(&addr.into())?;
+
+    tokio::net::UdpSocket::from_std(socket.into())
+}
+
+/// The first line of the SSDP NOTIFY frames Bambu printers broadcast.
+const NOTIFY_HEADER: &str = "NOTIFY * HTTP/1.1";
+
+/// How every SSDP datagram starts: a NOTIFY, a search, or the response to one.
+const SSDP_PREFIXES: [&[u8]; 3] = [b"NOTIFY", b"M-SEARCH", b"HTTP"];
+
+/// Parse every SSDP NOTIFY frame in a received datagram.
+/// Other traffic on the port, e.g. the binary packets macOS Bonjour sends, is dropped before
+/// it's converted to text, so it doesn't cost a parse or fill the logs.
+pub fn parse_ssdp_datagram(datagram: &[u8]) -> Vec<DiscoveredBambu> {
+    if !SSDP_PREFIXES.iter().any(|prefix| datagram.starts_with(prefix)) {
+        tracing::trace!("Not SSDP, ignoring packet");
+
+        return vec![];
+    }
+
+    // The frames we're looking for from Bambu printers are pure ASCII, so we don't mind if we
+    // end up with garbage in the resulting string.
+    parse_ssdp_notifies(&String::from_utf8_lossy(datagram))
+}
+
+/// Parse every SSDP NOTIFY frame in a datagram.
+/// Printers send one frame a datagram, but routers that re-broadcast SSDP can join several into
+/// one.
+pub fn parse_ssdp_notifies(payload: &str) -> Vec<DiscoveredBambu> {
+    // A frame starts at each header that begins a line.
+    let mut starts: Vec<usize> = payload
+        .match_indices(NOTIFY_HEADER)
+        .map(|(start, _)| start)
+        .filter(|&start| start == 0 || payload[..start].ends_with('\n'))
+        .collect();
+    // Anything before the first header isn't a NOTIFY, but is still parsed so it's logged.
+    if starts.first() != Some(&0) {
+        starts.insert(0, 0);
+    }
+    starts.push(payload.len());
+
+    starts
+        .windows(2)
+        .filter_map(|frame| parse_ssdp_notify(&payload[frame[0]..frame[1]]))
+        .collect()
+}
+
+/// Parse the SSDP NOTIFY frame Bambu printers broadcast.
+/// Returns `None` if the payload isn't a NOTIFY.
+pub fn parse_ssdp_notify(payload: &str) -> Option<DiscoveredBambu> {
+    // Iterate through all non-blank lines in the payload
+    let mut lines = payload.lines().filter_map(|l| {
+        let l = l.trim();
+
+        if l.is_empty() {
+            None
+        } else {
+            Some(l)
+        }
+    });
+
+    // First line is a different format to the rest. We also need to check this for the message
+    // type the Bambu printer emits, which is "NOTIFY * HTTP/1.1"
+    let Some(header) = lines.next() else {
+        tracing::debug!("Bad UPnP");
+
+        return None;
+    };
+
+    // We don't need to parse this properly :)))))
+    if header != NOTIFY_HEADER {
+        tracing::trace!("Not a notify, ignoring header {:?}", header);
+
+        return None;
+    }
+
+    let mut discovered = DiscoveredBambu::default();
+
+    for line in lines {
+        let Some((token, rest)) = line.split_once(':') else {
+            tracing::debug!("Bad token line {}", line);
+
+            continue;
+        };
+
+        let token = token.trim();
+        let rest = rest.trim();
+
+        tracing::trace!("----> Token {}: {}", token, rest);
+
+        match token {
+            "Location" => match parse_location(rest) {
+                Some(ip) => {
+                    discovered.ip = Some(ip);
+                    discovered.port = parse_mqtt_port(rest);
+                }
+                None => {
+                    tracing::warn!("Bad Location {:?} in NOTIFY, ignoring packet", rest);
+
+                    return None;
+                }
+            },
+            "DevModel.bambu.com" => discovered.model_code = Some(rest.to_owned()),
+            "DevName.bambu.com" => discovered.name = Some(rest.to_owned()),
+            "USN" => discovered.serial = Some(rest.to_owned()),
+            "NT" => discovered.urn = Some(rest.to_owned()),
+            // Ignore everything else
+            _ => (),
+        }
+    }
+
+    Some(discovered)
+}
+
+/// Get the IP address out of a `Location` header.
+/// Bambu printers send a bare IP address, but UPnP says it should be a URL, which other devices on
+/// the network will send.
+pub fn parse_location(location: &str) -> Option<IpAddr> {
+    if let Ok(ip) = location.parse() {
+        return Some(ip);
+    }
+
+    let uri: http::Uri = location.parse().ok()?;
+
+    // IPv6 hosts are wrapped in brackets.
+    uri.host()?.trim_start_matches('[').trim_end_matches(']').parse().ok()
+}
+
+/// Get the MQTT port out of a `Location` header.
+/// Printers that don't use the default port give an MQTT URL as their location, e.g.
+/// `mqtts://192.168.1.5:8884`. Other URLs are for other services, so their ports are ignored.
+pub fn parse_mqtt_port(location: &str) -> Option<u16> {
+    let uri: http::Uri = location.parse().ok()?;
+
+    match uri.scheme_str() {
+        Some("mqtt") | Some("mqtts") => uri.port_u16(),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use anyhow::Result;
+
+    use super::*;
+
+    /// A NOTIFY captured from an X1 Carbon.
+    const X1_CARBON_NOTIFY: &str = "NOTIFY * HTTP/1.1\r\n\
+        HOST: 239.255.255.250:1990\r\n\
+        Server: UPnP/1.0\r\n\
+        Location: 192.168.1.42\r\n\
+        NT: urn:bambulab-com:device:3dprinter:1\r\n\
+        NTS: ssdp:alive\r\n\
+        USN: 00M09A350100123\r\n\
+        Cache-Control: max-age=1800\r\n\
+        DevModel.bambu.com: 3DPrinter-X1-Carbon\r\n\
+        DevName.bambu.com: workshop-x1c\r\n\
+        DevConnect.bambu.com: lan\r\n\
+        DevBind.bambu.com: free\r\n\
+        Devseclink.bambu.com: secure\r\n\
+        \r\n";
+
+    #[test]
+    fn test_parse_ssdp_notify() {
+        let discovered = parse_ssdp_notify(X1_CARBON_NOTIFY).unwrap();
+
+        assert_eq!(
+            discovered,
+            DiscoveredBambu {
+                urn: Some("urn:bambulab-com:device:3dprinter:1".to_string()),
+                model_code: Some("3DPrinter-X1-Carbon".to_string()),
+                name: Some("workshop-x1c".to_string()),
+                ip: Some("192.168.1.42".parse().unwrap()),
+                serial: Some("00M09A350100123".to_string()),
+                port: None,
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_ssdp_notifies() {
+        let other = X1_CARBON_NOTIFY
+            .replace("192.168.1.42", "192.168.1.43")
+            .replace("00M09A350100123", "00M09A350100456");
+        let discovered = parse_ssdp_notifies(&format!("{}{}", X1_CARBON_NOTIFY, other));
+
+        assert_eq!(discovered.len(), 2);
+        assert_eq!(discovered[0], parse_ssdp_notify(X1_CARBON_NOTIFY).unwrap());
+        assert_eq!(discovered[1].serial.as_deref(), Some("00M09A350100456"));
+        assert_eq!(discovered[1].ip, Some("192.168.1.43".parse().unwrap()));
+
+        // Without the blank line between them.
+        let joined = format!(
+            "{}{}",
+            X1_CARBON_NOTIFY.trim_end(),
+            other.replacen("NOTIFY", "\r\nNOTIFY", 1)
+        );
+        assert_eq!(parse_ssdp_notifies(&joined).len(), 2);
+
+        assert_eq!(parse_ssdp_notifies(X1_CARBON_NOTIFY).len(), 1);
+        assert!(parse_ssdp_notifies("M-SEARCH * HTTP/1.1\r\n\r\n").is_empty());
+    }
+
+    /// Counts the warnings logged while it's the subscriber.
+    struct WarningCounter(std::sync::Arc<std::sync::atomic::AtomicUsize>);
+
+    impl<S: tracing::Subscriber> tracing_subscriber::Layer<S> for WarningCounter {
+        fn on_event(&self, event: &tracing::Event<'_>, _: tracing_subscriber::layer::Context<'_, S>) {
+            if *event.metadata().level() <= tracing::Level::WARN {
+                self.0.fetch_add(1, std::sync::atomic::Ordering::SeqCst);
+            }
+        }
+    }
+
+    #[test]
+    fn test_parse_ssdp_datagram() {
+        use tracing_subscriber::layer::SubscriberExt;
+
+        // An mDNS response like Bonjour sends: a binary header, then length-prefixed labels.
+        let mut bonjour = vec![0x00, 0x00, 0x84, 0x00, 0x00, 0x00, 0x00, 0x01, 0x00, 0x00, 0x00, 0x00];
+        for label in ["_airplay", "_tcp", "local"] {
+            bonjour.push(label.len() as u8);
+            bonjour.extend_from_slice(label.as_bytes());
+        }
+        bonjour.extend_from_slice(&[0x00, 0x00, 0x0c, 0x00, 0x01, 0xff, 0xfe]);
+        bonjour.extend_from_slice(b"\r\nLocation: not an address\r\n");
+
+        let warnings = std::sync::Arc::new(std::sync::atomic::AtomicUsize::new(0));
+        let subscriber = tracing_subscriber::registry().with(WarningCounter(warnings.clone()));
+        tracing::subscriber::with_default(subscriber, || {
+            assert!(parse_ssdp_datagram(&bonjour).is_empty());
+            assert!(parse_ssdp_datagram(b"").is_empty());
+            assert!(parse_ssdp_datagram(b"\r\nNOTIFY * HTTP/1.1\r\n").is_empty());
+        });
+        assert_eq!(warnings.load(std::sync::atomic::Ordering::SeqCst), 0);
+
+        assert_eq!(
+            parse_ssdp_datagram(X1_CARBON_NOTIFY.as_bytes()),
+            vec![parse_ssdp_notify(X1_CARBON_NOTIFY).unwrap()]
+        );
+        assert!(parse_ssdp_datagram(b"M-SEARCH * HTTP/1.1\r\n\r\n").is_empty());
+    }
+
+    #[test]
+    fn test_bambu_model_from_model_code() {
+        // (model code, model, chamber temp, AMS by default, camera, chamber light, max nozzle temp)
+        let models = [
+            ("3DPrinter-X1-Carbon", BambuModel::X1Carbon, true, true, true, true, 300),
+            ("BL-P001", BambuModel::X1Carbon, true, true, true, true, 300),
+            ("3DPrinter-X1", BambuModel::X1, true, true, true, true, 300),
+            ("BL-P002", BambuModel::X1, true, true, true, true, 300),
+            ("C13", BambuModel::X1E, true, true, true, true, 320),
+            ("C11", BambuModel::P1P, false, false, false, true, 300),
+            ("C12", BambuModel::P1S, false, false, false, true, 300),
+            ("N2S", BambuModel::A1, false, false, false, false, 300),
+            ("N1", BambuModel::A1Mini, false, false, false, false, 300),
+            ("O1D", BambuModel::H2D, true, false, true, true, 350),
+        ];
+
+        for (code, model, chamber, ams, camera, light, max_nozzle) in models {
+            assert_eq!(BambuModel::from_code(code), model, "{}", code);
+            assert_eq!(BambuModel::from_code(model.code()), model, "{}", code);
+            assert_eq!(
+                BambuModel::from_name(&model.to_string()),
+                Some(model.clone()),
+                "{}",
+                code
+            );
+            assert_eq!(model.supports_chamber_temp(), chamber, "{}", code);
+            // The same models have a door sensor.
+            assert_eq!(model.has_door_sensor(), chamber, "{}", code);
+            assert_eq!(model.default_has_ams(), ams, "{}", code);
+            assert_eq!(model.has_camera(), camera, "{}", code);
+            assert_eq!(model.has_chamber_light(), light, "{}", code);
+            assert_eq!(model.max_nozzle_temp(), max_nozzle, "{}", code);
+        }
+
+        // Nothing is assumed about a printer that doesn't say what it is.
+        assert_eq!(BambuModel::default().to_string(), "Bambu Lab model unknown");
+        assert!(!BambuModel::default().supports_chamber_temp());
+
+        assert_eq!(BambuModel::from_config("Bambu Lab P1S"), BambuModel::P1S);
+        assert_eq!(BambuModel::from_config("A1 Mini"), BambuModel::A1Mini);
+        assert_eq!(BambuModel::from_config("C12"), BambuModel::P1S);
+        assert_eq!(BambuModel::from_config("Z9X"), BambuModel::Unknown("Z9X".to_string()));
+    }
+
+    #[test]
+    fn test_bambu_model_unknown_code() {
+        let model = BambuModel::from_code("Z9X");
+        assert_eq!(model, BambuModel::Unknown("Z9X".to_string()));
+        assert_eq!(model.code(), "Z9X");
+        assert_eq!(model.to_string(), "Bambu Lab model Z9X");
+        assert_eq!(BambuModel::from_code(model.code()), model);
+        assert_eq!(BambuModel::from_name(&model.to_string()), Some(model.clone()));
+
+        // Nothing is assumed about what it can do.
+        assert!(!model.supports_chamber_temp() && !model.has_camera() && !model.has_lidar());
+        assert!(!model.has_door_sensor() && !model.has_chamber_light());
+        assert_eq!(model.max_nozzle_temp(), 300);
+
+        assert_eq!(BambuModel::from_name("Prusa MK4"), None);
+    }
+
+    #[test]
+    fn test_parse_location() {
+        assert_eq!(parse_location("192.168.1.5"), Some("192.168.1.5".parse().unwrap()));
+        assert_eq!(
+            parse_location("http://192.168.1.5:80/desc.xml"),
+            Some("192.168.1.5".parse().unwrap())
+        );
+        assert_eq!(
+            parse_location("http://[fe80::1]:80/desc.xml"),
+            Some("fe80::1".parse().unwrap())
+        );
+        assert_eq!(parse_location("2001:db8::10"), Some("2001:db8::10".parse().unwrap()));
+        assert_eq!(
+            parse_location("mqtts://[2001:db8::10]:8884"),
+            Some("2001:db8::10".parse().unwrap())
+        );
+        assert_eq!(parse_location("http://printer.local/desc.xml"), None);
+        assert_eq!(parse_location("not an address"), None);
+    }
+
+    #[test]
+    fn test_parse_ssdp_mqtt_port() {
+        let notify = X1_CARBON_NOTIFY.replace("Location: 192.168.1.42", "Location: mqtts://192.168.1.42:8884");
+        let discovered = parse_ssdp_notify(&notify).unwrap();
+        assert_eq!(discovered.ip, Some("192.168.1.42".parse().unwrap()));
+        assert_eq!(discovered.port, Some(8884));
+
+        assert_eq!(parse_mqtt_port("http://192.168.1.42:80/desc.xml"), None);
+        assert_eq!(parse_mqtt_port("192.168.1.42"), None);
+    }
+
+    #[test]
+    fn test_parse_ssdp_bad_location() {
+        let garbage = X1_CARBON_NOTIFY.replace("Location: 192.168.1.42", "Location: \u{1}garbage");
+        assert_eq!(parse_ssdp_notify(&garbage), None);
+
+        // A bad packet doesn't stop the next one being parsed.
+        let url = X1_CARBON_NOTIFY.replace("Location: 192.168.1.42", "Location: http://192.168.1.42:80/desc.xml");
+        assert_eq!(
+            parse_ssdp_notify(&url).unwrap().ip,
+            Some("192.168.1.42".parse().unwrap())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_bind_reusable() -> Result<()> {
+        let first = bind_reusable("127.0.0.1:0".parse()?)?;
+        let second = bind_reusable(first.local_addr()?)?;
+
+        assert_eq!(first.local_addr()?, second.local_addr()?);
+
+        Ok(())
+    }
+
+    #[test]
+    fn test_parse_ssdp_not_notify() {
+        assert_eq!(parse_ssdp_notify("M-SEARCH * HTTP/1.1\r\nST: ssdp:all\r\n\r\n"), None);
+        assert_eq!(parse_ssdp_notify(""), None);
+    }
+}
@@ -0,0 +1,480 @@
+//! End-to-end tests of Bambu discovery and connections: NOTIFY frames captured from real printers
+//! are sent to the discovery socket, and the printers connect to a fake MQTT broker in place of
+//! the printer.
+
+use std::{
+    net::SocketAddr,
+    sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    },
+    time::Duration,
+};
+
+use tokio::io::{AsyncReadExt, AsyncWriteExt};
+use tokio_rustls::rustls;
+
+use crate::{
+    config::BambuLabsConfig,
+    network_printer::{
+        bambu::{Bambu, BAMBU_X1_CARBON_URN},
+        bambu_common::DiscoveredBambu,
+        NetworkPrinterInfo, NetworkPrinters, RejectionReason,
+    },
+};
+
+/// A NOTIFY captured from an X1 Carbon.
+const X1_CARBON_NOTIFY: &str = "NOTIFY * HTTP/1.1\r\n\
+    HOST: 239.255.255.250:1990\r\n\
+    Server: UPnP/1.0\r\n\
+    Location: 192.168.1.42\r\n\
+    NT: urn:bambulab-com:device:3dprinter:1\r\n\
+    NTS: ssdp:alive\r\n\
+    USN: 00M09A350100123\r\n\
+    Cache-Control: max-age=1800\r\n\
+    DevModel.bambu.com: 3DPrinter-X1-Carbon\r\n\
+    DevName.bambu.com: workshop-x1c\r\n\
+    DevSignal.bambu.com: -44\r\n\
+    DevConnect.bambu.com: lan\r\n\
+    DevBind.bambu.com: free\r\n\
+    Devseclink.bambu.com: secure\r\n\
+    \r\n";
+
+/// A NOTIFY captured from a P1S.
+const P1S_NOTIFY: &str = "NOTIFY * HTTP/1.1\r\n\
+    HOST: 239.255.255.250:1990\r\n\
+    Server: UPnP/1.0\r\n\
+    Location: 192.168.1.43\r\n\
+    NT: urn:bambulab-com:device:3dprinter:1\r\n\
+    NTS: ssdp:alive\r\n\
+    USN: 01P00A391800456\r\n\
+    Cache-Control: max-age=1800\r\n\
+    DevModel.bambu.com: C12\r\n\
+    DevName.bambu.com: workshop-p1s\r\n\
+    DevSignal.bambu.com: -51\r\n\
+    DevConnect.bambu.com: lan\r\n\
+    DevBind.bambu.com: free\r\n\
+    Devseclink.bambu.com: secure\r\n\
+    DevVersion.bambu.com: 01.06.00.00\r\n\
+    DevCap.bambu.com: 1\r\n\
+    \r\n";
+
+/// A NOTIFY captured from an A1.
+const A1_NOTIFY: &str = "NOTIFY * HTTP/1.1\r\n\
+    HOST: 239.255.255.250:1990\r\n\
+    Server: UPnP/1.0\r\n\
+    Location: 192.168.1.44\r\n\
+    NT: urn:bambulab-com:device:3dprinter:1\r\n\
+    NTS: ssdp:alive\r\n\
+    USN: 03919A3A2300789\r\n\
+    Cache-Control: max-age=1800\r\n\
+    DevModel.bambu.com: N2S\r\n\
+    DevName.bambu.com: workshop-a1\r\n\
+    DevSignal.bambu.com: -38\r\n\
+    DevConnect.bambu.com: lan\r\n\
+    DevBind.bambu.com: free\r\n\
+    Devseclink.bambu.com: secure\r\n\
+    DevVersion.bambu.com: 01.02.00.00\r\n\
+    DevCap.bambu.com: 1\r\n\
+    \r\n";
+
+/// The status the fake broker reports for every printer.
+const STATUS_REPORT: &str = r#"{"print":{"command":"push_status","sequence_id":"1","gcode_state":"IDLE"}}"#;
+
+/// How the fake broker treats its clients, and what it saw of them.
+#[derive(Clone, Default)]
+struct BrokerOptions {
+    /// The access code to accept, any is accepted if unset.
+    access_code: Option<String>,
+    /// How many connections to drop after their first report, like a printer on a flaky link.
+    drop_connections: Arc<AtomicUsize>,
+    /// How many times a client subscribed to the reports.
+    subscribes: Arc<AtomicUsize>,
+    /// How many commands clients published.
+    commands: Arc<AtomicUsize>,
+}
+
+/// Start an MQTT broker on the IPv4 loopback, see [`start_fake_broker_on`].
+async fn start_fake_broker() -> u16 {
+    start_fake_broker_on("127.0.0.1:0").await
+}
+
+/// Start an MQTT broker that accepts any client, and sends each one a status report on the
+/// topic it subscribes to, like a printer does.
+/// Returns the port it listens on.
+async fn start_fake_broker_on(addr: &str) -> u16 {
+    start_fake_broker_with(addr, BrokerOptions::default()).await
+}
+
+/// Start an MQTT broker like [`start_fake_broker_on`], treating clients as `options` says.
+async fn start_fake_broker_with(addr: &str, options: BrokerOptions) -> u16 {
+    let certified = rcgen::generate_simple_self_signed(vec!["localhost".to_string()]).unwrap();
+    let key = rustls::pki_types::PrivatePkcs8KeyDer::from(certified.key_pair.serialize_der());
+    let config = rustls::ServerConfig::builder()
+        .with_no_client_auth()
+        .with_single_cert(vec![certified.cert.der().clone()], key.into())
+        .unwrap();
+    let acceptor = tokio_rustls::TlsAcceptor::from(std::sync::Arc::new(config));
+
+    let listener = tokio::net::TcpListener::bind(addr).await.unwrap();
+    let port = listener.local_addr().unwrap().port();
+
+    tokio::spawn(async move {
+        while let Ok((stream, _)) = listener.accept().await {
+            let acceptor = acceptor.clone();
+            let options = options.clone();
+            tokio::spawn(async move {
+                if let Ok(stream) = acceptor.accept(stream).await {
+                    // The client going away ends the connection, that's fine.
+                    let _ = serve_client(stream, &options).await;
+                }
+            });
+        }
+    });
+
+    port
+}
+
+/// Answer a client's MQTT packets until it disconnects, or `options` says to drop it.
+async fn serve_client<S: AsyncReadExt + AsyncWriteExt + Unpin>(
+    mut stream: S,
+    options: &BrokerOptions,
+) -> std::io::Result<()> {
+    loop {
+        let (header, body) = read_packet(&mut stream).await?;
+
+        match header >> 4 {
+            // CONNECT, accept it unless the access code is wrong.
+            1 => {
+                if options
+                    .access_code
+                    .as_ref()
+                    .is_some_and(|access_code| connect_password(&body) != access_code.as_bytes())
+                {
+                    // Not authorized.
+                    stream.write_all(&packet(0x20, &[0, 5])).await?;
+                    return Ok(());
+                }
+
+                stream.write_all(&packet(0x20, &[0, 0])).await?
+            }
+            // SUBSCRIBE, grant it and report on the topic straight away.
+            8 => {
+                let topic_len = u16::from_be_bytes([body[2], body[3]]) as usize;
+                let topic = &body[4..4 + topic_len];
+                stream.write_all(&packet(0x90, &[body[0], body[1], 0])).await?;
+
+                let mut publish = Vec::new();
+                publish.extend_from_slice(&(topic.len() as u16).to_be_bytes());
+                publish.extend_from_slice(topic);
+                publish.extend_from_slice(STATUS_REPORT.as_bytes());
+                stream.write_all(&packet(0x30, &publish)).await?;
+                options.subscribes.fetch_add(1, Ordering::SeqCst);
+
+                let drop = options
+                    .drop_connections
+                    .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |n| n.checked_sub(1));
+                if drop.is_ok() {
+                    return Ok(());
+                }
+            }
+            // PUBLISH, a command.
+            3 => {
+                options.commands.fetch_add(1, Ordering::SeqCst);
+            }
+            // PINGREQ.
+            12 => stream.write_all(&packet(0xd0, &[])).await?,
+            // DISCONNECT.
+            14 => return Ok(()),
+            _ => (),
+        }
+    }
+}
+
+/// The password in the body of a CONNECT packet, empty if there isn't one.
+fn connect_password(body: &[u8]) -> &[u8] {
+    // Skip the protocol name, level, flags and keep alive to get to the payload.
+    let name_len = u16::from_be_bytes([body[0], body[1]]) as usize;
+    let flags = body[2 + name_len + 1];
+    let mut payload = &body[2 + name_len + 4..];
+
+    let mut next_field = || {
+        let len = u16::from_be_bytes([payload[0], payload[1]]) as usize;
+        let (field, rest) = payload[2..].split_at(len);
+        payload = rest;
+        field
+    };
+
+    // The client ID comes first, then the username and password if there are any.
+    next_field();
+    if flags & 0x80 != 0 {
+        next_field();
+    }
+    if flags & 0x40 != 0 {
+        return next_field();
+    }
+
+    &[]
+}
+
+/// Read an MQTT packet, returning its header byte and body.
+async fn read_packet<S: AsyncReadExt + Unpin>(stream: &mut S) -> std::io::Result<(u8, Vec<u8>)> {
+    let header = stream.read_u8().await?;
+
+    // The remaining length is 7 bits a byte, with the top bit set while there's more.
+    let mut len = 0;
+    for shift in (0..28).step_by(7) {
+        let byte = stream.read_u8().await?;
+        len |= ((byte & 0x7f) as usize) << shift;
+        if byte & 0x80 == 0 {
+            break;
+        }
+    }
+
+    let mut body = vec![0; len];
+    stream.read_exact(&mut body).await?;
+
+    Ok((header, body))
+}
+
+/// Build an MQTT packet from its header byte and body.
+fn packet(header: u8, body: &[u8]) -> Vec<u8> {
+    let mut packet = vec![header];
+
+    let mut len = body.len();
+    loop {
+        let byte = (len % 128) as u8;
+        len /= 128;
+        if len == 0 {
+            packet.push(byte);
+            break;
+        }
+        packet.push(byte | 0x80);
+    }
+
+    packet.extend_from_slice(body);
+    packet
+}
+
+/// A Bambu backend listening for NOTIFY frames on `bind_addr`, with a config for every fixture.
+fn bambu(bind_addr: SocketAddr) -> Bambu {
+    let config: BambuLabsConfig = toml::from_str(&format!(
+        r#"
+        bind_addr = "{}"
+        connect_timeout_secs = 5
+
+        [[machines]]
+        id = "workshop-x1c"
+        access_code = "12345678"
+        slicer_config = "config/bambu"
+
+        [[machines]]
+        id = "workshop-p1s"
+        access_code = "12345678"
+        slicer_config = "config/bambu"
+
+        [[machines]]
+        id = "workshop-a1"
+        access_code = "12345678"
+        slicer_config = "config/bambu"
+        "#,
+        bind_addr
+    ))
+    .unwrap();
+
+    Bambu::new(&config)
+}
+
+/// Point the printer announcing a NOTIFY at the fake broker, rather than its own address.
+fn at_broker(notify: &str, broker_port: u16) -> String {
+    let location = notify.lines().find(|line| line.starts_with("Location:")).unwrap();
+    notify.replace(location, &format!("Location: mqtts://127.0.0.1:{}", broker_port))
+}
+
+/// Run discovery while announcing the datagram to it at `to`, as a printer would, until
+/// `expected` printers are found.
+async fn discover_announced(bambu: &Bambu, to: SocketAddr, datagram: &str, expected: usize) -> Vec<NetworkPrinterInfo> {
+    let socket = tokio::net::UdpSocket::bind("127.0.0.1:0").await.unwrap();
+    let cancel = tokio_util::sync::CancellationToken::new();
+
+    let announce = async {
+        // Keep announcing, the first frames can arrive before discovery is listening.
+        let _ = tokio::time::timeout(Duration::from_secs(10), async {
+            while bambu.list().unwrap().len() < expected {
+                socket.send_to(datagram.as_bytes(), to).await.unwrap();
+                tokio::time::sleep(Duration::from_millis(100)).await;
+            }
+        })
+        .await;
+        cancel.cancel();
+    };
+
+    let (printers, ()) = tokio::join!(bambu.discover_for(Duration::from_secs(15), cancel.clone()), announce);
+    printers.unwrap()
+}
+
+#[tokio::test]
+async fn test_discover_from_notify() {
+    let broker_port = start_fake_broker().await;
+
+    for (notify, serial, name, model) in [
+        (
+            X1_CARBON_NOTIFY,
+            "00M09A350100123",
+            "workshop-x1c",
+            "Bambu Lab X1 Carbon",
+        ),
+        (P1S_NOTIFY, "01P00A391800456", "workshop-p1s", "Bambu Lab P1S"),
+        (A1_NOTIFY, "03919A3A2300789", "workshop-a1", "Bambu Lab A1"),
+    ] {
+        let addr: SocketAddr = ([127, 0, 0, 1], portpicker::pick_unused_port().unwrap()).into();
+        let bambu = bambu(addr);
+
+        let printers = discover_announced(&bambu, addr, &at_broker(notify, broker_port), 1).await;
+        assert_eq!(
+            printers.len(),
+            1,
+            "{} was not discovered: {:?}",
+            name,
+            bambu.list_rejected()
+        );
+
+        let printer = &printers[0];
+        assert_eq!(printer.serial.as_deref(), Some(serial));
+        assert_eq!(printer.hostname.as_deref(), Some(name));
+        assert_eq!(printer.model.as_deref(), Some(model));
+        assert_eq!(printer.ip.to_string(), "127.0.0.1");
+        assert_eq!(printer.port, Some(broker_port));
+    }
+}
+
+#[tokio::test]
+async fn test_discover_joined_notifies() {
+    let broker_port = start_fake_broker().await;
+    let addr: SocketAddr = ([127, 0, 0, 1], portpicker::pick_unused_port().unwrap()).into();
+    let bambu = bambu(addr);
+
+    // A router re-broadcasting both printers' frames in one datagram.
+    let datagram = format!(
+        "{}{}",
+        at_broker(X1_CARBON_NOTIFY, broker_port),
+        at_broker(P1S_NOTIFY, broker_port)
+    );
+
+    let mut serials: Vec<_> = discover_announced(&bambu, addr, &datagram, 2)
+        .await
+        .into_iter()
+        .filter_map(|printer| printer.serial)
+        .collect();
+    serials.sort();
+    assert_eq!(serials, ["00M09A350100123", "01P00A391800456"]);
+}
+
+#[tokio::test]
+async fn test_discover_ipv6() {
+    let broker_port = start_fake_broker_on("[::1]:0").await;
+    let addr: SocketAddr = ([127, 0, 0, 1], portpicker::pick_unused_port().unwrap()).into();
+    let bambu = bambu(addr);
+
+    let location = X1_CARBON_NOTIFY
+        .lines()
+        .find(|line| line.starts_with("Location:"))
+        .unwrap();
+    let notify = X1_CARBON_NOTIFY.replace(location, &format!("Location: mqtts://[::1]:{}", broker_port));
+
+    let printers = discover_announced(&bambu, addr, &notify, 1).await;
+    assert_eq!(printers.len(), 1, "not discovered: {:?}", bambu.list_rejected());
+    assert_eq!(printers[0].ip.to_string(), "::1");
+    assert_eq!(printers[0].port, Some(broker_port));
+}
+
+#[tokio::test]
+async fn test_reconnect_after_drop() {
+    let options = BrokerOptions {
+        drop_connections: Arc::new(AtomicUsize::new(1)),
+        ..Default::default()
+    };
+    let broker_port = start_fake_broker_with("127.0.0.1:0", options.clone()).await;
+    let addr: SocketAddr = ([127, 0, 0, 1], portpicker::pick_unused_port().unwrap()).into();
+    let bambu = bambu(addr);
+
+    let printers = discover_announced(&bambu, addr, &at_broker(X1_CARBON_NOTIFY, broker_port), 1).await;
+    assert_eq!(printers.len(), 1, "not discovered: {:?}", bambu.list_rejected());
+
+    // The broker dropped the first connection after its report, the client has to connect and
+    // subscribe again.
+    tokio::time::timeout(Duration::from_secs(10), async {
+        while options.subscribes.load(Ordering::SeqCst) < 2 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the printer was not reconnected");
+
+    // Commands from the printer handle go out on the new connection.
+    let printer = bambu.get_by_serial("00M09A350100123").unwrap();
+    printer.client.emergency_stop().await.unwrap();
+    tokio::time::timeout(Duration::from_secs(5), async {
+        while options.commands.load(Ordering::SeqCst) < 1 {
+            tokio::time::sleep(Duration::from_millis(50)).await;
+        }
+    })
+    .await
+    .expect("the command was not sent on the new connection");
+
+    bambu.shutdown().await;
+}
+
+#[tokio::test]
+async fn test_access_code_refused() {
+    let options = BrokerOptions {
+        access_code: Some("87654321".to_string()),
+        ..Default::default()
+    };
+    let broker_port = start_fake_broker_with("127.0.0.1:0", options).await;
+    let config = |access_code: &str| -> BambuLabsConfig {
+        toml::from_str(&format!(
+            r#"
+            connect_timeout_secs = 1
+
+            [[machines]]
+            id = "workshop-x1c"
+            access_code = "{}"
+            slicer_config = "config/bambu"
+            "#,
+            access_code
+        ))
+        .unwrap()
+    };
+    let bambu = Bambu::new(&config("12345678"));
+    let discovered = DiscoveredBambu {
+        urn: Some(BAMBU_X1_CARBON_URN.to_string()),
+        model_code: Some("3DPrinter-X1-Carbon".to_string()),
+        name: Some("workshop-x1c".to_string()),
+        ip: Some([127, 0, 0, 1].into()),
+        serial: Some("00M09A350100123".to_string()),
+        port: Some(broker_port),
+    };
+
+    bambu.add_discovered(discovered.clone()).await.unwrap();
+    assert_eq!(
+        bambu.list_rejected().unwrap()[0].reason,
+        RejectionReason::AuthFailed {
+            name: "workshop-x1c".to_string()
+        }
+    );
+
+    // The printer said no, so it isn't tried again with the same access code.
+    tokio::time::timeout(Duration::from_millis(500), bambu.add_discovered(discovered.clone()))
+        .await
+        .expect("tried the access code that was refused again")
+        .unwrap();
+    assert!(bambu.list().unwrap().is_empty());
+
+    // Until the access code is changed.
+    bambu.reload_config(config("87654321"));
+    bambu.add_discovered(discovered).await.unwrap();
+    assert_eq!(bambu.list().unwrap().len(), 1);
+
+    bambu.shutdown().await;
+}
@@ -0,0 +1,639 @@
+//! Elegoo backend for the [`crate::network_printer::NetworkPrinter`] trait, for printers speaking
+//! the SDCP protocol, e.g. the Centauri Carbon.
+
+use std::{
+    net::{IpAddr, SocketAddr},
+    sync::Arc,
+    time::Duration,
+};
+
+use anyhow::Result;
+use dashmap::DashMap;
+use futures::{SinkExt, StreamExt};
+use serde::{Deserialize, Serialize};
+use tokio_tungstenite::tungstenite::Message as WsMessage;
+
+use crate::{
+    config::ElegooConfig,
+    network_printer::{
+        CommandAck, Message, NetworkPrinter, NetworkPrinterError, NetworkPrinterHandle, NetworkPrinterInfo,
+        NetworkPrinterManufacturer, NetworkPrinters, PrinterSnapshot, PrinterState,
+    },
+};
+
+/// The UDP port printers listen for the discovery broadcast on.
+const DISCOVERY_PORT: u16 = 3000;
+
+/// The discovery broadcast, printers answer it with their details.
+const DISCOVERY_MESSAGE: &[u8] = b"M99999";
+
+/// How often to broadcast the discovery message.
+const DISCOVERY_INTERVAL: Duration = Duration::from_secs(15);
+
+/// The port of the SDCP websocket.
+const WEBSOCKET_PORT: u16 = 3030;
+
+/// How often to ping the printer, it closes connections that go quiet for a minute.
+const HEARTBEAT_INTERVAL: Duration = Duration::from_secs(30);
+
+/// How long to wait before connecting again after the connection drops.
+const RECONNECT_DELAY: Duration = Duration::from_secs(5);
+
+/// How long to wait for the printer to answer a command.
+const COMMAND_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// SDCP command to report the status.
+const CMD_STATUS: u32 = 0;
+/// SDCP command to pause the print.
+const CMD_PAUSE: u32 = 129;
+/// SDCP command to stop the print.
+const CMD_STOP: u32 = 130;
+/// SDCP command to resume the print.
+const CMD_RESUME: u32 = 131;
+
+/// An Elegoo printer that answered the discovery broadcast.
+/// Every field is optional since nothing stops a device sending us a partial answer, it's up to the
+/// backend to decide what it needs.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct DiscoveredElegoo {
+    /// The name the printer was given (`Name`).
+    pub name: Option<String>,
+    /// The model of the printer (`MachineName`), e.g. `Centauri Carbon`.
+    pub model: Option<String>,
+    /// The IP address of the printer (`MainboardIP`).
+    pub ip: Option<IpAddr>,
+    /// The ID of the printer's mainboard (`MainboardID`), used to address it over SDCP.
+    pub mainboard_id: Option<String>,
+    /// The version of SDCP the printer speaks (`ProtocolVersion`).
+    pub protocol_version: Option<String>,
+}
+
+/// The answer to the discovery broadcast.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct DiscoveryResponse {
+    data: DiscoveryData,
+}
+
+#[derive(Debug, Deserialize)]
+struct DiscoveryData {
+    #[serde(rename = "Name")]
+    name: Option<String>,
+    #[serde(rename = "MachineName")]
+    machine_name: Option<String>,
+    #[serde(rename = "MainboardIP")]
+    mainboard_ip: Option<String>,
+    #[serde(rename = "MainboardID")]
+    mainboard_id: Option<String>,
+    #[serde(rename = "ProtocolVersion")]
+    protocol_version: Option<String>,
+}
+
+/// Parse a printer's answer to the discovery broadcast.
+/// Returns `None` if the payload isn't an SDCP discovery answer.
+pub fn parse_discovery(payload: &[u8]) -> Option<DiscoveredElegoo> {
+    let response: DiscoveryResponse = match serde_json::from_slice(payload) {
+        Ok(response) => response,
+        Err(e) => {
+            tracing::trace!("Not an SDCP discovery answer, ignoring: {}", e);
+
+            return None;
+        }
+    };
+    let data = response.data;
+
+    Some(DiscoveredElegoo {
+        name: data.name,
+        model: data.machine_name,
+        ip: data.mainboard_ip.and_then(|ip| ip.parse().ok()),
+        mainboard_id: data.mainboard_id,
+        protocol_version: data.protocol_version,
+    })
+}
+
+/// Elegoo printer backend.
+pub struct Elegoo {
+    config: ElegooConfig,
+    /// The printers found, keyed by mainboard ID.
+    printers: DashMap<String, NetworkPrinterHandle>,
+}
+
+impl Elegoo {
+    /// Create a new Elegoo printer backend.
+    pub fn new(config: &ElegooConfig) -> Self {
+        Self {
+            config: config.clone(),
+            printers: DashMap::new(),
+        }
+    }
+
+    /// Add a printer that answered the discovery broadcast from `from`.
+    fn add_discovered(&self, discovered: DiscoveredElegoo, from: IpAddr) {
+        let Some(mainboard_id) = discovered.mainboard_id else {
+            tracing::debug!("Elegoo printer at {} did not send a mainboard ID, ignoring", from);
+            return;
+        };
+        // The printer knows its own address better than we do, it may have answered from another.
+        let ip = discovered.ip.unwrap_or(from);
+
+        if self
+            .printers
+            .get(&mainboard_id)
+            .is_some_and(|handle| handle.info.ip == ip)
+        {
+            return;
+        }
+
+        tracing::info!(
+            "Found Elegoo printer {} at {} (SDCP {})",
+            mainboard_id,
+            ip,
+            discovered.protocol_version.as_deref().unwrap_or("unknown")
+        );
+
+        let handle = NetworkPrinterHandle {
+            info: NetworkPrinterInfo {
+                hostname: discovered.name,
+                ip,
+                port: Some(WEBSOCKET_PORT),
+                manufacturer: NetworkPrinterManufacturer::Elegoo,
+                model: discovered.model,
+                serial: Some(mainboard_id.clone()),
+            },
+            client: Arc::new(Box::new(ElegooPrinter::connect(ip, &mainboard_id))),
+        };
+        // Replacing the handle of a printer that moved drops its old connection.
+        self.printers.insert(mainboard_id, handle);
+    }
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinters for Elegoo {
+    async fn discover(&self) -> Result<()> {
+        tracing::info!("Spawning Elegoo discovery task");
+
+        let socket = tokio::net::UdpSocket::bind((std::net::Ipv4Addr::UNSPECIFIED, 0)).await?;
+        socket.set_broadcast(true)?;
+        let target = SocketAddr::new(self.config.broadcast_addr(), DISCOVERY_PORT);
+
+        let mut search = tokio::time::interval(DISCOVERY_INTERVAL);
+        let mut buf = [0; 2048];
+        loop {
+            tokio::select! {
+                _ = search.tick() => {
+                    socket.send_to(DISCOVERY_MESSAGE, target).await?;
+                }
+                received = socket.recv_from(&mut buf) => {
+                    let (len, from) = received?;
+                    if let Some(discovered) = parse_discovery(&buf[..len]) {
+                        self.add_discovered(discovered, from.ip());
+                    }
+                }
+            }
+        }
+    }
+
+    fn list(&self) -> Result<Vec<NetworkPrinterInfo>> {
+        Ok(self
+            .printers
+            .iter()
+            .map(|printer| printer.value().info.clone())
+            .collect())
+    }
+
+    fn list_handles(&self) -> Result<Vec<NetworkPrinterHandle>> {
+        Ok(self.printers.iter().map(|printer| printer.value().clone()).collect())
+    }
+}
+
+/// A frame sent by the printer over the websocket, either a status or the answer to a command.
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SdcpFrame {
+    data: Option<SdcpResponse>,
+    status: Option<SdcpStatus>,
+}
+
+/// The printer's answer to a command.
+#[derive(Debug, Clone, Deserialize)]
+struct SdcpResponse {
+    #[serde(rename = "RequestID")]
+    request_id: String,
+    #[serde(rename = "Data")]
+    data: SdcpAck,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "PascalCase")]
+struct SdcpAck {
+    /// 0 if the command was accepted.
+    ack: i64,
+}
+
+/// The status the printer reports.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SdcpStatus {
+    #[serde(default)]
+    current_status: Vec<u32>,
+    temp_of_nozzle: Option<f64>,
+    temp_target_nozzle: Option<f64>,
+    temp_of_hotbed: Option<f64>,
+    temp_target_hotbed: Option<f64>,
+    temp_of_box: Option<f64>,
+    print_info: Option<SdcpPrintInfo>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+#[serde(rename_all = "PascalCase")]
+struct SdcpPrintInfo {
+    status: u32,
+    current_layer: Option<u32>,
+    total_layer: Option<u32>,
+    current_ticks: Option<f64>,
+    total_ticks: Option<f64>,
+    filename: Option<String>,
+    progress: Option<u8>,
+}
+
+/// The commands waiting on an answer from the printer, keyed by request ID.
+type PendingRequests = DashMap<String, tokio::sync::oneshot::Sender<SdcpResponse>>;
+
+/// An Elegoo printer, controlled over an SDCP websocket.
+pub struct ElegooPrinter {
+    mainboard_id: String,
+    /// Frames to send to the printer.
+    outgoing: tokio::sync::mpsc::Sender<String>,
+    pending: Arc<PendingRequests>,
+    status: tokio::sync::watch::Receiver<Option<SdcpStatus>>,
+    connection: tokio::task::JoinHandle<()>,
+}
+
+impl ElegooPrinter {
+    /// Connect to the printer at `ip`, reconnecting in the background whenever the connection drops.
+    fn connect(ip: IpAddr, mainboard_id: &str) -> Self {
+        let url = format!("ws://{}/websocket", SocketAddr::new(ip, WEBSOCKET_PORT));
+        let (outgoing, mut frames) = tokio::sync::mpsc::channel(16);
+        let pending = Arc::new(PendingRequests::new());
+        let (status_tx, status) = tokio::sync::watch::channel(None);
+
+        let connection = {
+            let mainboard_id = mainboard_id.to_string();
+            let pending = pending.clone();
+            tokio::spawn(async move {
+                loop {
+                    if let Err(e) = run_connection(&url, &mainboard_id, &mut frames, &pending, &status_tx).await {
+                        tracing::warn!(
+                            event = "printer_disconnected",
+                            serial = %mainboard_id,
+                            "Lost connection to Elegoo printer {}: {:?}",
+                            mainboard_id,
+                            e
+                        );
+                    }
+                    tokio::time::sleep(RECONNECT_DELAY).await;
+                }
+            })
+        };
+
+        Self {
+            mainboard_id: mainboard_id.to_string(),
+            outgoing,
+            pending,
+            status,
+            connection,
+        }
+    }
+
+    /// Send a command and wait for the printer to answer it.
+    async fn command(&self, cmd: u32) -> Result<Message> {
+        let request_id = uuid::Uuid::new_v4().simple().to_string();
+        let (answer_tx, answer) = tokio::sync::oneshot::channel();
+        self.pending.insert(request_id.clone(), answer_tx);
+
+        let frame = request_frame(&self.mainboard_id, &request_id, cmd);
+        if self.outgoing.send(frame).await.is_err() {
+            self.pending.remove(&request_id);
+            return Err(NetworkPrinterError::Offline.into());
+        }
+
+        let response = match tokio::time::timeout(COMMAND_TIMEOUT, answer).await {
+            Ok(Ok(response)) => response,
+            _ => {
+                self.pending.remove(&request_id);
+                return Err(NetworkPrinterError::Offline.into());
+            }
+        };
+
+        Ok(Message::CommandAck(CommandAck {
+            success: Some(response.data.ack == 0),
+            reason: (response.data.ack != 0).then(|| format!("Rejected with ack {}", response.data.ack)),
+        }))
+    }
+}
+
+impl Drop for ElegooPrinter {
+    fn drop(&mut self) {
+        self.connection.abort();
+    }
+}
+
+/// Connect to the printer's websocket and pass frames both ways until the connection drops.
+async fn run_connection(
+    url: &str,
+    mainboard_id: &str,
+    outgoing: &mut tokio::sync::mpsc::Receiver<String>,
+    pending: &PendingRequests,
+    status: &tokio::sync::watch::Sender<Option<SdcpStatus>>,
+) -> Result<()> {
+    let (websocket, _) = tokio_tungstenite::connect_async(url).await?;
+    let (mut sink, mut stream) = websocket.split();
+
+    // Ask for the status straight away rather than waiting for the printer to change.
+    let request_id = uuid::Uuid::new_v4().simple().to_string();
+    sink.send(WsMessage::Text(request_frame(mainboard_id, &request_id, CMD_STATUS)))
+        .await?;
+
+    let mut heartbeat = tokio::time::interval(HEARTBEAT_INTERVAL);
+    loop {
+        tokio::select! {
+            frame = stream.next() => match frame {
+                Some(Ok(WsMessage::Text(text))) => handle_frame(&text, pending, status),
+                Some(Ok(WsMessage::Close(_))) | None => anyhow::bail!("The printer closed the connection"),
+                Some(Ok(_)) => (),
+                Some(Err(e)) => return Err(e.into()),
+            },
+            Some(frame) = outgoing.recv() => sink.send(WsMessage::Text(frame)).await?,
+            _ = heartbeat.tick() => sink.send(WsMessage::Text("ping".to_string())).await?,
+        }
+    }
+}
+
+/// Handle a frame from the printer, passing answers to whoever is waiting on them.
+fn handle_frame(text: &str, pending: &PendingRequests, status: &tokio::sync::watch::Sender<Option<SdcpStatus>>) {
+    // The answer to our heartbeat.
+    if text == "pong" {
+        return;
+    }
+
+    let frame: SdcpFrame = match serde_json::from_str(text) {
+        Ok(frame) => frame,
+        Err(e) => {
+            tracing::debug!("Ignoring unknown SDCP frame: {}", e);
+            return;
+        }
+    };
+
+    if let Some(new_status) = frame.status {
+        status.send_replace(Some(new_status));
+    }
+    if let Some(response) = frame.data {
+        if let Some((_, answer)) = pending.remove(&response.request_id) {
+            let _ = answer.send(response);
+        }
+    }
+}
+
+/// Build the frame for an SDCP command.
+fn request_frame(mainboard_id: &str, request_id: &str, cmd: u32) -> String {
+    let timestamp = std::time::SystemTime::now()
+        .duration_since(std::time::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+
+    serde_json::json!({
+        "Id": "",
+        "Data": {
+            "Cmd": cmd,
+            "Data": {},
+            "RequestID": request_id,
+            "MainboardID": mainboard_id,
+            "TimeStamp": timestamp,
+            "From": 0,
+        },
+        "Topic": format!("sdcp/request/{}", mainboard_id),
+    })
+    .to_string()
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinter for ElegooPrinter {
+    /// Check the printer has reported its status over the websocket.
+    fn is_ready(&self) -> bool {
+        self.status.borrow().is_some()
+    }
+
+    /// Get the status of a printer.
+    async fn status(&self) -> Result<Message> {
+        let mut status = self.status.clone();
+        status.mark_unchanged();
+
+        // The status comes separately from the answer to the command, give it a moment to arrive.
+        self.command(CMD_STATUS).await?;
+        let _ = tokio::time::timeout(COMMAND_TIMEOUT, status.changed()).await;
+
+        let snapshot = status.borrow().clone().ok_or(NetworkPrinterError::NoStatus)?;
+        Ok(Message::Status(snapshot.into()))
+    }
+
+    /// Get the version of the printer.
+    async fn version(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Pause the current print.
+    async fn pause(&self) -> Result<Message> {
+        self.command(CMD_PAUSE).await
+    }
+
+    /// Resume the current print.
+    async fn resume(&self) -> Result<Message> {
+        self.command(CMD_RESUME).await
+    }
+
+    /// Stop the current print.
+    async fn stop(&self) -> Result<Message> {
+        self.command(CMD_STOP).await
+    }
+
+    /// Immediately halt the printer.
+    async fn emergency_stop(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Set the led on or off.
+    async fn set_led(&self, _on: bool) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Get the accessories.
+    async fn accessories(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Slice a file.
+    async fn slice(&self, _file: &std::path::Path) -> Result<std::path::PathBuf> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Print a file.
+    /// Files are uploaded to SDCP printers over a separate HTTP API, which isn't supported yet.
+    async fn print(&self, _job_name: &str, _file: &std::path::Path) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+}
+
+/// Get the state of the printer from the SDCP print status.
+fn printer_state(status: &SdcpStatus) -> PrinterState {
+    match status.print_info.as_ref().map(|info| info.status) {
+        None | Some(0) if status.current_status.first().copied().unwrap_or(0) == 0 => PrinterState::Idle,
+        // Homing, dropping, exposing, lifting, stopping, checking the file and printing.
+        Some(1..=4 | 7 | 10 | 13) => PrinterState::Printing,
+        // Pausing and paused.
+        Some(5 | 6) => PrinterState::Paused,
+        Some(8) => PrinterState::Failed,
+        Some(9) => PrinterState::Finished,
+        _ => PrinterState::Unknown,
+    }
+}
+
+impl From<SdcpStatus> for PrinterSnapshot {
+    fn from(status: SdcpStatus) -> Self {
+        let state = printer_state(&status);
+        let print_info = status.print_info;
+
+        PrinterSnapshot {
+            state,
+            job_name: print_info
+                .as_ref()
+                .and_then(|info| info.filename.clone())
+                .filter(|filename| !filename.is_empty())
+                .map(|filename| match filename.rsplit_once('.') {
+                    Some((stem, "gcode")) => stem.to_string(),
+                    _ => filename,
+                }),
+            nozzle_temperature: status.temp_of_nozzle,
+            nozzle_target_temperature: status.temp_target_nozzle,
+            bed_temperature: status.temp_of_hotbed,
+            bed_target_temperature: status.temp_target_hotbed,
+            chamber_temperature: status.temp_of_box,
+            progress_percent: print_info.as_ref().and_then(|info| info.progress),
+            remaining_secs: print_info
+                .as_ref()
+                .and_then(|info| Some((info.total_ticks? - info.current_ticks?).max(0.0).round() as u64)),
+            current_layer: print_info.as_ref().and_then(|info| info.current_layer),
+            total_layers: print_info.as_ref().and_then(|info| info.total_layer),
+            speed_profile: None,
+            calibrating: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// A discovery answer captured from a Centauri Carbon.
+    const CENTAURI_DISCOVERY: &str = r#"{
+        "Id": "a6f3b1c2d4e5f60718293a4b5c6d7e8f",
+        "Data": {
+            "Name": "workshop-cc",
+            "MachineName": "Centauri Carbon",
+            "BrandName": "ELEGOO",
+            "MainboardIP": "192.168.1.60",
+            "MainboardID": "0a69ee780f4c0103",
+            "ProtocolVersion": "V3.0.0",
+            "FirmwareVersion": "V1.1.29"
+        }
+    }"#;
+
+    #[test]
+    fn test_parse_discovery() {
+        assert_eq!(
+            parse_discovery(CENTAURI_DISCOVERY.as_bytes()).unwrap(),
+            DiscoveredElegoo {
+                name: Some("workshop-cc".to_string()),
+                model: Some("Centauri Carbon".to_string()),
+                ip: Some("192.168.1.60".parse().unwrap()),
+                mainboard_id: Some("0a69ee780f4c0103".to_string()),
+                protocol_version: Some("V3.0.0".to_string()),
+            }
+        );
+
+        assert_eq!(parse_discovery(DISCOVERY_MESSAGE), None);
+        assert_eq!(parse_discovery(b"{}"), None);
+    }
+
+    #[test]
+    fn test_request_frame() {
+        let frame: serde_json::Value =
+            serde_json::from_str(&request_frame("0a69ee780f4c0103", "req-1", CMD_PAUSE)).unwrap();
+
+        assert_eq!(frame["Topic"], "sdcp/request/0a69ee780f4c0103");
+        assert_eq!(frame["Data"]["Cmd"], 129);
+        assert_eq!(frame["Data"]["RequestID"], "req-1");
+        assert_eq!(frame["Data"]["MainboardID"], "0a69ee780f4c0103");
+    }
+
+    #[test]
+    fn test_handle_frame() {
+        let pending = PendingRequests::new();
+        let (status_tx, status) = tokio::sync::watch::channel(None);
+        let (answer_tx, mut answer) = tokio::sync::oneshot::channel();
+        pending.insert("req-1".to_string(), answer_tx);
+
+        handle_frame(
+            r#"{"Id": "", "Data": {"Cmd": 129, "Data": {"Ack": 0}, "RequestID": "req-1", "MainboardID": "0a69ee780f4c0103", "TimeStamp": 1700000000}, "Topic": "sdcp/response/0a69ee780f4c0103"}"#,
+            &pending,
+            &status_tx,
+        );
+        assert_eq!(answer.try_recv().unwrap().data.ack, 0);
+        assert!(pending.is_empty());
+
+        handle_frame(
+            r#"{"Status": {"CurrentStatus": [1], "TempOfNozzle": 219.8, "TempTargetNozzle": 220, "TempOfHotbed": 59.5, "TempTargetHotbed": 60, "TempOfBox": 31.2, "PrintInfo": {"Status": 13, "CurrentLayer": 12, "TotalLayer": 240, "CurrentTicks": 600, "TotalTicks": 3600, "Filename": "benchy.gcode", "Progress": 17}}, "MainboardID": "0a69ee780f4c0103", "TimeStamp": 1700000001, "Topic": "sdcp/status/0a69ee780f4c0103"}"#,
+            &pending,
+            &status_tx,
+        );
+        let snapshot = PrinterSnapshot::from(status.borrow().clone().unwrap());
+        assert_eq!(snapshot.state, PrinterState::Printing);
+        assert_eq!(snapshot.job_name.as_deref(), Some("benchy"));
+        assert_eq!(snapshot.chamber_temperature, Some(31.2));
+        assert_eq!(snapshot.progress_percent, Some(17));
+        assert_eq!(snapshot.remaining_secs, Some(3000));
+        assert_eq!(snapshot.current_layer, Some(12));
+
+        // Heartbeat answers and garbage are ignored.
+        handle_frame("pong", &pending, &status_tx);
+        handle_frame("not json", &pending, &status_tx);
+    }
+
+    #[test]
+    fn test_printer_state() {
+        let status = |current: u32, print: Option<u32>| SdcpStatus {
+            current_status: vec![current],
+            temp_of_nozzle: None,
+            temp_target_nozzle: None,
+            temp_of_hotbed: None,
+            temp_target_hotbed: None,
+            temp_of_box: None,
+            print_info: print.map(|status| SdcpPrintInfo {
+                status,
+                current_layer: None,
+                total_layer: None,
+                current_ticks: None,
+                total_ticks: None,
+                filename: None,
+                progress: None,
+            }),
+        };
+
+        assert_eq!(printer_state(&status(0, None)), PrinterState::Idle);
+        assert_eq!(printer_state(&status(0, Some(0))), PrinterState::Idle);
+        assert_eq!(printer_state(&status(1, Some(13))), PrinterState::Printing);
+        assert_eq!(printer_state(&status(1, Some(6))), PrinterState::Paused);
+        assert_eq!(printer_state(&status(0, Some(9))), PrinterState::Finished);
+        assert_eq!(printer_state(&status(0, Some(8))), PrinterState::Failed);
+        assert_eq!(printer_state(&status(1, Some(99))), PrinterState::Unknown);
+    }
+}
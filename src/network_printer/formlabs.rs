@@ -9,7 +9,8 @@ use futures_util::{pin_mut, stream::StreamExt};
 use crate::{
     config::FormLabsConfig,
     network_printer::{
-        Message, NetworkPrinter, NetworkPrinterHandle, NetworkPrinterInfo, NetworkPrinterManufacturer, NetworkPrinters,
+        Message, NetworkPrinter, NetworkPrinterError, NetworkPrinterHandle, NetworkPrinterInfo,
+        NetworkPrinterManufacturer, NetworkPrinters,
     },
 };
 
@@ -102,6 +103,11 @@ impl NetworkPrinter for FormlabsPrinter {
         unimplemented!()
     }
 
+    /// Immediately halt the printer.
+    async fn emergency_stop(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
     /// Set the led on or off.
     async fn set_led(&self, _on: bool) -> Result<Message> {
         unimplemented!()
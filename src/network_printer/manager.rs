@@ -0,0 +1,402 @@
+//! One place to find printers from every discovery backend.
+
+use std::path::Path;
+
+use anyhow::Result;
+use futures::StreamExt;
+
+use crate::network_printer::{
+    DiscoveryEvent, NetworkPrinterHandle, NetworkPrinterInfo, NetworkPrinters, RejectedPrinter,
+};
+
+/// How many discovery events to buffer for a slow subscriber.
+const EVENT_CAPACITY: usize = 64;
+
+/// How often to check whether a subscriber is still listening.
+const SUBSCRIBER_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(1);
+
+/// Owns all the discovery backends, and looks printers up across them.
+/// A printer found by more than one backend is only listed once, from the first backend that
+/// found it.
+pub struct PrinterManager {
+    backends: Vec<Box<dyn NetworkPrinters>>,
+}
+
+impl PrinterManager {
+    /// Create a manager for the given backends.
+    pub fn new(backends: Vec<Box<dyn NetworkPrinters>>) -> Self {
+        Self { backends }
+    }
+
+    /// Get the handle of the printer with the given serial number.
+    pub fn get(&self, serial: &str) -> Option<NetworkPrinterHandle> {
+        self.get_by_serial(serial)
+    }
+
+    /// Restart the client of the printer with the given serial number, to recover a connection
+    /// that has stopped getting reports.
+    pub fn restart_client(&self, serial: &str) -> Result<()> {
+        let printer = self
+            .get(serial)
+            .ok_or_else(|| anyhow::anyhow!("printer not found by serial: {:?}", serial))?;
+
+        printer.client.restart_client()
+    }
+
+    /// Reconnect to the printers saved with `save_cache`, so they're listed as soon as they
+    /// report rather than once they're next discovered.
+    /// Printers that can't be reached are dropped, and left out the next time the cache is
+    /// saved. A missing cache is not an error.
+    pub async fn load_cache(&self, path: &Path) -> Result<()> {
+        let cache = match tokio::fs::read_to_string(path).await {
+            Ok(cache) => cache,
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => return Ok(()),
+            Err(e) => return Err(e.into()),
+        };
+        let printers: Vec<NetworkPrinterInfo> = serde_json::from_str(&cache)?;
+
+        let restores = printers.iter().map(|info| async move {
+            match self.restore(info).await {
+                Ok(true) => tracing::info!("Restored printer {} from the cache", printer_key(info)),
+                Ok(false) => tracing::debug!("No backend restores printer {}, dropping it", printer_key(info)),
+                Err(e) => tracing::warn!("Failed to restore printer {}, dropping it: {:?}", printer_key(info), e),
+            }
+        });
+        futures::future::join_all(restores).await;
+
+        Ok(())
+    }
+
+    /// Save the printers found so far, for `load_cache` to reconnect to after a restart.
+    pub async fn save_cache(&self, path: &Path) -> Result<()> {
+        let printers = serde_json::to_string_pretty(&self.list()?)?;
+        tokio::fs::write(path, printers).await?;
+
+        Ok(())
+    }
+}
+
+/// The key to tell printers apart by: the make and serial number, or the make and address for
+/// printers without one. Several printers can share an IP, e.g. Moonraker instances on one host.
+fn printer_key(info: &NetworkPrinterInfo) -> String {
+    match (&info.serial, info.port) {
+        (Some(serial), _) => format!("{:?} {}", info.manufacturer, serial),
+        (None, Some(port)) => format!("{:?} {}", info.manufacturer, std::net::SocketAddr::new(info.ip, port)),
+        (None, None) => format!("{:?} {}", info.manufacturer, info.ip),
+    }
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinters for PrinterManager {
+    /// Discover printers with every backend at once.
+    /// A backend failing is logged and doesn't stop the others.
+    async fn discover(&self) -> Result<()> {
+        let discoveries = self.backends.iter().map(|backend| async move {
+            let result = backend.discover().await;
+            if let Err(e) = &result {
+                tracing::error!("Printer discovery failed: {:?}", e);
+            }
+            result
+        });
+
+        futures::future::join_all(discoveries)
+            .await
+            .into_iter()
+            .collect::<Result<Vec<()>>>()?;
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<NetworkPrinterInfo>> {
+        Ok(self.list_handles()?.into_iter().map(|handle| handle.info).collect())
+    }
+
+    fn list_handles(&self) -> Result<Vec<NetworkPrinterHandle>> {
+        let mut seen = std::collections::HashSet::new();
+        let mut handles = Vec::new();
+        for backend in &self.backends {
+            for handle in backend.list_handles()? {
+                if seen.insert(printer_key(&handle.info)) {
+                    handles.push(handle);
+                }
+            }
+        }
+
+        Ok(handles)
+    }
+
+    fn get_by_serial(&self, serial: &str) -> Option<NetworkPrinterHandle> {
+        self.backends.iter().find_map(|backend| backend.get_by_serial(serial))
+    }
+
+    fn get_by_hostname(&self, hostname: &str) -> Option<NetworkPrinterHandle> {
+        self.backends
+            .iter()
+            .find_map(|backend| backend.get_by_hostname(hostname))
+    }
+
+    fn get_by_ip(&self, ip: std::net::IpAddr) -> Option<NetworkPrinterHandle> {
+        self.backends.iter().find_map(|backend| backend.get_by_ip(ip))
+    }
+
+    fn subscribe(&self) -> tokio::sync::broadcast::Receiver<DiscoveryEvent> {
+        let (events, receiver) = tokio::sync::broadcast::channel(EVENT_CAPACITY);
+
+        let mut backend_events = futures::stream::select_all(self.backends.iter().map(|backend| {
+            Box::pin(futures::stream::unfold(
+                backend.subscribe(),
+                |mut backend_events| async move {
+                    loop {
+                        match backend_events.recv().await {
+                            Ok(event) => return Some((event, backend_events)),
+                            Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+                            Err(tokio::sync::broadcast::error::RecvError::Closed) => return None,
+                        }
+                    }
+                },
+            ))
+        }));
+
+        tokio::spawn(async move {
+            let mut check = tokio::time::interval(SUBSCRIBER_CHECK_INTERVAL);
+            loop {
+                tokio::select! {
+                    event = backend_events.next() => {
+                        // Stop once every backend is gone, or nobody is listening.
+                        let Some(event) = event else {
+                            return;
+                        };
+                        if events.send(event).is_err() {
+                            return;
+                        }
+                    }
+                    // Backends can go a long time without an event, so don't wait for one to
+                    // notice the receiver was dropped.
+                    _ = check.tick() => {
+                        if events.receiver_count() == 0 {
+                            return;
+                        }
+                    }
+                }
+            }
+        });
+
+        receiver
+    }
+
+    fn start_liveness_checks(&self, interval: std::time::Duration) -> Result<()> {
+        for backend in &self.backends {
+            backend.start_liveness_checks(interval)?;
+        }
+
+        Ok(())
+    }
+
+    fn list_rejected(&self) -> Result<Vec<RejectedPrinter>> {
+        let mut rejected = Vec::new();
+        for backend in &self.backends {
+            rejected.extend(backend.list_rejected()?);
+        }
+
+        Ok(rejected)
+    }
+
+    fn reload_config(&self, config: &crate::config::Config) -> Result<()> {
+        for backend in &self.backends {
+            backend.reload_config(config)?;
+        }
+
+        Ok(())
+    }
+
+    async fn shutdown(&self) {
+        futures::future::join_all(self.backends.iter().map(|backend| backend.shutdown())).await;
+    }
+
+    /// Restore the printer with the first backend that handles it.
+    async fn restore(&self, info: &NetworkPrinterInfo) -> Result<bool> {
+        for backend in &self.backends {
+            if backend.restore(info).await? {
+                return Ok(true);
+            }
+        }
+
+        Ok(false)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::network_printer::bambu::Bambu;
+
+    fn bambu(printers: &[(&str, &str)]) -> Bambu {
+        let bambu = Bambu::new(&crate::network_printer::bambu::test_config("workshop-x1c", ""));
+        for (ip, serial) in printers {
+            let client = bambulabs::client::Client::new(*ip, "12345678", *serial).unwrap();
+            bambu
+                .add_connected(
+                    ip.parse().unwrap(),
+                    None,
+                    serial.to_string(),
+                    "workshop-x1c".to_string(),
+                    crate::network_printer::bambu_common::BambuModel::X1Carbon,
+                    client,
+                    None,
+                )
+                .unwrap();
+        }
+
+        bambu
+    }
+
+    #[tokio::test]
+    async fn test_printer_manager_dedups_by_serial() {
+        let manager = PrinterManager::new(vec![
+            Box::new(bambu(&[("192.0.2.10", "00M09A350100123")])),
+            // Found again by a second backend, e.g. over mDNS.
+            Box::new(bambu(&[
+                ("192.0.2.10", "00M09A350100123"),
+                ("192.0.2.20", "00M09A350100456"),
+            ])),
+        ]);
+
+        let mut serials: Vec<_> = manager
+            .list()
+            .unwrap()
+            .into_iter()
+            .filter_map(|info| info.serial)
+            .collect();
+        serials.sort();
+        assert_eq!(serials, ["00M09A350100123", "00M09A350100456"]);
+
+        let handle = manager.get("00M09A350100456").unwrap();
+        assert_eq!(handle.info.ip.to_string(), "192.0.2.20");
+        assert!(manager.get("00M09A350100789").is_none());
+
+        let handle = manager.get_by_hostname("workshop-x1c").unwrap();
+        assert_eq!(handle.info.serial.as_deref(), Some("00M09A350100123"));
+        assert!(manager.get_by_hostname("garage-x1c").is_none());
+
+        let handle = manager.get_by_ip("192.0.2.20".parse().unwrap()).unwrap();
+        assert_eq!(handle.info.serial.as_deref(), Some("00M09A350100456"));
+        assert!(manager.get_by_ip("192.0.2.30".parse().unwrap()).is_none());
+    }
+
+    #[test]
+    fn test_printer_key() {
+        let moonraker = |ip: &str, port| NetworkPrinterInfo {
+            hostname: None,
+            ip: ip.parse().unwrap(),
+            port,
+            manufacturer: crate::network_printer::NetworkPrinterManufacturer::Klipper,
+            model: None,
+            serial: None,
+        };
+
+        // Instances on one host are told apart by port.
+        assert_ne!(
+            printer_key(&moonraker("192.0.2.30", Some(7125))),
+            printer_key(&moonraker("192.0.2.30", Some(7126)))
+        );
+        assert_eq!(printer_key(&moonraker("::1", Some(7125))), "Klipper [::1]:7125");
+        assert_eq!(printer_key(&moonraker("192.0.2.30", None)), "Klipper 192.0.2.30");
+
+        // Serials are only unique to a make.
+        let bambu = NetworkPrinterInfo {
+            manufacturer: crate::network_printer::NetworkPrinterManufacturer::Bambu,
+            serial: Some("00M09A350100123".to_string()),
+            ..moonraker("192.0.2.10", None)
+        };
+        let prusa = NetworkPrinterInfo {
+            manufacturer: crate::network_printer::NetworkPrinterManufacturer::Prusa,
+            ..bambu.clone()
+        };
+        assert_ne!(printer_key(&bambu), printer_key(&prusa));
+        assert_eq!(
+            printer_key(&bambu),
+            printer_key(&NetworkPrinterInfo {
+                ip: "192.0.2.11".parse().unwrap(),
+                ..bambu.clone()
+            })
+        );
+    }
+
+    /// A backend without printers, that sends the discovery events it's given.
+    struct EventsBackend {
+        events: tokio::sync::broadcast::Sender<DiscoveryEvent>,
+    }
+
+    #[async_trait::async_trait]
+    impl NetworkPrinters for EventsBackend {
+        async fn discover(&self) -> Result<()> {
+            Ok(())
+        }
+
+        fn list(&self) -> Result<Vec<NetworkPrinterInfo>> {
+            Ok(vec![])
+        }
+
+        fn list_handles(&self) -> Result<Vec<NetworkPrinterHandle>> {
+            Ok(vec![])
+        }
+
+        fn subscribe(&self) -> tokio::sync::broadcast::Receiver<DiscoveryEvent> {
+            self.events.subscribe()
+        }
+    }
+
+    #[tokio::test]
+    async fn test_subscribe_stops_forwarding() {
+        let events = tokio::sync::broadcast::channel(8).0;
+        let manager = PrinterManager::new(vec![Box::new(EventsBackend { events: events.clone() })]);
+
+        let mut receiver = manager.subscribe();
+        events
+            .send(DiscoveryEvent::Lost("00M09A350100123".to_string()))
+            .unwrap();
+        assert!(matches!(receiver.recv().await, Ok(DiscoveryEvent::Lost(serial)) if serial == "00M09A350100123"));
+
+        // The backend has nothing more to say, but forwarding still stops once nobody listens.
+        drop(receiver);
+        tokio::time::timeout(SUBSCRIBER_CHECK_INTERVAL * 3, async {
+            while events.receiver_count() > 0 {
+                tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+            }
+        })
+        .await
+        .expect("still forwarding events after the receiver was dropped");
+    }
+
+    #[tokio::test]
+    async fn test_restart_client() {
+        let manager = PrinterManager::new(vec![Box::new(bambu(&[("192.0.2.10", "00M09A350100123")]))]);
+
+        manager.restart_client("00M09A350100123").unwrap();
+        assert!(manager.restart_client("00M09A350100789").is_err());
+    }
+
+    #[tokio::test]
+    async fn test_printer_cache() {
+        let dir = tempdir::TempDir::new("cache").unwrap();
+        let path = dir.path().join("printers.json");
+
+        // Nothing has been cached yet.
+        let empty = PrinterManager::new(vec![Box::new(bambu(&[]))]);
+        empty.load_cache(&path).await.unwrap();
+        assert!(empty.list().unwrap().is_empty());
+
+        let manager = PrinterManager::new(vec![Box::new(bambu(&[("192.0.2.10", "00M09A350100123")]))]);
+        manager.save_cache(&path).await.unwrap();
+
+        let cached: Vec<NetworkPrinterInfo> = serde_json::from_str(&std::fs::read_to_string(&path).unwrap()).unwrap();
+        assert_eq!(cached, manager.list().unwrap());
+
+        // The printer isn't really there, so it's dropped rather than restored.
+        let restarted = PrinterManager::new(vec![Box::new(bambu(&[]))]);
+        restarted.load_cache(&path).await.unwrap();
+        assert!(restarted.list().unwrap().is_empty());
+        restarted.save_cache(&path).await.unwrap();
+        assert_eq!(std::fs::read_to_string(&path).unwrap(), "[]");
+    }
+}
@@ -0,0 +1,182 @@
+//! mDNS discovery of Bambu printers for the [`crate::network_printer::NetworkPrinters`] trait.
+//! Bambu printers in LAN only mode advertise themselves over mDNS as well as the SSDP broadcasts,
+//! which helps on networks that filter the broadcasts.
+
+use std::{net::IpAddr, sync::Arc};
+
+use anyhow::Result;
+use futures_util::{pin_mut, stream::StreamExt};
+use tracing::Instrument;
+
+use crate::network_printer::{
+    bambu::{Bambu, BAMBU_X1_CARBON_URN},
+    bambu_common::DiscoveredBambu,
+    NetworkPrinterHandle, NetworkPrinterInfo, NetworkPrinters,
+};
+
+/// The service Bambu printers advertise.
+const SERVICE_NAME: &str = "_bambulab._tcp.local";
+
+/// Bambu printer backend discovering printers over mDNS.
+/// Printers are added to the [`Bambu`] backend given, so they're shared with its SSDP
+/// discovery.
+pub struct BambuMdns {
+    bambu: Arc<Bambu>,
+    with_ssdp: bool,
+}
+
+impl BambuMdns {
+    /// Create a new mDNS backend adding printers to `bambu`.
+    /// If `with_ssdp` is set, the SSDP discovery of `bambu` is run alongside the mDNS discovery.
+    pub fn new(bambu: Arc<Bambu>, with_ssdp: bool) -> Self {
+        Self { bambu, with_ssdp }
+    }
+
+    /// Browse for printers over mDNS.
+    async fn browse(&self) -> Result<()> {
+        tracing::info!("Spawning Bambu mDNS discovery task");
+
+        let stream = mdns::discover::all(SERVICE_NAME, std::time::Duration::from_secs(15))?.listen();
+        pin_mut!(stream);
+
+        while let Some(Ok(response)) = stream.next().await {
+            let span = tracing::info_span!("discovery_packet", ip = ?response.ip_addr());
+            let discovered = span.in_scope(|| {
+                parse_txt_records(
+                    response.txt_records(),
+                    response.ip_addr(),
+                    response.port(),
+                    response.hostname(),
+                )
+            });
+
+            if let Err(e) = self.bambu.add_discovered(discovered).instrument(span).await {
+                tracing::warn!("Failed to add Bambu printer found over mDNS: {:#}", e);
+            }
+        }
+
+        anyhow::bail!("Bambu mDNS discovery ended unexpectedly");
+    }
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinters for BambuMdns {
+    async fn discover(&self) -> Result<()> {
+        if self.with_ssdp {
+            // Either failing leaves the other running.
+            let (ssdp, mdns) = tokio::join!(self.bambu.discover(), self.browse());
+            ssdp.and(mdns)
+        } else {
+            self.browse().await
+        }
+    }
+
+    fn list(&self) -> Result<Vec<NetworkPrinterInfo>> {
+        self.bambu.list()
+    }
+
+    fn list_handles(&self) -> Result<Vec<NetworkPrinterHandle>> {
+        self.bambu.list_handles()
+    }
+
+    fn get_by_serial(&self, serial: &str) -> Option<NetworkPrinterHandle> {
+        self.bambu.get_by_serial(serial)
+    }
+
+    fn get_by_hostname(&self, hostname: &str) -> Option<NetworkPrinterHandle> {
+        self.bambu.get_by_hostname(hostname)
+    }
+
+    fn subscribe(&self) -> tokio::sync::broadcast::Receiver<crate::network_printer::DiscoveryEvent> {
+        self.bambu.subscribe()
+    }
+
+    fn start_liveness_checks(&self, interval: std::time::Duration) -> Result<()> {
+        self.bambu.start_liveness_checks(interval)
+    }
+
+    fn list_rejected(&self) -> Result<Vec<crate::network_printer::RejectedPrinter>> {
+        self.bambu.list_rejected()
+    }
+
+    fn reload_config(&self, config: &crate::config::Config) -> Result<()> {
+        NetworkPrinters::reload_config(self.bambu.as_ref(), config)
+    }
+
+    async fn restore(&self, info: &NetworkPrinterInfo) -> Result<bool> {
+        self.bambu.restore(info).await
+    }
+
+    async fn shutdown(&self) {
+        self.bambu.shutdown().await
+    }
+}
+
+/// Read a printer's details out of its mDNS TXT records, which are `key=value` pairs.
+fn parse_txt_records<'a>(
+    records: impl Iterator<Item = &'a str>,
+    ip: Option<IpAddr>,
+    port: Option<u16>,
+    hostname: Option<&str>,
+) -> DiscoveredBambu {
+    let mut discovered = DiscoveredBambu {
+        // The service only covers Bambu printers, which all advertise the same URN over SSDP.
+        urn: Some(BAMBU_X1_CARBON_URN.to_string()),
+        ip,
+        port,
+        ..Default::default()
+    };
+
+    for record in records {
+        let Some((key, value)) = record.split_once('=') else {
+            continue;
+        };
+
+        match key.trim().to_lowercase().as_str() {
+            "sn" | "serial" | "dev_id" => discovered.serial = Some(value.trim().to_owned()),
+            "model" | "dev_model" => discovered.model_code = Some(value.trim().to_owned()),
+            "name" | "dev_name" => discovered.name = Some(value.trim().to_owned()),
+            _ => (),
+        }
+    }
+
+    // Fall back to the hostname for printers that don't put their name in the TXT records.
+    if discovered.name.is_none() {
+        discovered.name = hostname.map(|hostname| hostname.trim_end_matches(".local").to_owned());
+    }
+
+    discovered
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_txt_records() {
+        let discovered = parse_txt_records(
+            [
+                "sn=00M09A350100123",
+                "dev_model=3DPrinter-X1-Carbon",
+                "dev_name=workshop-x1c",
+                "junk",
+            ]
+            .into_iter(),
+            Some("192.168.1.42".parse().unwrap()),
+            Some(8883),
+            Some("bambu.local"),
+        );
+
+        assert_eq!(
+            discovered,
+            DiscoveredBambu {
+                urn: Some(BAMBU_X1_CARBON_URN.to_string()),
+                model_code: Some("3DPrinter-X1-Carbon".to_string()),
+                name: Some("workshop-x1c".to_string()),
+                ip: Some("192.168.1.42".parse().unwrap()),
+                serial: Some("00M09A350100123".to_string()),
+                port: Some(8883),
+            }
+        );
+    }
+}
@@ -1,7 +1,17 @@
 //! A trait for a printer on a network.
 
+pub mod bambu;
+pub mod bambu_common;
+#[cfg(test)]
+mod bambu_discovery_tests;
+#[cfg(feature = "bambu-x1-carbon")]
 pub mod bambu_x1_carbon;
+pub mod elegoo;
 pub mod formlabs;
+pub mod manager;
+pub mod mdns;
+pub mod moonraker;
+pub mod prusalink;
 
 use std::{fmt::Debug, net::IpAddr, sync::Arc};
 
@@ -9,6 +19,96 @@ use anyhow::Result;
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
+/// A health management system (HMS) error reported by a printer.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq, Hash)]
+pub struct HmsError {
+    /// The module and severity of the error.
+    pub attr: u32,
+    /// The error code.
+    pub code: u32,
+}
+
+/// The descriptions of common HMS errors, by their full code.
+/// The rest are described on the Bambu Lab wiki.
+const HMS_DESCRIPTIONS: &[(&str, &str)] = &[
+    (
+        "0300_0300_0001_0001",
+        "The hotend cooling fan speed is too slow or stopped.",
+    ),
+    (
+        "0300_0400_0002_0001",
+        "The part cooling fan speed is too slow or stopped.",
+    ),
+    ("0700_2000_0002_0001", "AMS A slot 1 has run out of filament."),
+    ("0C00_0300_0003_0008", "Possible spaghetti defects were detected."),
+];
+
+impl HmsError {
+    /// The full code of the error, as Bambu Lab writes it, e.g. `0300_0400_0002_0001`.
+    pub fn full_code(&self) -> String {
+        format!(
+            "{:04X}_{:04X}_{:04X}_{:04X}",
+            self.attr >> 16,
+            self.attr & 0xFFFF,
+            self.code >> 16,
+            self.code & 0xFFFF
+        )
+    }
+
+    /// How serious the error is, from the high half of the code.
+    pub fn severity(&self) -> HmsSeverity {
+        match self.code >> 16 {
+            1 => HmsSeverity::Fatal,
+            2 => HmsSeverity::Serious,
+            3 => HmsSeverity::Common,
+            4 => HmsSeverity::Info,
+            _ => HmsSeverity::Unknown,
+        }
+    }
+
+    /// Decode the error for people to read.
+    pub fn diagnostic(&self) -> HmsDiagnostic {
+        let code = self.full_code();
+        let description = HMS_DESCRIPTIONS
+            .iter()
+            .find(|(known, _)| *known == code)
+            .map(|(_, description)| description.to_string());
+
+        HmsDiagnostic {
+            code,
+            severity: self.severity(),
+            description,
+        }
+    }
+}
+
+/// How serious an HMS error is.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum HmsSeverity {
+    /// The printer can't carry on.
+    Fatal,
+    /// The print has been paused until the problem is fixed.
+    Serious,
+    /// Needs attention, but the print carries on.
+    Common,
+    /// For information only.
+    Info,
+    /// The printer reported a severity we don't know about.
+    Unknown,
+}
+
+/// An HMS error decoded for people to read.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct HmsDiagnostic {
+    /// The full code of the error, e.g. `0300_0400_0002_0001`.
+    pub code: String,
+    /// How serious the error is.
+    pub severity: HmsSeverity,
+    /// What the error means, for the errors we have a description of.
+    pub description: Option<String>,
+}
+
 /// A network printers interface.
 #[async_trait::async_trait]
 pub trait NetworkPrinters: Send + Sync {
@@ -22,14 +122,495 @@ pub trait NetworkPrinters: Send + Sync {
 
     /// List all printer handles found on the network.
     fn list_handles(&self) -> Result<Vec<NetworkPrinterHandle>>;
+
+    /// List the printers found on the network that match the filter.
+    fn list_filtered(&self, filter: &PrinterFilter) -> Result<Vec<NetworkPrinterInfo>> {
+        Ok(self
+            .list_handles()?
+            .into_iter()
+            .filter(|handle| filter.matches(&handle.info) && (!filter.online_only || handle.client.is_ready()))
+            .map(|handle| handle.info)
+            .collect())
+    }
+
+    /// Get the handle of the printer with the given serial number, if it has been found.
+    fn get_by_serial(&self, serial: &str) -> Option<NetworkPrinterHandle> {
+        self.list_handles()
+            .ok()?
+            .into_iter()
+            .find(|handle| handle.info.serial.as_deref() == Some(serial))
+    }
+
+    /// Get the handle of the printer with the given hostname, if it has been found.
+    fn get_by_hostname(&self, hostname: &str) -> Option<NetworkPrinterHandle> {
+        self.list_handles()
+            .ok()?
+            .into_iter()
+            .find(|handle| handle.info.hostname.as_deref() == Some(hostname))
+    }
+
+    /// Get the handle of the printer at the given IP address, if it has been found.
+    fn get_by_ip(&self, ip: IpAddr) -> Option<NetworkPrinterHandle> {
+        self.list_handles()
+            .ok()?
+            .into_iter()
+            .find(|handle| handle.info.ip == ip)
+    }
+
+    /// Subscribe to events for printers being found.
+    /// Backends that don't emit events return a receiver that is already closed.
+    fn subscribe(&self) -> tokio::sync::broadcast::Receiver<DiscoveryEvent> {
+        tokio::sync::broadcast::channel(1).1
+    }
+
+    /// Start a background task removing printers that stop reporting, checking every `interval`.
+    /// Calling it again replaces the task, and `shutdown` stops it. Backends without a connection to their printers can't tell when they go away, and do
+    /// nothing.
+    fn start_liveness_checks(&self, _interval: std::time::Duration) -> Result<()> {
+        Ok(())
+    }
+
+    /// List the devices seen on the network that were not added as printers.
+    fn list_rejected(&self) -> Result<Vec<RejectedPrinter>> {
+        Ok(Vec::new())
+    }
+
+    /// Use a new config for printers found from now on.
+    /// Backends without any config of their own ignore it.
+    fn reload_config(&self, _config: &crate::config::Config) -> Result<()> {
+        Ok(())
+    }
+
+    /// Reconnect to a printer found by an earlier run, without waiting for it to be discovered.
+    /// Returns `false` if the printer isn't one this backend handles, and an error if it is but
+    /// could not be reached.
+    async fn restore(&self, _info: &NetworkPrinterInfo) -> Result<bool> {
+        Ok(false)
+    }
+
+    /// Close the connections to the printers found and forget them, waiting for the
+    /// connections to close.
+    async fn shutdown(&self) {}
+
+    /// Discover printers on the network until the duration has passed or discovery is cancelled.
+    /// Returns the printers found so far.
+    async fn discover_for(
+        &self,
+        duration: std::time::Duration,
+        cancel: tokio_util::sync::CancellationToken,
+    ) -> Result<Vec<NetworkPrinterInfo>> {
+        // Discovery runs until it's stopped, we only care about what it found in the meantime.
+        tokio::select! {
+            result = self.discover() => result?,
+            _ = tokio::time::sleep(duration) => (),
+            _ = cancel.cancelled() => (),
+        }
+
+        self.list()
+    }
+
+    /// Discover printers on the network for the given duration.
+    /// Returns the printers found and the devices that were seen but rejected.
+    async fn discover_diagnostic(&self, timeout: std::time::Duration) -> Result<DiscoveryReport> {
+        let printers = self.discover_for(timeout, Default::default()).await?;
+
+        Ok(DiscoveryReport {
+            printers,
+            rejected: self.list_rejected()?,
+        })
+    }
+
+    /// Discover printers on the network for the given duration, and return the ones that are
+    /// connected and ready to use by the end of it.
+    async fn discover_and_connect(&self, timeout: std::time::Duration) -> Result<Vec<NetworkPrinterHandle>> {
+        // This gives the printers the whole timeout to be found and to connect.
+        self.discover_for(timeout, Default::default()).await?;
+
+        Ok(self
+            .list_handles()?
+            .into_iter()
+            .filter(|handle| handle.client.is_ready())
+            .collect())
+    }
+
+    /// Print the same file on every printer matching the filter, at the same time.
+    /// The file is sliced once, by the first matching printer, so the printers should share a
+    /// slicer config. Returns the result for each printer, keyed by serial number (or IP if the
+    /// printer has no serial).
+    async fn print_to_all(
+        &self,
+        job_name: &str,
+        file: &std::path::Path,
+        filter: &(dyn for<'a> Fn(&'a NetworkPrinterInfo) -> bool + Send + Sync),
+    ) -> Result<Vec<(String, Result<Message>)>> {
+        let handles: Vec<NetworkPrinterHandle> = self
+            .list_handles()?
+            .into_iter()
+            .filter(|handle| filter(&handle.info))
+            .collect();
+
+        let Some(first) = handles.first() else {
+            return Ok(Vec::new());
+        };
+
+        let sliced = crate::slicer::SlicedOutput::new(first.client.slice(file).await?);
+
+        let prints = handles.iter().map(|handle| {
+            let sliced = sliced.path();
+            async move {
+                let key = handle.info.serial.clone().unwrap_or_else(|| handle.info.ip.to_string());
+
+                (key, handle.client.print(job_name, sliced).await)
+            }
+        });
+
+        Ok(futures::future::join_all(prints).await)
+    }
+}
+
+/// Which printers to list.
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PrinterFilter {
+    /// Only printers made by this manufacturer.
+    pub manufacturer: Option<NetworkPrinterManufacturer>,
+    /// Only printers whose model contains this, ignoring case.
+    pub model_contains: Option<String>,
+    /// Only printers that are connected and have reported their status.
+    pub online_only: bool,
+}
+
+impl PrinterFilter {
+    /// Check if a printer's details match the filter.
+    /// Whether the printer is online can't be told from its details, so that is not checked.
+    pub fn matches(&self, info: &NetworkPrinterInfo) -> bool {
+        if self
+            .manufacturer
+            .as_ref()
+            .is_some_and(|manufacturer| *manufacturer != info.manufacturer)
+        {
+            return false;
+        }
+
+        match &self.model_contains {
+            Some(model_contains) => info
+                .model
+                .as_ref()
+                .is_some_and(|model| model.to_lowercase().contains(&model_contains.to_lowercase())),
+            None => true,
+        }
+    }
+}
+
+/// An event from discovering printers on the network.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "type", content = "printer")]
+pub enum DiscoveryEvent {
+    /// A new printer was found.
+    Found(NetworkPrinterInfo),
+    /// The details of a known printer changed, e.g. its IP address.
+    Updated(NetworkPrinterInfo),
+    /// A printer stopped reporting and was removed. Contains the serial number of the printer.
+    Lost(String),
+}
+
+/// The result of discovering printers on the network.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+pub struct DiscoveryReport {
+    /// The printers that were found.
+    pub printers: Vec<NetworkPrinterInfo>,
+    /// The devices that were seen but not added as printers.
+    pub rejected: Vec<RejectedPrinter>,
+}
+
+/// A device seen during discovery that was not added as a printer.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+pub struct RejectedPrinter {
+    /// The IP address of the device.
+    pub ip: Option<IpAddr>,
+    /// The serial number of the device.
+    pub serial: Option<String>,
+    /// Why the device was rejected.
+    pub reason: RejectionReason,
+}
+
+/// The reason a device seen during discovery was not added as a printer.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case", tag = "type")]
+pub enum RejectionReason {
+    /// The device did not advertise an IP address.
+    NoIp,
+    /// The device is not a printer we support.
+    UnknownUrn {
+        /// The URN the device advertised.
+        urn: Option<String>,
+    },
+    /// The device did not advertise a serial number.
+    NoSerial,
+    /// The device did not advertise a name.
+    NoName,
+    /// There is no configuration for the printer.
+    NoConfig {
+        /// The name of the printer.
+        name: String,
+    },
+    /// The printer refused the access code.
+    AuthFailed {
+        /// The name of the printer.
+        name: String,
+    },
+    /// Connecting to the printer failed, or it never reported.
+    ConnectFailed {
+        /// The name of the printer.
+        name: String,
+        /// What went wrong.
+        error: String,
+    },
+}
+
+impl RejectionReason {
+    /// A short, stable name for the reason, used as a field in log events.
+    pub fn as_str(&self) -> &'static str {
+        match self {
+            RejectionReason::NoIp => "no_ip",
+            RejectionReason::UnknownUrn { .. } => "unknown_urn",
+            RejectionReason::NoSerial => "no_serial",
+            RejectionReason::NoName => "no_name",
+            RejectionReason::NoConfig { .. } => "no_config",
+            RejectionReason::AuthFailed { .. } => "auth_failed",
+            RejectionReason::ConnectFailed { .. } => "connect_failed",
+        }
+    }
+}
+
+/// The temperatures of a printer, in Celsius.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq)]
+pub struct Temperatures {
+    /// The nozzle temperature.
+    pub nozzle: f64,
+    /// The target nozzle temperature.
+    pub nozzle_target: f64,
+    /// The bed temperature.
+    pub bed: f64,
+    /// The target bed temperature.
+    pub bed_target: f64,
+    /// The chamber temperature, if the printer has a chamber sensor.
+    pub chamber: Option<f64>,
+}
+
+/// The progress of a print.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PrintProgress {
+    /// How much of the print is done, from 0 to 100.
+    pub percent: u8,
+    /// The layer being printed.
+    pub current_layer: Option<u32>,
+    /// The number of layers in the print.
+    pub total_layers: Option<u32>,
+    /// The estimated time left in seconds.
+    pub remaining_secs: Option<u64>,
+    /// The speed profile the print is running at.
+    pub speed_profile: Option<SpeedProfile>,
+}
+
+/// How a job on a printer ended.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JobResult {
+    /// The name of the job.
+    pub job_name: String,
+    /// If the job finished, rather than failing or being cancelled.
+    pub success: bool,
+    /// How long the job ran for.
+    pub duration: std::time::Duration,
+}
+
+/// A print started on a printer, used to follow or cancel that specific job later.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct JobHandle {
+    /// The name the job was started with.
+    pub job_name: String,
+    /// The serial number of the printer running the job.
+    pub serial: String,
+    /// When the job was started.
+    pub started_at: std::time::SystemTime,
+}
+
+/// A slot of an automatic material system (AMS).
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct AmsSlot {
+    /// The index of the AMS unit the slot is in.
+    pub index: u8,
+    /// The index of the slot in the AMS unit.
+    pub tray_id: u8,
+    /// The material loaded, e.g. `PLA`.
+    pub material: Option<String>,
+    /// The color of the filament loaded, as RRGGBBAA hex.
+    pub color_hex: Option<String>,
+    /// How much of the spool is left, from 0 to 100.
+    pub remaining_percent: Option<u8>,
+}
+
+/// A fan of a printer.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FanKind {
+    /// The part cooling fan on the toolhead.
+    Part,
+    /// The auxiliary part cooling fan.
+    Aux,
+    /// The chamber fan.
+    Chamber,
+}
+
+/// A speed profile of a print, trading quality for speed.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum SpeedProfile {
+    /// The slowest and quietest profile.
+    Silent,
+    /// The default profile.
+    Standard,
+    /// Faster than standard.
+    Sport,
+    /// The fastest profile.
+    Ludicrous,
+}
+
+/// A calibration a printer can run.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum CalibrationKind {
+    /// Level the bed.
+    BedLevel,
+    /// Compensate for the vibration of the frame.
+    VibrationComp,
+    /// Calibrate the flow rate of the extruder.
+    FlowRate,
+    /// Every calibration the printer supports.
+    Full,
+}
+
+/// An axis of a printer.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub enum Axis {
+    /// The X axis.
+    X,
+    /// The Y axis.
+    Y,
+    /// The Z axis.
+    Z,
+    /// The extruder.
+    E,
+}
+
+/// The result of inspecting the first layer of a print.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum FirstLayerResult {
+    /// The first layer was inspected and looked good.
+    Ok,
+    /// The first layer was inspected and a problem was found. The print is paused.
+    Flagged,
+    /// The first layer was not inspected.
+    NotChecked,
+}
+
+/// What a printer supports, so callers can hide controls rather than finding out from an
+/// `Unsupported` error.
+#[derive(Debug, Clone, Copy, Default, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PrinterCapabilities {
+    /// The nozzle temperature can be set.
+    pub set_temperature: bool,
+    /// The printer reports its chamber temperature.
+    pub chamber_temperature: bool,
+    /// The fan speeds can be set.
+    pub fan_control: bool,
+    /// The printer has a camera we can stream from.
+    pub has_camera: bool,
+    /// The printer can feed filament from an AMS.
+    pub ams: bool,
+    /// The axes can be homed and moved.
+    pub move_axis: bool,
+    /// The light can be switched on and off.
+    pub led: bool,
+    /// Files can be sent to the printer to print.
+    pub print: bool,
+    /// Raw gcode can be sent to the printer.
+    pub send_gcode: bool,
+    /// Objects of the current plate can be skipped mid-print.
+    pub skip_objects: bool,
 }
 
 /// A network printers interface.
 #[async_trait::async_trait]
 pub trait NetworkPrinter: Send + Sync {
+    /// Get when the printer last reported in.
+    /// Returns `None` for printers that we don't hold a connection to.
+    fn last_seen(&self) -> Option<std::time::Instant> {
+        None
+    }
+
+    /// Check if the printer is connected and has reported its status, so calls like `status`
+    /// will work. Printers that don't hold a connection are always ready.
+    fn is_ready(&self) -> bool {
+        true
+    }
+
+    /// Check the printer is reachable and we're authenticated with it, without fetching its full
+    /// status. Returns `false` rather than an error if it isn't.
+    async fn ping(&self) -> Result<bool> {
+        Ok(self.is_ready())
+    }
+
+    /// Get what the printer supports.
+    /// Backends that don't say support nothing beyond the required methods.
+    fn capabilities(&self) -> PrinterCapabilities {
+        PrinterCapabilities::default()
+    }
+
     /// Get the status of a printer.
     async fn status(&self) -> Result<Message>;
 
+    /// Get the temperatures of the printer.
+    async fn get_temperatures(&self) -> Result<Temperatures> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Get the progress of the current print.
+    /// Fails if nothing is printing.
+    async fn progress(&self) -> Result<PrintProgress> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Get the progress of a job started with `start_job`.
+    /// Fails if the printer has moved on to a different job.
+    async fn job_progress(&self, job: &JobHandle) -> Result<PrintProgress> {
+        ensure_current_job(self, job).await?;
+        self.progress().await
+    }
+
+    /// Cancel a job started with `start_job`.
+    /// Fails rather than stopping whatever else is printing if the printer has moved on to a
+    /// different job.
+    async fn cancel_job(&self, job: &JobHandle) -> Result<Message> {
+        ensure_current_job(self, job).await?;
+        self.stop().await
+    }
+
+    /// Get a stream of the status of the printer, as the printer reports it.
+    /// A consumer that falls behind skips the oldest statuses rather than slowing the printer's
+    /// connection down. Printers that don't push their status return an empty stream.
+    fn status_stream(&self) -> futures::stream::BoxStream<'static, Message> {
+        Box::pin(futures::stream::empty())
+    }
+
+    /// Get a stream of the jobs that end, whether they finished or failed.
+    /// Only jobs seen running after the stream was created are included, and their duration is
+    /// measured from when they were first seen. Printers that don't push their status return an
+    /// empty stream.
+    fn on_job_complete(&self) -> futures::stream::BoxStream<'static, JobResult> {
+        Box::pin(futures::stream::empty())
+    }
+
     /// Get the version of the printer.
     async fn version(&self) -> Result<Message>;
 
@@ -42,12 +623,156 @@ pub trait NetworkPrinter: Send + Sync {
     /// Stop the current print.
     async fn stop(&self) -> Result<Message>;
 
+    /// Immediately halt the printer, disabling the steppers and heaters.
+    /// Unlike `stop`, this does not wait for the print to be cancelled gracefully.
+    async fn emergency_stop(&self) -> Result<Message>;
+
     /// Set the led on or off.
     async fn set_led(&self, on: bool) -> Result<Message>;
 
+    /// Reboot the printer. This kills any active print, so it is refused unless the printer
+    /// is idle or `force` is set.
+    async fn reboot(&self, _force: bool) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Reconnect to the printer with a fresh client, for a connection that has stopped getting
+    /// reports without dropping.
+    fn restart_client(&self) -> Result<()> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Close the connection to the printer, waiting for it to close. The printer can't be used
+    /// afterwards.
+    async fn shutdown(&self) {}
+
+    /// Set the target nozzle temperature in celsius.
+    async fn set_nozzle_temperature(&self, _celsius: u16) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Set the speed of a fan, from 0 to 100 percent.
+    async fn set_fan_speed(&self, _fan: FanKind, _percent: u8) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Home all axes.
+    /// Fails if the printer is in the middle of a print.
+    async fn home(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Move an axis by `mm`, relative to where it is, at `feedrate` mm/min.
+    /// Fails if the printer is in the middle of a print.
+    async fn move_axis(&self, _axis: Axis, _mm: f64, _feedrate: Option<f64>) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Send a raw gcode command, for anything there isn't a method for.
+    /// Only printers with the `send_gcode` capability support it.
+    async fn send_gcode(&self, _line: &str) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Stop printing some objects of the current plate, leaving the rest to finish.
+    /// Fails if no objects are given or nothing is printing.
+    async fn skip_objects(&self, _object_ids: &[u32]) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Change the speed profile of the current print.
+    /// Fails if nothing is printing.
+    async fn set_speed_profile(&self, _profile: SpeedProfile) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Start a calibration. Fails if the printer is in the middle of a print.
+    /// Returns once the printer has accepted the command, not once the calibration is done, which
+    /// can take several minutes; follow it with `calibrating` in the status.
+    async fn calibrate(&self, _kind: CalibrationKind) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Get the objects on the plate of the current job, for picking which to skip.
+    /// Empty when no job is loaded, or the printer doesn't report them. Some printers only know
+    /// every object of jobs started from here, and otherwise only those already skipped.
+    fn plate_objects(&self) -> Result<Vec<PlateObject>> {
+        Ok(vec![])
+    }
+
     /// Get the accessories.
     async fn accessories(&self) -> Result<Message>;
 
+    /// Get the URL of the printer's camera stream.
+    /// Returns `None` if the printer has no camera we can stream from.
+    fn camera_url(&self) -> Result<Option<String>> {
+        Ok(None)
+    }
+
+    /// Get the recommended (nozzle, bed) temperatures in celsius for the filament loaded in a slot.
+    /// Returns `None` if the slot is empty or the filament has no recommendation.
+    fn recommended_temps(&self, _slot: u8) -> Result<Option<(u16, u16)>> {
+        Ok(None)
+    }
+
+    /// Get the filament loaded in each AMS slot.
+    /// Printers without an AMS have no slots.
+    fn ams_slots(&self) -> Result<Vec<AmsSlot>> {
+        Ok(Vec::new())
+    }
+
+    /// Get the AMS slot feeding the nozzle, as `(ams_index, slot_index)`.
+    /// Returns `None` if the printer is idle or the filament isn't coming from an AMS.
+    fn active_ams_slot(&self) -> Result<Option<(u8, u8)>> {
+        Ok(None)
+    }
+
+    /// Get the estimated time the current print will complete.
+    /// Returns `None` if nothing is printing.
+    fn estimated_completion(&self) -> Result<Option<std::time::SystemTime>> {
+        Ok(None)
+    }
+
+    /// Get the line of the sliced gcode the printer is currently executing.
+    /// Returns `None` if the printer doesn't report it.
+    fn current_gcode_line(&self) -> Result<Option<u64>> {
+        Ok(None)
+    }
+
+    /// Get the result of the first layer inspection of the current print.
+    /// Returns `None` if nothing is printing or the first layer hasn't been inspected yet.
+    fn first_layer_result(&self) -> Result<Option<FirstLayerResult>> {
+        Ok(None)
+    }
+
+    /// Get the health errors the printer is reporting.
+    /// Errors cleared with `clear_errors` are left out, even if the printer still reports them.
+    fn hms_errors(&self) -> Result<Vec<HmsError>> {
+        Ok(vec![])
+    }
+
+    /// Get the health errors the printer is reporting, decoded for people to read.
+    /// Empty when the printer has no faults.
+    fn active_errors(&self) -> Result<Vec<HmsDiagnostic>> {
+        Ok(self.hms_errors()?.iter().map(HmsError::diagnostic).collect())
+    }
+
+    /// Clear the errors the printer is reporting, once the problem has been resolved.
+    async fn clear_errors(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// List the bed mesh calibrations saved on the printer.
+    async fn list_bed_meshes(&self) -> Result<Vec<String>> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Apply a saved bed mesh calibration.
+    /// Printers that only support automatic leveling will level the bed instead.
+    async fn apply_bed_mesh(&self, _name: &str) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
     /// Slice a file.
     /// Returns the path to the sliced file.
     async fn slice(&self, file: &std::path::Path) -> Result<std::path::PathBuf>;
@@ -55,11 +780,122 @@ pub trait NetworkPrinter: Send + Sync {
     /// Print a file.
     async fn print(&self, job_name: &str, file: &std::path::Path) -> Result<Message>;
 
+    /// List the files stored on the printer.
+    async fn list_files(&self) -> Result<Vec<PrinterFile>> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Delete a file stored on the printer.
+    async fn delete_file(&self, _name: &str) -> Result<()> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Print a file already stored on the printer again, without uploading it.
+    async fn reprint(&self, _filename: &str) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Start printing a file, returning a handle to follow the job with.
+    async fn start_job(&self, _job_name: &str, _file: &std::path::Path) -> Result<JobHandle> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
     /// Slice and print a file.
+    /// The sliced file is removed afterwards, whether or not the print started. Slicer failures
+    /// are returned as `NetworkPrinterError::SliceFailed`.
     async fn slice_and_print(&self, job_name: &str, file: &std::path::Path) -> Result<Message> {
-        let sliced = self.slice(file).await?;
-        self.print(job_name, &sliced).await
+        let sliced =
+            crate::slicer::SlicedOutput::new(self.slice(file).await.map_err(NetworkPrinterError::SliceFailed)?);
+
+        self.print(job_name, sliced.path()).await
+    }
+}
+
+/// The ways a printer operation can fail that callers may want to handle.
+/// Printer methods return `anyhow::Result`, downcast the error to this to tell these apart from
+/// anything else that went wrong.
+#[derive(Debug, thiserror::Error)]
+pub enum NetworkPrinterError {
+    /// The printer has stopped reporting, so it can't be reached.
+    #[error("The printer is offline")]
+    Offline,
+    /// The printer is connected but has not reported its status yet.
+    #[error("The printer has not reported its status yet")]
+    NoStatus,
+    /// The printer is busy with a print.
+    #[error("Can't {action} while the printer is {state:?}, stop the print first")]
+    PrintInProgress {
+        /// What was attempted.
+        action: String,
+        /// The state the printer is in.
+        state: PrinterState,
+    },
+    /// The file could not be uploaded to the printer.
+    #[error("Failed to upload file")]
+    UploadFailed(#[source] anyhow::Error),
+    /// The slicer failed, nothing was sent to the printer.
+    #[error("Failed to slice file")]
+    SliceFailed(#[source] anyhow::Error),
+    /// The printer doesn't support this operation.
+    #[error("not supported")]
+    Unsupported,
+}
+
+/// Check the printer is still running `job`.
+async fn ensure_current_job<P: NetworkPrinter + ?Sized>(printer: &P, job: &JobHandle) -> Result<()> {
+    let Message::Status(status) = printer.status().await? else {
+        return Err(NetworkPrinterError::NoStatus.into());
+    };
+
+    if status.job_name.as_deref() != Some(job.job_name.as_str())
+        || !matches!(status.state, PrinterState::Printing | PrinterState::Paused)
+    {
+        anyhow::bail!("Job {} is no longer running on printer {}", job.job_name, job.serial);
     }
+
+    Ok(())
+}
+
+/// Get the IP address and port of the host in an HTTP API URL.
+/// URLs can name the host, but printers are listed by IP.
+pub(crate) async fn resolve_url(url: &str) -> Result<(IpAddr, u16)> {
+    let parsed = reqwest::Url::parse(url).map_err(|e| anyhow::anyhow!("Bad URL {}: {}", url, e))?;
+    let host = parsed
+        .host_str()
+        .ok_or_else(|| anyhow::anyhow!("No host in URL {}", url))?;
+    let port = parsed
+        .port_or_known_default()
+        .ok_or_else(|| anyhow::anyhow!("No port in URL {}", url))?;
+
+    let addr = tokio::net::lookup_host((host.trim_matches(|c| c == '[' || c == ']'), port))
+        .await
+        .map_err(|e| anyhow::anyhow!("Failed to resolve {}: {}", host, e))?
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No address found for {}", host))?;
+
+    Ok((addr.ip(), port))
+}
+
+/// Clean up raw gcode before it's sent to a printer.
+/// Control characters are stripped, and line breaks are refused unless `allow_multiline` is set,
+/// so a single command can't smuggle in others.
+pub fn sanitize_gcode(gcode: &str, allow_multiline: bool) -> Result<String> {
+    if !allow_multiline && gcode.trim_end().contains(['\n', '\r']) {
+        anyhow::bail!("Gcode must be a single line");
+    }
+
+    let gcode: String = gcode
+        .trim()
+        .lines()
+        .map(|line| line.chars().filter(|c| !c.is_control()).collect::<String>())
+        .collect::<Vec<_>>()
+        .join("\n");
+
+    if gcode.trim().is_empty() {
+        anyhow::bail!("Gcode is empty");
+    }
+
+    Ok(gcode)
 }
 
 /// Handle for a 3d printer.
@@ -71,8 +907,16 @@ pub struct NetworkPrinterHandle {
     pub client: Arc<Box<dyn NetworkPrinter>>,
 }
 
+impl Debug for NetworkPrinterHandle {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("NetworkPrinterHandle")
+            .field("info", &self.info)
+            .finish_non_exhaustive()
+    }
+}
+
 /// Details for a 3d printer connected over network.
-#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
 pub struct NetworkPrinterInfo {
     /// The hostname of the printer.
     pub hostname: Option<String>,
@@ -89,23 +933,268 @@ pub struct NetworkPrinterInfo {
 }
 
 /// Network printer manufacturer.
-#[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, Hash, Eq, PartialEq, Ord, PartialOrd)]
+#[derive(Clone, Debug, JsonSchema, Serialize, Deserialize, Hash, Eq, PartialEq, Ord, PartialOrd, clap::ValueEnum)]
 pub enum NetworkPrinterManufacturer {
     /// Bambu.
     Bambu,
+    /// Elegoo.
+    Elegoo,
     /// Formlabs.
     Formlabs,
+    /// Printers running klipper, controlled through moonraker.
+    Klipper,
+    /// Prusa.
+    Prusa,
 }
 
 /// A message from the printer.
+/// Every backend maps its own messages into these variants, anything that doesn't have a
+/// normalized form is passed through as `Raw`.
 #[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
 pub enum Message {
-    Bambu(bambulabs::message::Message),
-    Formlabs {},
+    /// The current status of the printer.
+    Status(PrinterSnapshot),
+    /// The printer acknowledged a command.
+    CommandAck(CommandAck),
+    /// The firmware versions of the printer.
+    Version(VersionInfo),
+    /// A backend specific message.
+    Raw(serde_json::Value),
+}
+
+/// A snapshot of the status of a printer.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+pub struct PrinterSnapshot {
+    /// The state of the printer.
+    pub state: PrinterState,
+    /// The name of the current job.
+    pub job_name: Option<String>,
+    /// The nozzle temperature in celsius.
+    pub nozzle_temperature: Option<f64>,
+    /// The target nozzle temperature in celsius.
+    pub nozzle_target_temperature: Option<f64>,
+    /// The bed temperature in celsius.
+    pub bed_temperature: Option<f64>,
+    /// The target bed temperature in celsius.
+    pub bed_target_temperature: Option<f64>,
+    /// The chamber temperature in celsius.
+    pub chamber_temperature: Option<f64>,
+    /// The percentage of the current print completed.
+    pub progress_percent: Option<u8>,
+    /// The estimated time remaining for the current print in seconds.
+    pub remaining_secs: Option<u64>,
+    /// The layer currently being printed.
+    pub current_layer: Option<u32>,
+    /// The total number of layers in the current print.
+    pub total_layers: Option<u32>,
+    /// The speed profile of the current print.
+    pub speed_profile: Option<SpeedProfile>,
+    /// The calibration the printer is running, if any.
+    pub calibrating: Option<CalibrationKind>,
 }
 
-impl From<bambulabs::message::Message> for Message {
-    fn from(msg: bambulabs::message::Message) -> Self {
-        Self::Bambu(msg)
+/// The state of a printer.
+#[derive(Debug, Clone, Copy, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+#[serde(rename_all = "snake_case")]
+pub enum PrinterState {
+    /// The printer is idle.
+    Idle,
+    /// The printer is preparing or running a print.
+    Printing,
+    /// The current print is paused.
+    Paused,
+    /// The last print finished.
+    Finished,
+    /// The last print failed.
+    Failed,
+    /// The printer reported a state we don't know about.
+    Unknown,
+}
+
+/// A file stored on a printer.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PrinterFile {
+    /// The name of the file.
+    pub name: String,
+    /// The size of the file in bytes.
+    pub size: u64,
+    /// When the file was last modified, if the printer said.
+    pub modified: Option<std::time::SystemTime>,
+}
+
+/// An object on the plate of the current job.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize, PartialEq, Eq)]
+pub struct PlateObject {
+    /// The ID of the object, as passed to `skip_objects`.
+    pub id: u32,
+    /// The name of the object, when it is known.
+    pub name: Option<String>,
+    /// If the object has been skipped.
+    pub skipped: bool,
+}
+
+/// An acknowledgement of a command.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+pub struct CommandAck {
+    /// If the command succeeded, when the printer reports a result.
+    pub success: Option<bool>,
+    /// The reason given by the printer.
+    pub reason: Option<String>,
+}
+
+/// The firmware versions of a printer.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+pub struct VersionInfo {
+    /// The versions of each module in the printer.
+    pub modules: Vec<ModuleVersion>,
+}
+
+/// The version of a single module in a printer.
+#[derive(Debug, Clone, JsonSchema, Serialize, Deserialize)]
+pub struct ModuleVersion {
+    /// The module name.
+    pub name: String,
+    /// The software version.
+    pub software_version: String,
+    /// The hardware version.
+    pub hardware_version: Option<String>,
+    /// The serial number of the module.
+    pub serial: Option<String>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_hms_diagnostic() {
+        let fan = HmsError {
+            attr: 0x0300_0400,
+            code: 0x0002_0001,
+        };
+        assert_eq!(
+            fan.diagnostic(),
+            HmsDiagnostic {
+                code: "0300_0400_0002_0001".to_string(),
+                severity: HmsSeverity::Serious,
+                description: Some("The part cooling fan speed is too slow or stopped.".to_string()),
+            }
+        );
+
+        let unknown = HmsError {
+            attr: 0x0500_0100,
+            code: 0x0004_000a,
+        };
+        assert_eq!(unknown.full_code(), "0500_0100_0004_000A");
+        assert_eq!(unknown.severity(), HmsSeverity::Info);
+        assert_eq!(unknown.diagnostic().description, None);
+
+        let odd = HmsError {
+            attr: 0,
+            code: 0x0009_0001,
+        };
+        assert_eq!(odd.severity(), HmsSeverity::Unknown);
+    }
+
+    fn info(manufacturer: NetworkPrinterManufacturer, model: Option<&str>) -> NetworkPrinterInfo {
+        NetworkPrinterInfo {
+            hostname: None,
+            ip: "192.0.2.10".parse().unwrap(),
+            port: None,
+            manufacturer,
+            model: model.map(String::from),
+            serial: None,
+        }
+    }
+
+    #[test]
+    fn test_printer_filter() {
+        let printers = [
+            info(NetworkPrinterManufacturer::Bambu, Some("Bambu Lab X1 Carbon")),
+            info(NetworkPrinterManufacturer::Bambu, Some("Bambu Lab P1S")),
+            info(NetworkPrinterManufacturer::Formlabs, Some("Form 3")),
+            info(NetworkPrinterManufacturer::Formlabs, None),
+        ];
+        let matching = |filter: &PrinterFilter| printers.iter().filter(|info| filter.matches(info)).count();
+
+        assert_eq!(matching(&PrinterFilter::default()), 4);
+        assert_eq!(
+            matching(&PrinterFilter {
+                manufacturer: Some(NetworkPrinterManufacturer::Bambu),
+                ..Default::default()
+            }),
+            2
+        );
+        assert_eq!(
+            matching(&PrinterFilter {
+                model_contains: Some("x1".to_string()),
+                ..Default::default()
+            }),
+            1
+        );
+        assert_eq!(
+            matching(&PrinterFilter {
+                manufacturer: Some(NetworkPrinterManufacturer::Formlabs),
+                model_contains: Some("bambu".to_string()),
+                ..Default::default()
+            }),
+            0
+        );
+    }
+
+    #[test]
+    fn test_network_printer_info_serde() {
+        let info = NetworkPrinterInfo {
+            hostname: Some("workshop-x1c".to_string()),
+            port: Some(8883),
+            serial: Some("00M09A350100123".to_string()),
+            ..info(NetworkPrinterManufacturer::Bambu, Some("Bambu Lab X1 Carbon"))
+        };
+
+        let json = serde_json::to_value(&info).unwrap();
+        assert_eq!(json["ip"], "192.0.2.10");
+        assert_eq!(json["manufacturer"], "Bambu");
+        assert_eq!(serde_json::from_value::<NetworkPrinterInfo>(json).unwrap(), info);
+    }
+
+    #[test]
+    fn test_message_serde() {
+        let message = Message::CommandAck(CommandAck {
+            success: Some(true),
+            reason: None,
+        });
+
+        let json = serde_json::to_value(&message).unwrap();
+        assert_eq!(
+            json,
+            serde_json::json!({"command_ack": {"success": true, "reason": null}})
+        );
+        let round_trip = serde_json::from_value::<Message>(json.clone()).unwrap();
+        assert_eq!(serde_json::to_value(round_trip).unwrap(), json);
+    }
+
+    #[test]
+    fn test_sanitize_gcode() {
+        assert_eq!(sanitize_gcode(" M104 S200\n", false).unwrap(), "M104 S200");
+        assert_eq!(sanitize_gcode("M104\u{7}\u{1b} S200", false).unwrap(), "M104 S200");
+        assert!(sanitize_gcode("M104 S200\nM112", false).is_err());
+        assert!(sanitize_gcode("M104 S200\rM112", false).is_err());
+        assert!(sanitize_gcode(" \u{0} ", false).is_err());
+
+        assert_eq!(sanitize_gcode("G28\r\nG1 Z10\u{0}\n", true).unwrap(), "G28\nG1 Z10");
+    }
+
+    #[tokio::test]
+    async fn test_resolve_url() {
+        assert_eq!(
+            resolve_url("http://192.0.2.20:7125").await.unwrap(),
+            ("192.0.2.20".parse().unwrap(), 7125)
+        );
+        assert_eq!(
+            resolve_url("http://[2001:db8::20]/").await.unwrap(),
+            ("2001:db8::20".parse().unwrap(), 80)
+        );
+        assert!(resolve_url("not a url").await.is_err());
     }
 }
@@ -0,0 +1,368 @@
+//! Moonraker backend for the [`crate::network_printer::NetworkPrinter`] trait, for printers
+//! running klipper.
+
+use std::{net::IpAddr, sync::Arc};
+
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use futures_util::{pin_mut, stream::StreamExt};
+
+use crate::{
+    config::{MoonrakerConfig, MoonrakerMachineConfig},
+    network_printer::{
+        resolve_url, CommandAck, Message, ModuleVersion, NetworkPrinter, NetworkPrinterError, NetworkPrinterHandle,
+        NetworkPrinterInfo, NetworkPrinterManufacturer, NetworkPrinters, PrinterCapabilities, PrinterSnapshot,
+        PrinterState, Temperatures, VersionInfo,
+    },
+};
+
+/// The service moonraker advertises when its `zeroconf` component is enabled.
+const SERVICE_NAME: &str = "_moonraker._tcp.local";
+
+/// The port moonraker listens on by default.
+const DEFAULT_PORT: u16 = 7125;
+
+/// Moonraker printer backend.
+pub struct Moonraker {
+    config: MoonrakerConfig,
+    /// The printers found, keyed by the base URL of their API.
+    printers: DashMap<String, NetworkPrinterHandle>,
+}
+
+impl Moonraker {
+    /// Create a new Moonraker printer backend.
+    pub fn new(config: &MoonrakerConfig) -> Self {
+        Self {
+            config: config.clone(),
+            printers: DashMap::new(),
+        }
+    }
+
+    /// Add a moonraker instance from the config.
+    async fn add_configured(&self, machine: &MoonrakerMachineConfig) -> Result<()> {
+        let (ip, port) = resolve_url(&machine.url).await?;
+
+        self.add(
+            NetworkPrinterInfo {
+                hostname: Some(machine.id.clone()),
+                ip,
+                port: Some(port),
+                manufacturer: NetworkPrinterManufacturer::Klipper,
+                model: None,
+                serial: None,
+            },
+            machine.url.trim_end_matches('/'),
+        )
+    }
+
+    /// Add a moonraker instance, replacing any already found at the same URL.
+    fn add(&self, info: NetworkPrinterInfo, url: &str) -> Result<()> {
+        tracing::info!("Found moonraker instance at {}", url);
+
+        let handle = NetworkPrinterHandle {
+            info,
+            client: Arc::new(Box::new(MoonrakerPrinter {
+                client: moonraker::PrintManager::new(url)?,
+                led: self.config.led.clone(),
+            })),
+        };
+        self.printers.insert(url.to_string(), handle);
+
+        Ok(())
+    }
+
+    /// Browse for moonraker instances over mDNS.
+    async fn browse(&self) -> Result<()> {
+        let stream = mdns::discover::all(SERVICE_NAME, std::time::Duration::from_secs(15))?.listen();
+        pin_mut!(stream);
+
+        while let Some(Ok(response)) = stream.next().await {
+            let Some(ip) = response.ip_addr() else {
+                tracing::debug!("moonraker instance does not advertise address: {:?}", response);
+                continue;
+            };
+            let port = response.port().unwrap_or(DEFAULT_PORT);
+
+            let info = NetworkPrinterInfo {
+                hostname: response.hostname().map(|name| name.to_string()),
+                ip,
+                port: Some(port),
+                manufacturer: NetworkPrinterManufacturer::Klipper,
+                model: None,
+                serial: None,
+            };
+            self.add(info, &base_url(ip, port))?;
+        }
+
+        anyhow::bail!("moonraker discovery ended unexpectedly");
+    }
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinters for Moonraker {
+    async fn discover(&self) -> Result<()> {
+        for machine in &self.config.machines {
+            if let Err(e) = self.add_configured(machine).await {
+                tracing::warn!("Failed to add moonraker instance {}: {:?}", machine.id, e);
+            }
+        }
+
+        if self.config.mdns {
+            self.browse().await
+        } else {
+            Ok(())
+        }
+    }
+
+    fn list(&self) -> Result<Vec<NetworkPrinterInfo>> {
+        Ok(self
+            .printers
+            .iter()
+            .map(|printer| printer.value().info.clone())
+            .collect())
+    }
+
+    fn list_handles(&self) -> Result<Vec<NetworkPrinterHandle>> {
+        Ok(self.printers.iter().map(|printer| printer.value().clone()).collect())
+    }
+}
+
+/// Get the base URL of the moonraker API at `ip` and `port`.
+fn base_url(ip: IpAddr, port: u16) -> String {
+    match ip {
+        IpAddr::V4(ip) => format!("http://{}:{}", ip, port),
+        IpAddr::V6(ip) => format!("http://[{}]:{}", ip, port),
+    }
+}
+
+/// A printer running klipper, controlled through moonraker.
+pub struct MoonrakerPrinter {
+    client: moonraker::PrintManager,
+    /// The klipper LED to switch with `set_led`.
+    led: Option<String>,
+}
+
+impl MoonrakerPrinter {
+    /// Check the printer isn't busy with a print, before starting another.
+    async fn ensure_not_printing(&self, action: &str) -> Result<()> {
+        let state = printer_state(&self.client.status().await?.print_stats.state);
+        if matches!(state, PrinterState::Printing | PrinterState::Paused) {
+            return Err(NetworkPrinterError::PrintInProgress {
+                action: action.to_string(),
+                state,
+            }
+            .into());
+        }
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinter for MoonrakerPrinter {
+    /// Check moonraker answers.
+    async fn ping(&self) -> Result<bool> {
+        Ok(self.client.info().await.is_ok())
+    }
+
+    /// Get what the printer supports.
+    fn capabilities(&self) -> PrinterCapabilities {
+        PrinterCapabilities {
+            led: self.led.is_some(),
+            print: true,
+            ..Default::default()
+        }
+    }
+
+    /// Get the status of a printer.
+    async fn status(&self) -> Result<Message> {
+        Ok(Message::Status(self.client.status().await?.into()))
+    }
+
+    /// Get the temperatures of the printer.
+    async fn get_temperatures(&self) -> Result<Temperatures> {
+        let status = self.client.status().await?;
+
+        Ok(Temperatures {
+            nozzle: status.extruder.temperature,
+            nozzle_target: status.extruder.target,
+            bed: status.heater_bed.map(|bed| bed.temperature).unwrap_or_default(),
+            bed_target: status.heater_bed.map(|bed| bed.target).unwrap_or_default(),
+            chamber: None,
+        })
+    }
+
+    /// Get the version of the printer.
+    async fn version(&self) -> Result<Message> {
+        let info = self.client.info().await?;
+
+        Ok(Message::Version(VersionInfo {
+            modules: vec![ModuleVersion {
+                name: "klipper".to_string(),
+                software_version: info.software_version,
+                hardware_version: None,
+                serial: None,
+            }],
+        }))
+    }
+
+    /// Pause the current print.
+    async fn pause(&self) -> Result<Message> {
+        self.client.pause_print().await?;
+
+        Ok(command_ack())
+    }
+
+    /// Resume the current print.
+    async fn resume(&self) -> Result<Message> {
+        self.client.resume_print().await?;
+
+        Ok(command_ack())
+    }
+
+    /// Stop the current print.
+    async fn stop(&self) -> Result<Message> {
+        self.client.cancel_print().await?;
+
+        Ok(command_ack())
+    }
+
+    /// Immediately halt the printer.
+    async fn emergency_stop(&self) -> Result<Message> {
+        tracing::warn!("Emergency stopping moonraker printer {}", self.client.url_base());
+
+        self.client.emergency_stop().await?;
+
+        Ok(command_ack())
+    }
+
+    /// Set the led on or off.
+    async fn set_led(&self, on: bool) -> Result<Message> {
+        let led = self.led.as_ref().ok_or(NetworkPrinterError::Unsupported)?;
+        self.client
+            .run_gcode(&format!("SET_LED LED={} WHITE={}", led, if on { 1 } else { 0 }))
+            .await?;
+
+        Ok(command_ack())
+    }
+
+    /// Get the accessories.
+    async fn accessories(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Slice a file.
+    /// There is no slicer config for klipper printers, files must be sliced already.
+    async fn slice(&self, _file: &std::path::Path) -> Result<std::path::PathBuf> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Print a gcode file.
+    /// The file is uploaded as `<job_name>.gcode`, so the job shows up under its name.
+    async fn print(&self, job_name: &str, file: &std::path::Path) -> Result<Message> {
+        self.ensure_not_printing("print").await?;
+
+        let gcode = tokio::fs::read(file)
+            .await
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let uploaded = self
+            .client
+            .upload(std::path::Path::new(&format!("{}.gcode", job_name)), &gcode)
+            .await
+            .map_err(NetworkPrinterError::UploadFailed)?;
+
+        self.client.print(std::path::Path::new(&uploaded.item.path)).await?;
+
+        Ok(command_ack())
+    }
+}
+
+/// Moonraker only answers once a request has been carried out, so every answer is a success.
+fn command_ack() -> Message {
+    Message::CommandAck(CommandAck {
+        success: Some(true),
+        reason: None,
+    })
+}
+
+/// Get the state of the printer from klipper's `print_stats` state.
+fn printer_state(state: &str) -> PrinterState {
+    match state {
+        "standby" => PrinterState::Idle,
+        "printing" => PrinterState::Printing,
+        "paused" => PrinterState::Paused,
+        "complete" => PrinterState::Finished,
+        "cancelled" | "error" => PrinterState::Failed,
+        _ => PrinterState::Unknown,
+    }
+}
+
+impl From<moonraker::PrinterStatus> for PrinterSnapshot {
+    fn from(status: moonraker::PrinterStatus) -> Self {
+        let filename = status.print_stats.filename;
+        PrinterSnapshot {
+            state: printer_state(&status.print_stats.state),
+            job_name: (!filename.is_empty()).then(|| filename.strip_suffix(".gcode").unwrap_or(&filename).to_string()),
+            nozzle_temperature: Some(status.extruder.temperature),
+            nozzle_target_temperature: Some(status.extruder.target),
+            bed_temperature: status.heater_bed.map(|bed| bed.temperature),
+            bed_target_temperature: status.heater_bed.map(|bed| bed.target),
+            chamber_temperature: None,
+            progress_percent: status
+                .display_status
+                .map(|display| (display.progress * 100.0).round().clamp(0.0, 100.0) as u8),
+            remaining_secs: None,
+            current_layer: None,
+            total_layers: None,
+            speed_profile: None,
+            calibrating: None,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_moonraker_status_into_snapshot() {
+        let status: moonraker::PrinterStatus = serde_json::from_value(serde_json::json!({
+            "print_stats": {"state": "printing", "filename": "benchy.gcode", "print_duration": 120.5},
+            "extruder": {"temperature": 214.8, "target": 215.0},
+            "heater_bed": {"temperature": 59.9, "target": 60.0},
+            "display_status": {"progress": 0.426},
+        }))
+        .unwrap();
+
+        let snapshot = PrinterSnapshot::from(status);
+        assert_eq!(snapshot.state, PrinterState::Printing);
+        assert_eq!(snapshot.job_name.as_deref(), Some("benchy"));
+        assert_eq!(snapshot.nozzle_target_temperature, Some(215.0));
+        assert_eq!(snapshot.bed_temperature, Some(59.9));
+        assert_eq!(snapshot.progress_percent, Some(43));
+    }
+
+    #[test]
+    fn test_moonraker_idle_status_into_snapshot() {
+        let status: moonraker::PrinterStatus = serde_json::from_value(serde_json::json!({
+            "print_stats": {"state": "standby", "filename": "", "print_duration": 0.0},
+            "extruder": {"temperature": 24.0, "target": 0.0},
+        }))
+        .unwrap();
+
+        let snapshot = PrinterSnapshot::from(status);
+        assert_eq!(snapshot.state, PrinterState::Idle);
+        assert_eq!(snapshot.job_name, None);
+        assert_eq!(snapshot.bed_temperature, None);
+        assert_eq!(snapshot.progress_percent, None);
+    }
+
+    #[test]
+    fn test_base_url() {
+        assert_eq!(base_url("192.0.2.20".parse().unwrap(), 7125), "http://192.0.2.20:7125");
+        assert_eq!(
+            base_url("2001:db8::20".parse().unwrap(), 7125),
+            "http://[2001:db8::20]:7125"
+        );
+    }
+}
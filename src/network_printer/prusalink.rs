@@ -0,0 +1,515 @@
+//! PrusaLink backend for the [`crate::network_printer::NetworkPrinter`] trait, for Prusa MK4 and
+//! XL printers.
+
+use std::sync::Arc;
+
+use anyhow::{Context, Result};
+use dashmap::DashMap;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    config::{PrusaLinkConfig, PrusaLinkMachineConfig},
+    network_printer::{
+        resolve_url, CommandAck, Message, ModuleVersion, NetworkPrinter, NetworkPrinterError, NetworkPrinterHandle,
+        NetworkPrinterInfo, NetworkPrinterManufacturer, NetworkPrinters, PrinterCapabilities, PrinterSnapshot,
+        PrinterState, Temperatures, VersionInfo,
+    },
+};
+
+/// The header PrusaLink reads the API key from.
+const API_KEY_HEADER: &str = "X-Api-Key";
+
+/// PrusaLink printer backend.
+/// PrusaLink doesn't advertise itself, so only the printers in the config are added.
+pub struct PrusaLink {
+    config: PrusaLinkConfig,
+    /// The printers found, keyed by machine id.
+    printers: DashMap<String, NetworkPrinterHandle>,
+}
+
+impl PrusaLink {
+    /// Create a new PrusaLink printer backend.
+    pub fn new(config: &PrusaLinkConfig) -> Self {
+        Self {
+            config: config.clone(),
+            printers: DashMap::new(),
+        }
+    }
+
+    /// Add a printer from the config.
+    async fn add_configured(&self, machine: &PrusaLinkMachineConfig) -> Result<()> {
+        let (ip, port) = resolve_url(&machine.url).await?;
+        let printer = PrusaLinkPrinter::new(&machine.url, &machine.api_key);
+
+        // The version says which printer it is, but isn't needed to add it.
+        let model = match printer.get_version().await {
+            Ok(version) => version.hostname,
+            Err(e) => {
+                tracing::warn!("Failed to get the version of PrusaLink printer {}: {:?}", machine.id, e);
+                None
+            }
+        };
+
+        tracing::info!("Added PrusaLink printer {} at {}", machine.id, machine.url);
+        self.printers.insert(
+            machine.id.clone(),
+            NetworkPrinterHandle {
+                info: NetworkPrinterInfo {
+                    hostname: Some(machine.id.clone()),
+                    ip,
+                    port: Some(port),
+                    manufacturer: NetworkPrinterManufacturer::Prusa,
+                    model,
+                    serial: None,
+                },
+                client: Arc::new(Box::new(printer)),
+            },
+        );
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinters for PrusaLink {
+    async fn discover(&self) -> Result<()> {
+        for machine in &self.config.machines {
+            if let Err(e) = self.add_configured(machine).await {
+                tracing::warn!("Failed to add PrusaLink printer {}: {:?}", machine.id, e);
+            }
+        }
+
+        Ok(())
+    }
+
+    fn list(&self) -> Result<Vec<NetworkPrinterInfo>> {
+        Ok(self
+            .printers
+            .iter()
+            .map(|printer| printer.value().info.clone())
+            .collect())
+    }
+
+    fn list_handles(&self) -> Result<Vec<NetworkPrinterHandle>> {
+        Ok(self.printers.iter().map(|printer| printer.value().clone()).collect())
+    }
+}
+
+/// The response to `GET /api/version`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct VersionResponse {
+    /// The PrusaLink version.
+    server: String,
+    /// The firmware version.
+    firmware: Option<String>,
+    /// The name the printer gives itself, e.g. `PrusaMK4`.
+    hostname: Option<String>,
+}
+
+/// The response to `GET /api/printer`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct PrinterResponse {
+    temperature: Option<PrinterTemperatures>,
+    state: PrinterStatus,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+struct PrinterTemperatures {
+    tool0: Option<Heater>,
+    bed: Option<Heater>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+struct Heater {
+    actual: f64,
+    target: f64,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct PrinterStatus {
+    flags: PrinterFlags,
+}
+
+#[derive(Debug, Clone, Copy, Default, Deserialize, Serialize)]
+#[serde(default)]
+struct PrinterFlags {
+    operational: bool,
+    printing: bool,
+    paused: bool,
+    error: bool,
+}
+
+/// The response to `GET /api/job`.
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct JobResponse {
+    job: Option<Job>,
+    progress: Option<JobProgress>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct Job {
+    file: Option<JobFile>,
+}
+
+#[derive(Debug, Clone, Deserialize, Serialize)]
+struct JobFile {
+    name: Option<String>,
+    display: Option<String>,
+}
+
+#[derive(Debug, Clone, Copy, Deserialize, Serialize)]
+#[serde(rename_all = "camelCase")]
+struct JobProgress {
+    /// How much of the job is done, from 0.0 to 1.0.
+    completion: Option<f64>,
+    /// The estimated time left in seconds.
+    print_time_left: Option<u64>,
+}
+
+/// A Prusa printer, controlled through PrusaLink.
+pub struct PrusaLinkPrinter {
+    client: reqwest::Client,
+    url: String,
+    api_key: String,
+}
+
+impl PrusaLinkPrinter {
+    /// Create a client for the PrusaLink instance at `url`.
+    fn new(url: &str, api_key: &str) -> Self {
+        Self {
+            client: reqwest::Client::new(),
+            url: url.trim_end_matches('/').to_string(),
+            api_key: api_key.to_string(),
+        }
+    }
+
+    /// Start a request to `path`, authenticated with the API key.
+    fn request(&self, method: reqwest::Method, path: &str) -> reqwest::RequestBuilder {
+        self.request_url(method, format!("{}{}", self.url, path))
+    }
+
+    /// Start a request to `url`, authenticated with the API key.
+    fn request_url(&self, method: reqwest::Method, url: impl reqwest::IntoUrl) -> reqwest::RequestBuilder {
+        self.client.request(method, url).header(API_KEY_HEADER, &self.api_key)
+    }
+
+    /// The URL of a file in the printer's storage. The name is percent-encoded, so names with
+    /// spaces, `#` or `/` in them stay one path segment.
+    fn file_url(&self, name: &str) -> Result<reqwest::Url> {
+        let mut url = reqwest::Url::parse(&format!("{}/api/files/local", self.url))?;
+        url.path_segments_mut()
+            .map_err(|_| anyhow::anyhow!("Bad PrusaLink URL {}", self.url))?
+            .push(name);
+
+        Ok(url)
+    }
+
+    async fn get_version(&self) -> Result<VersionResponse> {
+        Ok(self
+            .request(reqwest::Method::GET, "/api/version")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn get_printer(&self) -> Result<PrinterResponse> {
+        Ok(self
+            .request(reqwest::Method::GET, "/api/printer")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    async fn get_job(&self) -> Result<JobResponse> {
+        Ok(self
+            .request(reqwest::Method::GET, "/api/job")
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?)
+    }
+
+    /// Send a command for the current job, e.g. `{"command": "cancel"}`.
+    async fn job_command(&self, command: serde_json::Value) -> Result<Message> {
+        self.request(reqwest::Method::POST, "/api/job")
+            .json(&command)
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(command_ack())
+    }
+
+    /// Upload a file to the printer's storage.
+    async fn upload(&self, name: &str, file: &std::path::Path) -> Result<()> {
+        let gcode = tokio::fs::read(file)
+            .await
+            .with_context(|| format!("Failed to read {}", file.display()))?;
+        let part = reqwest::multipart::Part::bytes(gcode)
+            .file_name(name.to_string())
+            .mime_str("text/x-gcode")?;
+
+        self.request(reqwest::Method::POST, "/api/files/local")
+            .multipart(reqwest::multipart::Form::new().part("file", part))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(())
+    }
+}
+
+#[async_trait::async_trait]
+impl NetworkPrinter for PrusaLinkPrinter {
+    /// Check PrusaLink answers and accepts the API key.
+    async fn ping(&self) -> Result<bool> {
+        Ok(self.get_version().await.is_ok())
+    }
+
+    /// Get what the printer supports.
+    fn capabilities(&self) -> PrinterCapabilities {
+        PrinterCapabilities {
+            print: true,
+            ..Default::default()
+        }
+    }
+
+    /// Get the status of a printer.
+    async fn status(&self) -> Result<Message> {
+        let (printer, job) = tokio::try_join!(self.get_printer(), self.get_job())?;
+
+        Ok(Message::Status(snapshot(printer, job)))
+    }
+
+    /// Get the temperatures of the printer.
+    async fn get_temperatures(&self) -> Result<Temperatures> {
+        let temperatures = self
+            .get_printer()
+            .await?
+            .temperature
+            .ok_or(NetworkPrinterError::NoStatus)?;
+        let nozzle = temperatures.tool0.ok_or(NetworkPrinterError::NoStatus)?;
+        let bed = temperatures.bed.ok_or(NetworkPrinterError::NoStatus)?;
+
+        Ok(Temperatures {
+            nozzle: nozzle.actual,
+            nozzle_target: nozzle.target,
+            bed: bed.actual,
+            bed_target: bed.target,
+            chamber: None,
+        })
+    }
+
+    /// Get the version of the printer.
+    async fn version(&self) -> Result<Message> {
+        let version = self.get_version().await?;
+
+        let mut modules = vec![ModuleVersion {
+            name: "prusalink".to_string(),
+            software_version: version.server,
+            hardware_version: None,
+            serial: None,
+        }];
+        if let Some(firmware) = version.firmware {
+            modules.push(ModuleVersion {
+                name: "firmware".to_string(),
+                software_version: firmware,
+                hardware_version: None,
+                serial: None,
+            });
+        }
+
+        Ok(Message::Version(VersionInfo { modules }))
+    }
+
+    /// Pause the current print.
+    async fn pause(&self) -> Result<Message> {
+        self.job_command(serde_json::json!({"command": "pause", "action": "pause"}))
+            .await
+    }
+
+    /// Resume the current print.
+    async fn resume(&self) -> Result<Message> {
+        self.job_command(serde_json::json!({"command": "pause", "action": "resume"}))
+            .await
+    }
+
+    /// Stop the current print.
+    async fn stop(&self) -> Result<Message> {
+        self.job_command(serde_json::json!({"command": "cancel"})).await
+    }
+
+    /// Immediately halt the printer.
+    async fn emergency_stop(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Set the led on or off.
+    async fn set_led(&self, _on: bool) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Get the accessories.
+    async fn accessories(&self) -> Result<Message> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Slice a file.
+    /// There is no slicer config for Prusa printers, files must be sliced already.
+    async fn slice(&self, _file: &std::path::Path) -> Result<std::path::PathBuf> {
+        Err(NetworkPrinterError::Unsupported.into())
+    }
+
+    /// Print a gcode file.
+    /// Like the Bambu printers, the file is uploaded first and then selected for printing. It is
+    /// uploaded under the job name, so the job shows up under its name.
+    async fn print(&self, job_name: &str, file: &std::path::Path) -> Result<Message> {
+        let state = printer_state(&self.get_printer().await?.state.flags);
+        if matches!(state, PrinterState::Printing | PrinterState::Paused) {
+            return Err(NetworkPrinterError::PrintInProgress {
+                action: "print".to_string(),
+                state,
+            }
+            .into());
+        }
+
+        // MK4 and XL printers also take binary gcode, keep whichever the file is.
+        let extension = file.extension().and_then(|ext| ext.to_str()).unwrap_or("gcode");
+        let name = format!("{}.{}", job_name, extension);
+
+        self.upload(&name, file)
+            .await
+            .map_err(NetworkPrinterError::UploadFailed)?;
+
+        self.request_url(reqwest::Method::POST, self.file_url(&name)?)
+            .json(&serde_json::json!({"command": "select", "print": true}))
+            .send()
+            .await?
+            .error_for_status()?;
+
+        Ok(command_ack())
+    }
+}
+
+/// PrusaLink only answers once a request has been carried out, so every answer is a success.
+fn command_ack() -> Message {
+    Message::CommandAck(CommandAck {
+        success: Some(true),
+        reason: None,
+    })
+}
+
+/// Get the state of the printer from its flags.
+fn printer_state(flags: &PrinterFlags) -> PrinterState {
+    if flags.paused {
+        PrinterState::Paused
+    } else if flags.printing {
+        PrinterState::Printing
+    } else if flags.error {
+        PrinterState::Failed
+    } else if flags.operational {
+        PrinterState::Idle
+    } else {
+        PrinterState::Unknown
+    }
+}
+
+/// Build a snapshot from the printer and job responses.
+fn snapshot(printer: PrinterResponse, job: JobResponse) -> PrinterSnapshot {
+    let nozzle = printer.temperature.and_then(|temperature| temperature.tool0);
+    let bed = printer.temperature.and_then(|temperature| temperature.bed);
+    let job_name = job
+        .job
+        .and_then(|job| job.file)
+        .and_then(|file| file.display.or(file.name))
+        .map(|name| match name.rsplit_once('.') {
+            Some((stem, "gcode" | "bgcode")) => stem.to_string(),
+            _ => name,
+        });
+
+    PrinterSnapshot {
+        state: printer_state(&printer.state.flags),
+        job_name,
+        nozzle_temperature: nozzle.map(|nozzle| nozzle.actual),
+        nozzle_target_temperature: nozzle.map(|nozzle| nozzle.target),
+        bed_temperature: bed.map(|bed| bed.actual),
+        bed_target_temperature: bed.map(|bed| bed.target),
+        chamber_temperature: None,
+        progress_percent: job
+            .progress
+            .and_then(|progress| progress.completion)
+            .map(|completion| (completion * 100.0).round().clamp(0.0, 100.0) as u8),
+        remaining_secs: job.progress.and_then(|progress| progress.print_time_left),
+        current_layer: None,
+        total_layers: None,
+        speed_profile: None,
+        calibrating: None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_prusalink_file_url() {
+        let printer = PrusaLinkPrinter::new("http://192.0.2.30/", "abcdef123456");
+
+        assert_eq!(
+            printer.file_url("benchy.bgcode").unwrap().as_str(),
+            "http://192.0.2.30/api/files/local/benchy.bgcode"
+        );
+        assert_eq!(
+            printer.file_url("part #2 / v3?.bgcode").unwrap().as_str(),
+            "http://192.0.2.30/api/files/local/part%20%232%20%2F%20v3%3F.bgcode"
+        );
+    }
+
+    #[test]
+    fn test_prusalink_snapshot() {
+        let printer: PrinterResponse = serde_json::from_value(serde_json::json!({
+            "telemetry": {"temp-bed": 60.1, "temp-nozzle": 215.2},
+            "temperature": {
+                "tool0": {"actual": 215.2, "target": 215.0},
+                "bed": {"actual": 60.1, "target": 60.0}
+            },
+            "state": {"text": "Printing", "flags": {"operational": true, "printing": true, "paused": false}}
+        }))
+        .unwrap();
+        let job: JobResponse = serde_json::from_value(serde_json::json!({
+            "state": "Printing",
+            "job": {"file": {"name": "BENCHY~1.BGC", "display": "benchy.bgcode"}},
+            "progress": {"completion": 0.42, "printTime": 1200, "printTimeLeft": 1680}
+        }))
+        .unwrap();
+
+        let snapshot = snapshot(printer, job);
+        assert_eq!(snapshot.state, PrinterState::Printing);
+        assert_eq!(snapshot.job_name.as_deref(), Some("benchy"));
+        assert_eq!(snapshot.nozzle_temperature, Some(215.2));
+        assert_eq!(snapshot.bed_target_temperature, Some(60.0));
+        assert_eq!(snapshot.progress_percent, Some(42));
+        assert_eq!(snapshot.remaining_secs, Some(1680));
+    }
+
+    #[test]
+    fn test_prusalink_printer_state() {
+        let flags = |operational, printing, paused, error| PrinterFlags {
+            operational,
+            printing,
+            paused,
+            error,
+        };
+
+        assert_eq!(printer_state(&flags(true, false, false, false)), PrinterState::Idle);
+        assert_eq!(printer_state(&flags(true, true, false, false)), PrinterState::Printing);
+        assert_eq!(printer_state(&flags(true, true, true, false)), PrinterState::Paused);
+        assert_eq!(printer_state(&flags(false, false, false, true)), PrinterState::Failed);
+        assert_eq!(printer_state(&flags(false, false, false, false)), PrinterState::Unknown);
+    }
+}
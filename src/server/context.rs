@@ -5,7 +5,7 @@ use tokio::sync::Mutex;
 
 use crate::{
     config::Config,
-    network_printer::{NetworkPrinterManufacturer, NetworkPrinters},
+    network_printer::{manager::PrinterManager, NetworkPrinters},
 };
 
 /**
@@ -15,8 +15,10 @@ pub struct Context {
     pub schema: serde_json::Value,
     pub logger: slog::Logger,
     pub usb_printers: Arc<HashMap<String, crate::usb_printer::UsbPrinterInfo>>,
-    pub network_printers: Arc<HashMap<NetworkPrinterManufacturer, Box<dyn NetworkPrinters>>>,
+    pub network_printers: Arc<PrinterManager>,
     pub active_jobs: Mutex<HashMap<String, tokio::task::JoinHandle<Result<crate::machine::Message>>>>,
+    #[cfg(feature = "metrics")]
+    pub metrics: Arc<crate::metrics::PrinterMetrics>,
 }
 
 impl Context {
@@ -24,45 +26,64 @@ impl Context {
      * Return a new Context.
      */
     pub async fn new(config: &Config, schema: serde_json::Value, logger: slog::Logger) -> Result<Context> {
-        let mut network_printers: HashMap<NetworkPrinterManufacturer, Box<dyn NetworkPrinters>> = HashMap::new();
+        let mut network_printers: Vec<Box<dyn NetworkPrinters>> = Vec::new();
 
         if let Some(formlabs_config) = &config.formlabs {
             // Add formlabs backend.
-            network_printers.insert(
-                NetworkPrinterManufacturer::Formlabs,
-                Box::new(crate::network_printer::formlabs::Formlabs::new(formlabs_config)),
-            );
+            network_printers.push(Box::new(crate::network_printer::formlabs::Formlabs::new(
+                formlabs_config,
+            )));
+        }
+
+        if let Some(moonraker_config) = &config.moonraker {
+            // Add moonraker backend.
+            network_printers.push(Box::new(crate::network_printer::moonraker::Moonraker::new(
+                moonraker_config,
+            )));
+        }
+
+        if let Some(prusalink_config) = &config.prusalink {
+            // Add PrusaLink backend.
+            network_printers.push(Box::new(crate::network_printer::prusalink::PrusaLink::new(
+                prusalink_config,
+            )));
+        }
+
+        if let Some(elegoo_config) = &config.elegoo {
+            // Add Elegoo backend.
+            network_printers.push(Box::new(crate::network_printer::elegoo::Elegoo::new(elegoo_config)));
         }
 
         if let Some(bambulabs_config) = &config.bambulabs {
             // Add Bambu Lab backend.
-            network_printers.insert(
-                NetworkPrinterManufacturer::Bambu,
-                Box::new(crate::network_printer::bambu_x1_carbon::BambuX1Carbon::new(
-                    bambulabs_config,
-                )),
-            );
+            let bambu = crate::network_printer::bambu::Bambu::new(bambulabs_config);
+            let backend: Box<dyn NetworkPrinters> = if bambulabs_config.mdns {
+                Box::new(crate::network_printer::mdns::BambuMdns::new(Arc::new(bambu), true))
+            } else {
+                Box::new(bambu)
+            };
+            network_printers.push(backend);
         }
 
         // Create the context.
         Ok(Context {
             schema,
             logger,
-            network_printers: Arc::new(network_printers),
+            network_printers: Arc::new(PrinterManager::new(network_printers)),
             usb_printers: Arc::new(crate::usb_printer::UsbPrinter::list_all()),
             active_jobs: Mutex::new(HashMap::new()),
+            #[cfg(feature = "metrics")]
+            metrics: Arc::new(crate::metrics::PrinterMetrics::new()?),
         })
     }
 
     pub fn list_machines(&self) -> Result<HashMap<String, crate::machine::Machine>> {
         let mut machines: HashMap<String, crate::machine::Machine> = HashMap::new();
-        for (_, np) in self.network_printers.iter() {
-            for np in np.list()? {
-                if let Some(hostname) = np.hostname.clone() {
-                    machines.insert(hostname, np.into());
-                } else {
-                    machines.insert(np.ip.to_string(), np.into());
-                }
+        for np in self.network_printers.list()? {
+            if let Some(hostname) = np.hostname.clone() {
+                machines.insert(hostname, np.into());
+            } else {
+                machines.insert(np.ip.to_string(), np.into());
             }
         }
         for (_, up) in self.usb_printers.iter() {
@@ -73,13 +94,11 @@ impl Context {
 
     pub fn list_machine_handles(&self) -> Result<HashMap<String, crate::machine::MachineHandle>> {
         let mut machines: HashMap<String, crate::machine::MachineHandle> = HashMap::new();
-        for (_, np) in self.network_printers.iter() {
-            for np in np.list_handles()? {
-                if let Some(hostname) = np.info.hostname.clone() {
-                    machines.insert(hostname, np.into());
-                } else {
-                    machines.insert(np.info.ip.to_string(), np.into());
-                }
+        for np in self.network_printers.list_handles()? {
+            if let Some(hostname) = np.info.hostname.clone() {
+                machines.insert(hostname, np.into());
+            } else {
+                machines.insert(np.info.ip.to_string(), np.into());
             }
         }
         for (_, up) in self.usb_printers.iter() {
@@ -101,19 +120,7 @@ pub async fn discovery(ctx: Arc<Context>, dur: tokio::time::Duration) -> Result<
     println!("Discovering printers...");
     // We don't care if it times out, we just want to wait for the discovery tasks to
     // finish.
-    let _ = tokio::time::timeout(dur, async move {
-        let form_labs = ctx
-            .network_printers
-            .get(&NetworkPrinterManufacturer::Formlabs)
-            .expect("No formlabs discover task registered");
-        let bambu = ctx
-            .network_printers
-            .get(&NetworkPrinterManufacturer::Bambu)
-            .expect("No Bambu discover task registered");
-
-        tokio::join!(form_labs.discover(), bambu.discover())
-    })
-    .await;
+    let _ = tokio::time::timeout(dur, ctx.network_printers.discover()).await;
 
     Ok(())
 }
@@ -1,10 +1,16 @@
 use std::{collections::HashMap, sync::Arc};
 
-use dropshot::{endpoint, HttpError, HttpResponseOk, Path, RequestContext};
+use dropshot::{
+    endpoint, HttpError, HttpResponseOk, HttpResponseUpdatedNoContent, Path, Query, RequestContext, TypedBody,
+};
 use schemars::JsonSchema;
 use serde::{Deserialize, Serialize};
 
-use crate::{print_manager::PrintJob, server::context::Context};
+use crate::{
+    network_printer::{NetworkPrinterError, NetworkPrinterHandle, NetworkPrinterInfo, NetworkPrinters},
+    print_manager::PrintJob,
+    server::context::Context,
+};
 
 /**
  * Return the OpenAPI schema in JSON format.
@@ -39,6 +45,26 @@ pub async fn ping(_rqctx: RequestContext<Arc<Context>>) -> Result<HttpResponseOk
     }))
 }
 
+/** Return printer telemetry in the Prometheus text exposition format. */
+#[cfg(feature = "metrics")]
+#[endpoint {
+    method = GET,
+    path = "/metrics",
+    tags = ["meta"],
+    unpublished = true,
+}]
+pub async fn get_metrics(rqctx: RequestContext<Arc<Context>>) -> Result<hyper::Response<hyper::Body>, HttpError> {
+    let text = rqctx.context().metrics.encode().map_err(|e| {
+        tracing::error!("failed to encode metrics: {:?}", e);
+        HttpError::for_internal_error(format!("failed to encode metrics: {:?}", e))
+    })?;
+
+    hyper::Response::builder()
+        .header(hyper::header::CONTENT_TYPE, prometheus::TEXT_FORMAT)
+        .body(text.into())
+        .map_err(|e| HttpError::for_internal_error(format!("failed to build metrics response: {:?}", e)))
+}
+
 /** List available machines and their statuses */
 #[endpoint {
     method = GET,
@@ -94,6 +120,374 @@ pub async fn get_machine(
     Ok(HttpResponseOk(message))
 }
 
+/** List the network printers that have been discovered */
+#[endpoint {
+    method = GET,
+    path = "/printers",
+    tags = ["machines"],
+}]
+pub async fn get_printers(
+    rqctx: RequestContext<Arc<Context>>,
+) -> Result<HttpResponseOk<Vec<NetworkPrinterInfo>>, HttpError> {
+    let ctx = rqctx.context();
+    let printers = ctx.network_printers.list().map_err(|e| {
+        tracing::error!("failed to list printers: {:?}", e);
+        HttpError::for_bad_request(None, "failed to list printers".to_string())
+    })?;
+    Ok(HttpResponseOk(printers))
+}
+
+/// The path parameters for performing operations on a network printer.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct PrinterPathParams {
+    /// The serial number of the printer, or for printers without one its name or IP address.
+    pub serial: String,
+}
+
+/// Look up a network printer by serial, falling back to its name or IP address for printers
+/// that don't report a serial, or return a 404.
+fn find_printer(ctx: &Context, id: &str) -> Result<NetworkPrinterHandle, HttpError> {
+    let printers = &ctx.network_printers;
+    printers
+        .get_by_serial(id)
+        .or_else(|| printers.get_by_hostname(id))
+        .or_else(|| id.parse().ok().and_then(|ip| printers.get_by_ip(ip)))
+        .ok_or_else(|| {
+            tracing::error!("printer not found: {:?}", id);
+            HttpError::for_not_found(None, format!("printer not found: {:?}", id))
+        })
+}
+
+/// Turn a printer error into a response, telling the client when the printer is unavailable
+/// rather than the request being bad.
+fn printer_error(err: &anyhow::Error, fallback: &str) -> HttpError {
+    match err.downcast_ref::<NetworkPrinterError>() {
+        Some(e @ (NetworkPrinterError::Offline | NetworkPrinterError::NoStatus)) => {
+            HttpError::for_unavail(None, e.to_string())
+        }
+        Some(NetworkPrinterError::SliceFailed(_)) => {
+            HttpError::for_bad_request(None, "failed to slice file".to_string())
+        }
+        Some(NetworkPrinterError::UploadFailed(_)) => {
+            HttpError::for_bad_request(None, "failed to upload file".to_string())
+        }
+        Some(e @ (NetworkPrinterError::PrintInProgress { .. } | NetworkPrinterError::Unsupported)) => {
+            HttpError::for_bad_request(None, e.to_string())
+        }
+        None => HttpError::for_bad_request(None, fallback.to_string()),
+    }
+}
+
+/// Like `printer_error`, for methods that check their arguments before talking to the printer.
+/// Anything that isn't a `NetworkPrinterError` is the request being refused, so its message is
+/// passed on.
+fn refused_error(err: &anyhow::Error, fallback: &str) -> HttpError {
+    match err.downcast_ref::<NetworkPrinterError>() {
+        Some(_) => printer_error(err, fallback),
+        None => HttpError::for_bad_request(None, err.to_string()),
+    }
+}
+
+/// The response from the `/printers/{serial}/ping` endpoint.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct PrinterPong {
+    /// Whether the printer is reachable and we're authenticated with it.
+    pub reachable: bool,
+}
+
+/** Check a specific network printer is reachable, without fetching its status */
+#[endpoint {
+    method = GET,
+    path = "/printers/{serial}/ping",
+    tags = ["machines"],
+}]
+pub async fn ping_printer(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+) -> Result<HttpResponseOk<PrinterPong>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let reachable = printer.client.ping().await.map_err(|e| {
+        tracing::error!("failed to ping printer: {:?}", e);
+        printer_error(&e, "failed to ping printer")
+    })?;
+
+    Ok(HttpResponseOk(PrinterPong { reachable }))
+}
+
+/** Get what a specific network printer supports */
+#[endpoint {
+    method = GET,
+    path = "/printers/{serial}/capabilities",
+    tags = ["machines"],
+}]
+pub async fn get_printer_capabilities(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+) -> Result<HttpResponseOk<crate::network_printer::PrinterCapabilities>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    Ok(HttpResponseOk(printer.client.capabilities()))
+}
+
+/** Get the status of a specific network printer */
+#[endpoint {
+    method = GET,
+    path = "/printers/{serial}/status",
+    tags = ["machines"],
+}]
+pub async fn get_printer_status(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+) -> Result<HttpResponseOk<crate::network_printer::Message>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let message = printer.client.status().await.map_err(|e| {
+        tracing::error!("failed to get printer status: {:?}", e);
+        printer_error(&e, "failed to get printer status")
+    })?;
+
+    Ok(HttpResponseOk(message))
+}
+
+/** Reconnect to a specific network printer whose connection has stopped getting reports */
+#[endpoint {
+    method = POST,
+    path = "/printers/{serial}/restart-client",
+    tags = ["machines"],
+}]
+pub async fn restart_printer_client(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+) -> Result<HttpResponseUpdatedNoContent, HttpError> {
+    let params = path_params.into_inner();
+    // Look the printer up first, so a wrong serial is a 404.
+    find_printer(rqctx.context(), &params.serial)?;
+
+    rqctx
+        .context()
+        .network_printers
+        .restart_client(&params.serial)
+        .map_err(|e| {
+            tracing::error!("failed to restart printer client: {:?}", e);
+            printer_error(&e, "failed to restart printer client")
+        })?;
+
+    Ok(HttpResponseUpdatedNoContent())
+}
+
+/** Pause the current print on a specific network printer */
+#[endpoint {
+    method = POST,
+    path = "/printers/{serial}/pause",
+    tags = ["machines"],
+}]
+pub async fn pause_printer(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+) -> Result<HttpResponseOk<crate::network_printer::Message>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let message = printer.client.pause().await.map_err(|e| {
+        tracing::error!("failed to pause printer: {:?}", e);
+        printer_error(&e, "failed to pause printer")
+    })?;
+
+    Ok(HttpResponseOk(message))
+}
+
+/// Raw gcode to send to a network printer.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct PrinterGcode {
+    /// The gcode command, e.g. `M104 S200`.
+    pub line: String,
+}
+
+/** Send a raw gcode command to a specific network printer */
+#[endpoint {
+    method = POST,
+    path = "/printers/{serial}/gcode",
+    tags = ["machines"],
+}]
+pub async fn send_printer_gcode(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+    body: TypedBody<PrinterGcode>,
+) -> Result<HttpResponseOk<crate::network_printer::Message>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    if !printer.client.capabilities().send_gcode {
+        return Err(HttpError::for_bad_request(
+            None,
+            "the printer does not accept raw gcode".to_string(),
+        ));
+    }
+
+    let message = printer.client.send_gcode(&body.into_inner().line).await.map_err(|e| {
+        tracing::error!("failed to send gcode to printer: {:?}", e);
+        refused_error(&e, "failed to send gcode to printer")
+    })?;
+
+    Ok(HttpResponseOk(message))
+}
+
+/// The calibration to run on a network printer.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct PrinterCalibration {
+    /// The kind of calibration.
+    pub kind: crate::network_printer::CalibrationKind,
+}
+
+/** Start a calibration on a specific network printer. Returns once the printer accepts it, not once it finishes */
+#[endpoint {
+    method = POST,
+    path = "/printers/{serial}/calibrate",
+    tags = ["machines"],
+}]
+pub async fn calibrate_printer(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+    body: TypedBody<PrinterCalibration>,
+) -> Result<HttpResponseOk<crate::network_printer::Message>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let message = printer.client.calibrate(body.into_inner().kind).await.map_err(|e| {
+        tracing::error!("failed to start calibration: {:?}", e);
+        refused_error(&e, "failed to start calibration")
+    })?;
+
+    Ok(HttpResponseOk(message))
+}
+
+/** Get the health errors a specific network printer is reporting */
+#[endpoint {
+    method = GET,
+    path = "/printers/{serial}/errors",
+    tags = ["machines"],
+}]
+pub async fn get_printer_errors(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+) -> Result<HttpResponseOk<Vec<crate::network_printer::HmsDiagnostic>>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let errors = printer.client.active_errors().map_err(|e| {
+        tracing::error!("failed to get printer errors: {:?}", e);
+        printer_error(&e, "failed to get printer errors")
+    })?;
+
+    Ok(HttpResponseOk(errors))
+}
+
+/** Get the objects on the plate of the current job on a specific network printer */
+#[endpoint {
+    method = GET,
+    path = "/printers/{serial}/plate-objects",
+    tags = ["machines"],
+}]
+pub async fn get_printer_plate_objects(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+) -> Result<HttpResponseOk<Vec<crate::network_printer::PlateObject>>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let objects = printer.client.plate_objects().map_err(|e| {
+        tracing::error!("failed to get plate objects: {:?}", e);
+        printer_error(&e, "failed to get plate objects")
+    })?;
+
+    Ok(HttpResponseOk(objects))
+}
+
+/// The objects to skip on a network printer.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct PrinterSkipObjects {
+    /// The IDs of the objects to skip, as given in the plate's slice info.
+    pub object_ids: Vec<u32>,
+}
+
+/** Stop printing some objects of the current plate on a specific network printer, leaving the rest to finish */
+#[endpoint {
+    method = POST,
+    path = "/printers/{serial}/skip-objects",
+    tags = ["machines"],
+}]
+pub async fn skip_printer_objects(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+    body: TypedBody<PrinterSkipObjects>,
+) -> Result<HttpResponseOk<crate::network_printer::Message>, HttpError> {
+    let params = path_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let message = printer
+        .client
+        .skip_objects(&body.into_inner().object_ids)
+        .await
+        .map_err(|e| {
+            tracing::error!("failed to skip objects: {:?}", e);
+            refused_error(&e, "failed to skip objects")
+        })?;
+
+    Ok(HttpResponseOk(message))
+}
+
+/// The query parameters for printing to a network printer.
+#[derive(Deserialize, Debug, JsonSchema, Serialize)]
+pub struct PrinterPrintParams {
+    /// The name for the job.
+    pub job_name: String,
+}
+
+/** Slice and print a file on a specific network printer. File must be a sliceable 3D model. */
+#[endpoint {
+    method = POST,
+    path = "/printers/{serial}/print",
+    tags = ["machines"],
+}]
+pub async fn print_to_printer(
+    rqctx: RequestContext<Arc<Context>>,
+    path_params: Path<PrinterPathParams>,
+    query_params: Query<PrinterPrintParams>,
+    body_param: dropshot::MultipartBody,
+) -> Result<HttpResponseOk<crate::network_printer::Message>, HttpError> {
+    let params = path_params.into_inner();
+    let query = query_params.into_inner();
+    let printer = find_printer(rqctx.context(), &params.serial)?;
+
+    let mut multipart = body_param.content;
+    let file = parse_multipart_file(&mut multipart).await?;
+    let filepath = std::env::temp_dir().join(format!(
+        "{}-{}",
+        uuid::Uuid::new_v4(),
+        file.file_name.unwrap_or("file".to_string())
+    ));
+    tokio::fs::write(&filepath, file.content).await.map_err(|e| {
+        tracing::error!("failed to write stl file: {:?}", e);
+        HttpError::for_bad_request(None, "failed to write stl file".to_string())
+    })?;
+
+    let result = printer.client.slice_and_print(&query.job_name, &filepath).await;
+    if let Err(e) = tokio::fs::remove_file(&filepath).await {
+        tracing::warn!("failed to remove uploaded file {}: {:?}", filepath.display(), e);
+    }
+
+    let message = result.map_err(|e| {
+        tracing::error!("failed to print file: {:?}", e);
+        printer_error(&e, "failed to print file")
+    })?;
+
+    Ok(HttpResponseOk(message))
+}
+
 /// The response from the `/print` endpoint.
 #[derive(Deserialize, Debug, JsonSchema, Serialize)]
 pub struct PrintJobResponse {
@@ -156,7 +550,7 @@ pub(crate) async fn print_file(
                 .await
                 .map_err(|e| {
                     tracing::error!("failed to print file: {:?}", e);
-                    HttpError::for_bad_request(None, "failed to print file".to_string())
+                    printer_error(&e, "failed to print file")
                 })?;
 
             tracing::info!("result: {:?}", result);
@@ -237,3 +631,18 @@ pub async fn parse_multipart_print_request(
         return Err(Error::MissingFileOrParams);
     }
 }
+
+/// Parses multipart data into a single file attachment.
+#[tracing::instrument(skip_all)]
+pub async fn parse_multipart_file(multipart: &mut multer::Multipart<'_>) -> Result<FileAttachment, Error> {
+    while let Some(field) = multipart.next_field().await? {
+        if field.name() == Some("file") {
+            return Ok(FileAttachment {
+                file_name: field.file_name().map(str::to_string),
+                content: field.bytes().await?,
+            });
+        }
+    }
+
+    Err(Error::MissingFileOrParams)
+}
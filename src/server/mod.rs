@@ -1,4 +1,7 @@
 pub mod context;
+// dropshot's #[endpoint] expands to module-level `Need*` check structs that are
+// never constructed; nothing else in the module needs this.
+#[allow(dead_code)]
 pub mod endpoints;
 
 use std::{env, net::SocketAddr, sync::Arc};
@@ -6,7 +9,11 @@ use std::{env, net::SocketAddr, sync::Arc};
 use anyhow::{anyhow, Result};
 use dropshot::{ApiDescription, ConfigDropshot, HttpServerStarter};
 
-use crate::{config::Config, network_printer::NetworkPrinterManufacturer, server::context::Context};
+use crate::{
+    config::Config,
+    network_printer::{manager::PrinterManager, NetworkPrinters},
+    server::context::Context,
+};
 
 async fn handle_signals(api_context: Arc<Context>) -> Result<()> {
     #[cfg(unix)]
@@ -43,10 +50,64 @@ async fn handle_signals(api_context: Arc<Context>) -> Result<()> {
     }
 
     slog::info!(api_context.logger, "triggering cleanup...");
+    #[cfg(feature = "metrics")]
+    api_context.metrics.shutdown();
+    api_context.network_printers.shutdown().await;
     slog::info!(api_context.logger, "all clean, exiting!");
     std::process::exit(0);
 }
 
+/// Restore the printers in the cache, then save it again whenever printers are found or lost.
+async fn keep_printer_cache(printers: Arc<PrinterManager>, path: std::path::PathBuf) {
+    // Subscribe first, so printers found while restoring are saved too.
+    let mut events = printers.subscribe();
+    if let Err(e) = printers.load_cache(&path).await {
+        tracing::error!("Failed to load the printer cache {}: {:?}", path.display(), e);
+    }
+
+    loop {
+        // Saving straight after loading drops the printers that couldn't be restored.
+        if let Err(e) = printers.save_cache(&path).await {
+            tracing::error!("Failed to save the printer cache {}: {:?}", path.display(), e);
+        }
+
+        match events.recv().await {
+            Ok(_) | Err(tokio::sync::broadcast::error::RecvError::Lagged(_)) => continue,
+            Err(tokio::sync::broadcast::error::RecvError::Closed) => return,
+        }
+    }
+}
+
+/// Reload the config file whenever the process gets a SIGHUP, so printers can be added to it
+/// without a restart.
+#[cfg(unix)]
+async fn reload_on_sighup(api_context: Arc<Context>, opts: crate::Opts) -> Result<()> {
+    use tokio::signal::unix::{signal, SignalKind};
+
+    let mut sighup = signal(SignalKind::hangup())?;
+    while sighup.recv().await.is_some() {
+        slog::info!(
+            api_context.logger,
+            "received SIGHUP, reloading {}",
+            opts.config.display()
+        );
+
+        let config = match opts.load_config().await {
+            Ok(config) => config,
+            Err(e) => {
+                slog::error!(api_context.logger, "failed to read config: {:?}", e);
+                continue;
+            }
+        };
+
+        if let Err(e) = api_context.network_printers.reload_config(&config) {
+            slog::error!(api_context.logger, "failed to reload config: {:?}", e);
+        }
+    }
+
+    Ok(())
+}
+
 /// Create an API description for the server.
 pub fn create_api_description() -> Result<ApiDescription<Arc<Context>>> {
     fn register_endpoints(api: &mut ApiDescription<Arc<Context>>) -> Result<(), String> {
@@ -55,6 +116,22 @@ pub fn create_api_description() -> Result<ApiDescription<Arc<Context>>> {
         api.register(crate::server::endpoints::print_file).unwrap();
         api.register(crate::server::endpoints::get_machines).unwrap();
         api.register(crate::server::endpoints::get_machine).unwrap();
+        api.register(crate::server::endpoints::get_printers).unwrap();
+        api.register(crate::server::endpoints::get_printer_status).unwrap();
+        api.register(crate::server::endpoints::ping_printer).unwrap();
+        api.register(crate::server::endpoints::get_printer_capabilities)
+            .unwrap();
+        api.register(crate::server::endpoints::get_printer_errors).unwrap();
+        api.register(crate::server::endpoints::restart_printer_client).unwrap();
+        api.register(crate::server::endpoints::pause_printer).unwrap();
+        api.register(crate::server::endpoints::send_printer_gcode).unwrap();
+        api.register(crate::server::endpoints::get_printer_plate_objects)
+            .unwrap();
+        api.register(crate::server::endpoints::skip_printer_objects).unwrap();
+        api.register(crate::server::endpoints::calibrate_printer).unwrap();
+        api.register(crate::server::endpoints::print_to_printer).unwrap();
+        #[cfg(feature = "metrics")]
+        api.register(crate::server::endpoints::get_metrics).unwrap();
 
         // YOUR ENDPOINTS HERE!
 
@@ -98,6 +175,13 @@ pub async fn create_server(
     Ok((server, api_context))
 }
 
+/// How often to poll printers for the Prometheus metrics.
+#[cfg(feature = "metrics")]
+const METRICS_POLL_INTERVAL: std::time::Duration = std::time::Duration::from_secs(15);
+
+/// How often to check for printers that have gone offline.
+const LIVENESS_CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(10);
+
 /// Get the OpenAPI specification for the server.
 pub fn get_openapi(api: &mut ApiDescription<Arc<Context>>) -> Result<serde_json::Value> {
     // Create the API schema.
@@ -129,20 +213,29 @@ pub async fn server(s: &crate::Server, opts: &crate::Opts, config: &Config) -> R
     let cloned_api_context = api_context.clone();
     tokio::spawn(handle_signals(cloned_api_context));
 
+    // Pick up new printers in the config file.
+    #[cfg(unix)]
+    tokio::spawn(reload_on_sighup(api_context.clone(), opts.clone()));
+
+    // Remove printers that go offline.
+    api_context
+        .network_printers
+        .start_liveness_checks(LIVENESS_CHECK_INTERVAL)?;
+
+    #[cfg(feature = "metrics")]
+    api_context
+        .metrics
+        .clone()
+        .spawn(api_context.network_printers.clone(), METRICS_POLL_INTERVAL);
+
+    // Reconnect to the printers found last time, and keep the cache up to date.
+    if let Some(path) = s.printer_cache.clone() {
+        tokio::spawn(keep_printer_cache(api_context.network_printers.clone(), path));
+    }
+
     // Start all the discovery tasks.
-    tokio::spawn(async move {
-        // TODO: Restart tasks if they fail, with some kind of backoff timer
-        let form_labs = api_context
-            .network_printers
-            .get(&NetworkPrinterManufacturer::Formlabs)
-            .expect("No formlabs discover task registered");
-        let bambu = api_context
-            .network_printers
-            .get(&NetworkPrinterManufacturer::Bambu)
-            .expect("No Bambu discover task registered");
-
-        tokio::join!(form_labs.discover(), bambu.discover())
-    });
+    // TODO: Restart tasks if they fail, with some kind of backoff timer
+    tokio::spawn(async move { api_context.network_printers.discover().await });
 
     server.await.map_err(|error| anyhow!("server failed: {}", error))?;
 
@@ -0,0 +1,86 @@
+//! A slicer for tests, that doesn't need a real slicer installed.
+
+use std::time::Duration;
+
+use anyhow::Result;
+
+use crate::slicer::Slicer;
+
+/// A slicer that "slices" by copying the input file.
+#[derive(Debug, Default, Clone)]
+pub struct MockSlicer {
+    /// How long each slice takes.
+    delay: Duration,
+    /// The error to fail slices with, if any.
+    error: Option<String>,
+}
+
+impl MockSlicer {
+    /// Create a slicer that copies files straight away.
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Take `delay` to slice each file.
+    pub fn with_delay(mut self, delay: Duration) -> Self {
+        self.delay = delay;
+        self
+    }
+
+    /// Fail every slice with `error`.
+    pub fn with_error(mut self, error: &str) -> Self {
+        self.error = Some(error.to_string());
+        self
+    }
+}
+
+#[async_trait::async_trait]
+impl Slicer for MockSlicer {
+    async fn slice(&self, file: &std::path::Path) -> Result<std::path::PathBuf> {
+        let uid = uuid::Uuid::new_v4();
+        let gcode_path = std::env::temp_dir().join(format!("{}.3mf", uid));
+
+        self.slice_to(file, &gcode_path).await?;
+
+        Ok(gcode_path)
+    }
+
+    async fn slice_to(&self, file: &std::path::Path, output: &std::path::Path) -> Result<()> {
+        tokio::time::sleep(self.delay).await;
+
+        if let Some(error) = &self.error {
+            anyhow::bail!("{}", error);
+        }
+
+        if let Some(parent) = output.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+        tokio::fs::copy(file, output).await?;
+
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[tokio::test]
+    async fn test_mock_slicer() {
+        let dir = tempdir::TempDir::new("mock").unwrap();
+        let model = dir.path().join("model.stl");
+        std::fs::write(&model, "solid cube\nendsolid cube\n").unwrap();
+
+        let sliced = MockSlicer::new().slice(&model).await.unwrap();
+        assert!(sliced.extension().is_some_and(|ext| ext == "3mf"));
+        assert_eq!(std::fs::read(&sliced).unwrap(), std::fs::read(&model).unwrap());
+        std::fs::remove_file(sliced).unwrap();
+
+        let err = MockSlicer::new()
+            .with_error("mesh is not manifold")
+            .slice(&model)
+            .await
+            .unwrap_err();
+        assert_eq!(err.to_string(), "mesh is not manifold");
+    }
+}
@@ -1,8 +1,13 @@
 //! A trait for a slicer.
 
+#[cfg(test)]
+pub mod mock;
 pub mod orca;
+pub mod pool;
 pub mod prusa;
 
+use std::{io::Read, path::Path, time::Duration};
+
 use anyhow::Result;
 
 /// A slicer interface.
@@ -11,4 +16,488 @@ pub trait Slicer: Send + Sync {
     /// Slice a file.
     /// Returns the path to the sliced file.
     async fn slice(&self, file: &std::path::Path) -> Result<std::path::PathBuf>;
+
+    /// Slice a file into the given output path.
+    /// Any missing parent directories of the output are created.
+    async fn slice_to(&self, file: &std::path::Path, output: &std::path::Path) -> Result<()>;
+
+    /// Slice a file with the given options.
+    /// Slicers that can't scale models to fit will fail if asked to.
+    async fn slice_with_options(&self, file: &std::path::Path, options: &SliceOptions) -> Result<SliceOutput> {
+        if options.auto_fit {
+            anyhow::bail!("Fitting the model to the build volume is not supported by this slicer");
+        }
+
+        Ok(SliceOutput {
+            path: self.slice(file).await?,
+            scale: 1.0,
+        })
+    }
+
+    /// Slice a file, sending progress to `sink` as the slicer reports it.
+    /// Slicers that don't report progress just slice the file.
+    async fn slice_with_progress(
+        &self,
+        file: &std::path::Path,
+        _sink: tokio::sync::mpsc::Sender<SliceProgress>,
+    ) -> Result<std::path::PathBuf> {
+        self.slice(file).await
+    }
+
+    /// Slice a file, along with the estimates the slicer wrote into it.
+    /// Estimates the slicer didn't write are left as `None`.
+    async fn slice_detailed(&self, file: &std::path::Path) -> Result<SlicedOutput> {
+        let path = self.slice(file).await?;
+
+        let mut sliced = SlicedOutput::new(path);
+        match read_gcode(sliced.path()) {
+            Ok(gcode) => {
+                let metadata = parse_slice_metadata(&gcode);
+                sliced.estimated_secs = metadata.estimated_secs;
+                sliced.filament_grams = metadata.filament_grams;
+                sliced.filament_mm = metadata.filament_mm;
+            }
+            Err(e) => tracing::debug!("Could not read the estimates from {}: {:?}", sliced.path().display(), e),
+        }
+
+        Ok(sliced)
+    }
+}
+
+/// The kind of file a slicer writes.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub enum OutputFormat {
+    /// A 3mf with the G-code embedded in it, what Bambu printers expect.
+    #[default]
+    ThreeMf,
+    /// Plain G-code.
+    Gcode,
+}
+
+impl OutputFormat {
+    /// Get the output format for a path from its extension.
+    pub fn from_path(path: &Path) -> Result<Self> {
+        match path.extension().and_then(|ext| ext.to_str()) {
+            Some(ext) if ext.eq_ignore_ascii_case("3mf") => Ok(OutputFormat::ThreeMf),
+            Some(ext) if ext.eq_ignore_ascii_case("gcode") => Ok(OutputFormat::Gcode),
+            _ => anyhow::bail!("Can't slice to {}, expected a .3mf or .gcode file", path.display()),
+        }
+    }
+
+    /// Get the file extension for the format.
+    pub fn extension(&self) -> &'static str {
+        match self {
+            OutputFormat::ThreeMf => "3mf",
+            OutputFormat::Gcode => "gcode",
+        }
+    }
+}
+
+/// Progress reported by a slicer.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SliceProgress {
+    /// What the slicer is doing, e.g. `Generating perimeters`.
+    pub stage: String,
+    /// How far through slicing the slicer is, from 0 to 100.
+    pub percent: Option<u8>,
+}
+
+/// Options for slicing a file.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SliceOptions {
+    /// Scale the model down until it fits in the build volume, rather than failing.
+    pub auto_fit: bool,
+    /// The smallest scale the model may be shrunk to when fitting it, e.g. `0.5` for half size.
+    pub max_downscale: f64,
+}
+
+impl Default for SliceOptions {
+    fn default() -> Self {
+        Self {
+            auto_fit: false,
+            max_downscale: 1.0,
+        }
+    }
+}
+
+/// A sliced file.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SliceOutput {
+    /// The path to the sliced file.
+    pub path: std::path::PathBuf,
+    /// The scale the model was sliced at, `1.0` unless it was shrunk to fit.
+    pub scale: f64,
+}
+
+/// A sliced file that is removed when dropped, so temporary slicer output doesn't pile up.
+/// Hold on to it until the file has been uploaded or printed.
+#[derive(Debug)]
+pub struct SlicedOutput {
+    path: std::path::PathBuf,
+    /// The estimated print time in seconds.
+    pub estimated_secs: Option<u64>,
+    /// The estimated filament used in grams.
+    pub filament_grams: Option<f64>,
+    /// The estimated filament used in millimeters.
+    pub filament_mm: Option<f64>,
+}
+
+impl SlicedOutput {
+    /// Take ownership of a sliced file.
+    pub fn new(path: std::path::PathBuf) -> Self {
+        Self {
+            path,
+            estimated_secs: None,
+            filament_grams: None,
+            filament_mm: None,
+        }
+    }
+
+    /// Get the path to the sliced file.
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+}
+
+impl Drop for SlicedOutput {
+    fn drop(&mut self) {
+        match std::fs::remove_file(&self.path) {
+            Ok(()) => {}
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {}
+            Err(e) => tracing::warn!("Failed to remove sliced file {}: {}", self.path.display(), e),
+        }
+    }
+}
+
+/// Get the estimated time of each layer in a sliced file.
+/// This accepts plain G-code or a 3mf with the G-code embedded in it.
+pub fn layer_times(gcode_3mf: &Path) -> Result<Vec<Duration>> {
+    Ok(parse_layer_times(&read_gcode(gcode_3mf)?))
+}
+
+/// An object on the plate of a sliced file.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SlicedObject {
+    /// The ID the printer knows the object by, e.g. to skip it.
+    pub id: u32,
+    /// The name of the object.
+    pub name: String,
+}
+
+/// Get the objects on the plate of a sliced 3mf, from the slice info the slicer writes.
+/// Plain G-code doesn't list its objects.
+pub fn sliced_objects(gcode_3mf: &Path) -> Result<Vec<SlicedObject>> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(gcode_3mf)?)?;
+
+    let mut slice_info = String::new();
+    archive
+        .by_name("Metadata/slice_info.config")?
+        .read_to_string(&mut slice_info)?;
+    Ok(parse_sliced_objects(&slice_info))
+}
+
+/// Parse the objects of the first plate out of a slice info config, e.g.
+/// `<object identify_id="181" name="Cube" skipped="false" />`.
+fn parse_sliced_objects(slice_info: &str) -> Vec<SlicedObject> {
+    let plate = slice_info.split("</plate>").next().unwrap_or_default();
+
+    plate
+        .split("<object ")
+        .skip(1)
+        .filter_map(|object| {
+            // With the space the `<object ` split took, so every attribute starts with one.
+            let tag = format!(" {}", object.split('>').next()?);
+            let attribute = |key: &str| {
+                let (_, value) = tag.split_once(&format!(" {}=\"", key))?;
+                value.split('"').next()
+            };
+
+            Some(SlicedObject {
+                id: attribute("identify_id")?.parse().ok()?,
+                name: unescape_xml(attribute("name").unwrap_or_default()),
+            })
+        })
+        .collect()
+}
+
+/// Replace the predefined XML entities in an attribute value.
+fn unescape_xml(value: &str) -> String {
+    value
+        .replace("&lt;", "<")
+        .replace("&gt;", ">")
+        .replace("&quot;", "\"")
+        .replace("&apos;", "'")
+        .replace("&amp;", "&")
+}
+
+/// Read the G-code of a sliced file, either plain G-code or a 3mf with the G-code embedded in it.
+fn read_gcode(gcode_3mf: &Path) -> Result<String> {
+    if gcode_3mf.extension().is_some_and(|ext| ext == "gcode") {
+        return Ok(std::fs::read_to_string(gcode_3mf)?);
+    }
+
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(gcode_3mf)?)?;
+    let name = archive
+        .file_names()
+        .find(|name| name.starts_with("Metadata/plate_") && name.ends_with(".gcode"))
+        .ok_or_else(|| anyhow::anyhow!("No G-code found in {}", gcode_3mf.display()))?
+        .to_string();
+
+    let mut gcode = String::new();
+    archive.by_name(&name)?.read_to_string(&mut gcode)?;
+    Ok(gcode)
+}
+
+/// The estimates a slicer writes into the comments of its G-code.
+#[derive(Debug, Default, PartialEq)]
+struct SliceMetadata {
+    estimated_secs: Option<u64>,
+    filament_grams: Option<f64>,
+    filament_mm: Option<f64>,
+}
+
+/// Parse the estimates out of the comments of sliced G-code.
+/// This understands the OrcaSlicer/Bambu header (`; total filament weight [g] : 3.69`) and the
+/// PrusaSlicer style footer (`; filament used [g] = 3.69`). Multi-material prints list a value
+/// for each filament, which are summed.
+fn parse_slice_metadata(gcode: &str) -> SliceMetadata {
+    let mut metadata = SliceMetadata::default();
+
+    for line in gcode.lines() {
+        let Some(comment) = line.trim().strip_prefix(';') else {
+            continue;
+        };
+        let Some((key, value)) = comment.split_once(" = ").or_else(|| comment.split_once(" : ")) else {
+            // The OrcaSlicer header puts the estimate in the middle of a line:
+            // `; model printing time: 34m 5s; total estimated time: 40m 12s`
+            if let Some((_, time)) = comment.split_once("total estimated time:") {
+                metadata.estimated_secs = metadata.estimated_secs.or(parse_duration(time));
+            }
+            continue;
+        };
+
+        match key.trim() {
+            "total filament weight [g]" | "filament used [g]" | "total filament used [g]" => {
+                metadata.filament_grams = metadata.filament_grams.or(sum_values(value));
+            }
+            "total filament length [mm]" | "filament used [mm]" => {
+                metadata.filament_mm = metadata.filament_mm.or(sum_values(value));
+            }
+            "estimated printing time (normal mode)" | "estimated printing time" => {
+                metadata.estimated_secs = metadata.estimated_secs.or(parse_duration(value));
+            }
+            _ => {}
+        }
+    }
+
+    metadata
+}
+
+/// Sum a comma separated list of numbers.
+fn sum_values(values: &str) -> Option<f64> {
+    values
+        .split(',')
+        .map(|value| value.trim().parse::<f64>().ok())
+        .sum::<Option<f64>>()
+}
+
+/// Parse a duration like `1d 2h 3m 4s` into seconds.
+fn parse_duration(duration: &str) -> Option<u64> {
+    let mut secs = 0;
+    for part in duration.split_whitespace() {
+        let unit = match part.chars().last()? {
+            'd' => 24 * 60 * 60,
+            'h' => 60 * 60,
+            'm' => 60,
+            's' => 1,
+            _ => return None,
+        };
+        let value: u64 = part[..part.len() - 1].parse().ok()?;
+        secs += value * unit;
+    }
+
+    (secs > 0 || duration.trim() == "0s").then_some(secs)
+}
+
+/// Parse the layer times out of G-code.
+/// Slicers report the remaining minutes with `M73 R<minutes>`, so the time of a layer is the
+/// difference between the remaining time at the start of it and the start of the next one.
+fn parse_layer_times(gcode: &str) -> Vec<Duration> {
+    // The remaining minutes at the start of each layer, if reported.
+    let mut layer_starts: Vec<Option<u64>> = Vec::new();
+
+    for line in gcode.lines() {
+        let line = line.trim();
+
+        if line.starts_with("; CHANGE_LAYER") || line.starts_with(";LAYER_CHANGE") {
+            layer_starts.push(None);
+            continue;
+        }
+
+        let Some(args) = line.strip_prefix("M73 ") else {
+            continue;
+        };
+
+        let Some(start) = layer_starts.last_mut() else {
+            continue;
+        };
+
+        if start.is_none() {
+            *start = args
+                .split_whitespace()
+                .find_map(|arg| arg.strip_prefix('R'))
+                .and_then(|minutes| minutes.parse().ok());
+        }
+    }
+
+    let mut remaining: Vec<u64> = Vec::with_capacity(layer_starts.len());
+    for start in layer_starts.iter().rev() {
+        // Layers without a progress report get the remaining time of the layer after them.
+        remaining.push(start.unwrap_or_else(|| remaining.last().copied().unwrap_or_default()));
+    }
+    remaining.reverse();
+
+    remaining
+        .iter()
+        .enumerate()
+        .map(|(i, start)| {
+            let next = remaining.get(i + 1).copied().unwrap_or_default();
+            Duration::from_secs(start.saturating_sub(next) * 60)
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn test_parse_sliced_objects() {
+        let slice_info = r##"<?xml version="1.0" encoding="UTF-8"?>
+<config>
+  <header>
+    <header_item key="X-BBL-Client-Type" value="slicer"/>
+  </header>
+  <plate>
+    <metadata key="index" value="1"/>
+    <object identify_id="181" name="Cube" skipped="false" />
+    <object identify_id="433" name="Benchy &amp; friend" skipped="false" />
+    <filament id="1" type="PLA" color="#FFFFFF" used_m="1.23" used_g="3.69" />
+  </plate>
+  <plate>
+    <object identify_id="512" name="Other plate" skipped="false" />
+  </plate>
+</config>"##;
+
+        assert_eq!(
+            parse_sliced_objects(slice_info),
+            vec![
+                SlicedObject {
+                    id: 181,
+                    name: "Cube".to_string()
+                },
+                SlicedObject {
+                    id: 433,
+                    name: "Benchy & friend".to_string()
+                },
+            ]
+        );
+        assert!(parse_sliced_objects("<config><plate></plate></config>").is_empty());
+    }
+
+    #[test]
+    fn test_parse_layer_times() {
+        let gcode = r#"
+M73 P0 R12
+; CHANGE_LAYER
+; Z_HEIGHT: 0.2
+M73 P0 R10
+G1 X1 Y1
+; CHANGE_LAYER
+; Z_HEIGHT: 0.4
+M73 P40 R6
+G1 X2 Y2
+M73 P50 R5
+; CHANGE_LAYER
+G1 X3 Y3
+; CHANGE_LAYER
+M73 P90 R1
+"#;
+
+        assert_eq!(
+            parse_layer_times(gcode),
+            vec![
+                Duration::from_secs(4 * 60),
+                Duration::from_secs(5 * 60),
+                Duration::from_secs(0),
+                Duration::from_secs(60),
+            ]
+        );
+    }
+
+    #[test]
+    fn test_parse_layer_times_no_layers() {
+        assert!(parse_layer_times("G28\nM73 P0 R3\n").is_empty());
+    }
+
+    #[test]
+    fn test_parse_slice_metadata_orca() {
+        let gcode = r#"
+; HEADER_BLOCK_START
+; generated by OrcaSlicer 2.1.1 on 2024-10-01 at 10:00:00
+; model printing time: 34m 5s; total estimated time: 1h 40m 12s
+; total layer number: 50
+; total filament length [mm] : 1234.56,100.00
+; total filament volume [cm^3] : 2970.23
+; total filament weight [g] : 3.69,0.31
+; HEADER_BLOCK_END
+G28
+"#;
+
+        assert_eq!(
+            parse_slice_metadata(gcode),
+            SliceMetadata {
+                estimated_secs: Some(60 * 60 + 40 * 60 + 12),
+                filament_grams: Some(4.0),
+                filament_mm: Some(1334.56),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_slice_metadata_prusa() {
+        let gcode = r#"
+G28
+; filament used [mm] = 1234.5
+; filament used [cm3] = 3.0
+; filament used [g] = 3.7
+; estimated printing time (normal mode) = 1d 2h 3m 4s
+"#;
+
+        assert_eq!(
+            parse_slice_metadata(gcode),
+            SliceMetadata {
+                estimated_secs: Some(24 * 60 * 60 + 2 * 60 * 60 + 3 * 60 + 4),
+                filament_grams: Some(3.7),
+                filament_mm: Some(1234.5),
+            }
+        );
+    }
+
+    #[test]
+    fn test_parse_slice_metadata_missing() {
+        assert_eq!(parse_slice_metadata("G28\nG1 X1 Y1\n"), SliceMetadata::default());
+    }
+
+    #[test]
+    fn test_sliced_output_removed_on_drop() {
+        let dir = tempdir::TempDir::new("sliced").unwrap();
+        let file = dir.path().join("sliced.3mf");
+        std::fs::write(&file, "sliced").unwrap();
+
+        let sliced = SlicedOutput::new(file.clone());
+        assert!(sliced.path().exists());
+        drop(sliced);
+        assert!(!file.exists());
+    }
 }
@@ -1,127 +1,945 @@
-use std::path::PathBuf;
+use std::{
+    io::Read,
+    path::{Path, PathBuf},
+};
 
 use anyhow::{Context, Result};
-use tokio::process::Command;
+use tokio::{
+    io::{AsyncBufReadExt, AsyncReadExt},
+    process::Command,
+};
 
-use crate::slicer::Slicer;
+use crate::slicer::{OutputFormat, SliceOptions, SliceOutput, SliceProgress, Slicer};
+
+/// The environment variable to point at the OrcaSlicer executable with.
+const ORCA_SLICER_PATH_ENV: &str = "ORCA_SLICER_PATH";
+
+/// The oldest OrcaSlicer whose command line takes the flags we slice with, `--load-filaments`
+/// in particular.
+const MIN_ORCA_SLICER_VERSION: semver::Version = semver::Version::new(1, 6, 0);
+
+/// How long OrcaSlicer gets to slice a file by default. It can hang on malformed meshes.
+pub const DEFAULT_SLICE_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(5 * 60);
 
 pub struct OrcaSlicer {
     config: PathBuf,
+    /// The OrcaSlicer executable to run, found automatically if not given.
+    binary: Option<PathBuf>,
+    /// How long to wait for OrcaSlicer before killing it.
+    timeout: std::time::Duration,
+    /// Extra arguments to pass to OrcaSlicer, for options not otherwise exposed.
+    extra_args: Vec<String>,
+    /// The filament config for each filament slot, relative to the config directory. Just
+    /// `filament.json` if empty.
+    filaments: Vec<PathBuf>,
+    /// The kind of file to slice to.
+    output_format: OutputFormat,
+    /// The version of the OrcaSlicer executable, once checked.
+    version: tokio::sync::OnceCell<semver::Version>,
 }
 
 impl OrcaSlicer {
-    pub fn new(config: PathBuf) -> Self {
-        Self { config }
+    pub fn new(config: PathBuf, binary: Option<PathBuf>) -> Self {
+        Self {
+            config,
+            binary,
+            timeout: DEFAULT_SLICE_TIMEOUT,
+            extra_args: Vec::new(),
+            filaments: Vec::new(),
+            output_format: OutputFormat::default(),
+            version: tokio::sync::OnceCell::new(),
+        }
+    }
+
+    /// Set the kind of file to slice to. Bambu printers need a 3mf.
+    pub fn with_output_format(mut self, output_format: OutputFormat) -> Self {
+        self.output_format = output_format;
+        self
+    }
+
+    /// Set the filament config for each filament slot, in slot order, for multi-material prints.
+    /// Paths are relative to the config directory.
+    pub fn with_filaments(mut self, filaments: Vec<PathBuf>) -> Self {
+        self.filaments = filaments;
+        self
+    }
+
+    /// Set extra arguments to pass to OrcaSlicer. Arguments that would change where the output
+    /// is written are dropped.
+    pub fn with_extra_args(mut self, extra_args: Vec<String>) -> Self {
+        self.extra_args = extra_args;
+        self
+    }
+
+    /// Set how long to wait for OrcaSlicer before killing it.
+    pub fn with_timeout(mut self, timeout: std::time::Duration) -> Self {
+        self.timeout = timeout;
+        self
+    }
+
+    /// Check if the OrcaSlicer executable is installed.
+    pub fn is_available(binary: Option<&Path>) -> bool {
+        find_orca_slicer(binary).is_ok()
     }
 }
 
 #[async_trait::async_trait]
 impl Slicer for OrcaSlicer {
     async fn slice(&self, file: &std::path::Path) -> Result<std::path::PathBuf> {
-        // Make sure the config path is a directory.
-        if !self.config.is_dir() {
-            anyhow::bail!(
-                "Invalid slicer config path: {}, must be a directory",
-                self.config.display()
-            );
+        Ok(self.slice_with_options(file, &SliceOptions::default()).await?.path)
+    }
+
+    async fn slice_to(&self, file: &std::path::Path, gcode_path: &std::path::Path) -> Result<()> {
+        let output = self.run(file, gcode_path, 1.0, None).await?;
+
+        check_output(&output, gcode_path)
+    }
+
+    async fn slice_with_progress(
+        &self,
+        file: &std::path::Path,
+        sink: tokio::sync::mpsc::Sender<SliceProgress>,
+    ) -> Result<std::path::PathBuf> {
+        let gcode_path = self.temp_output_path();
+
+        let output = self.run(file, &gcode_path, 1.0, Some(&sink)).await?;
+        check_output(&output, &gcode_path)?;
+
+        Ok(gcode_path)
+    }
+
+    async fn slice_with_options(&self, file: &std::path::Path, options: &SliceOptions) -> Result<SliceOutput> {
+        let gcode_path = self.temp_output_path();
+
+        let mut scale = 1.0;
+        loop {
+            let output = self.run(file, &gcode_path, scale, None).await?;
+
+            if options.auto_fit && !output.status.success() && is_outside_build_volume(&output) {
+                let next = scale * AUTO_FIT_STEP;
+                if next < options.max_downscale {
+                    anyhow::bail!(
+                        "Model does not fit in the build volume, even scaled to {} (limit {})",
+                        scale,
+                        options.max_downscale
+                    );
+                }
+
+                tracing::info!("Model is outside the build volume, reslicing at scale {}", next);
+                scale = next;
+                continue;
+            }
+
+            check_output(&output, &gcode_path)?;
+
+            return Ok(SliceOutput {
+                path: gcode_path,
+                scale,
+            });
         }
+    }
+}
+
+/// How much to shrink a model by each time it doesn't fit in the build volume.
+const AUTO_FIT_STEP: f64 = 0.9;
+
+impl OrcaSlicer {
+    /// Get the version of the OrcaSlicer executable.
+    /// The executable is only run the first time, the version is cached after that.
+    pub async fn version(&self) -> Result<semver::Version> {
+        self.version
+            .get_or_try_init(|| async {
+                let orca_slicer_path = find_orca_slicer(self.binary.as_deref())?;
+                let output = tokio::time::timeout(
+                    self.timeout,
+                    Command::new(&orca_slicer_path)
+                        .arg("--help")
+                        .kill_on_drop(true)
+                        .output(),
+                )
+                .await
+                .context("orca-slicer --help timed out")?
+                .context("Failed to execute orca-slicer command")?;
+
+                let text = format!(
+                    "{}{}",
+                    String::from_utf8_lossy(&output.stdout),
+                    String::from_utf8_lossy(&output.stderr)
+                );
+                parse_version(&text).ok_or_else(|| {
+                    anyhow::anyhow!(
+                        "Could not find the OrcaSlicer version in the output of {} --help",
+                        orca_slicer_path.display()
+                    )
+                })
+            })
+            .await
+            .cloned()
+    }
 
+    /// Make sure the OrcaSlicer executable is new enough to understand the flags we pass it.
+    /// Builds that don't report a version are let through.
+    async fn ensure_supported_version(&self) -> Result<()> {
+        match self.version().await {
+            Ok(version) if version < MIN_ORCA_SLICER_VERSION => anyhow::bail!(
+                "OrcaSlicer {} is too old, upgrade to {} or newer",
+                version,
+                MIN_ORCA_SLICER_VERSION
+            ),
+            Ok(_) => Ok(()),
+            Err(e) => {
+                tracing::warn!("Could not check the OrcaSlicer version: {:#}", e);
+                Ok(())
+            }
+        }
+    }
+
+    /// A new path in the temp directory to slice to, with the extension for the output format.
+    fn temp_output_path(&self) -> PathBuf {
         let uid = uuid::Uuid::new_v4();
-        let gcode_path = std::env::temp_dir().join(format!("{}.3mf", uid));
-        let process_config = self
-            .config
-            .join("process.json")
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Invalid slicer config path: {}", self.config.display()))?
-            .to_string();
-        let machine_config = self
-            .config
-            .join("machine.json")
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Invalid slicer config path: {}", self.config.display()))?
-            .to_string();
-        let filament_config = self
-            .config
-            .join("filament.json")
-            .to_str()
-            .ok_or_else(|| anyhow::anyhow!("Invalid slicer config path: {}", self.config.display()))?
-            .to_string();
+        std::env::temp_dir().join(format!("{}.{}", uid, self.output_format.extension()))
+    }
+
+    /// Build the OrcaSlicer command line to slice a file, scaling the model by `scale`.
+    fn args(&self, file: &std::path::Path, gcode_path: &std::path::Path, scale: f64) -> Result<Vec<String>> {
+        let [process_config, machine_config, filament_config] = config_files(&self.config, &self.filaments)?;
 
         let settings = [process_config, machine_config].join(";");
 
-        let args: Vec<String> = vec![
+        let mut args: Vec<String> = vec![
             "--load-settings".to_string(),
             settings,
             "--load-filaments".to_string(),
             filament_config,
+        ];
+
+        args.extend(filter_extra_args(&self.extra_args));
+
+        args.extend([
             "--slice".to_string(),
             "0".to_string(),
             "--orient".to_string(),
             "1".to_string(),
-            "--export-3mf".to_string(),
+            match self.output_format {
+                OutputFormat::ThreeMf => "--export-3mf",
+                OutputFormat::Gcode => "--export-gcode",
+            }
+            .to_string(),
             gcode_path
                 .to_str()
                 .ok_or_else(|| anyhow::anyhow!("Invalid output G-code path: {}", gcode_path.display()))?
                 .to_string(),
+        ]);
+
+        if scale != 1.0 {
+            args.push("--scale".to_string());
+            args.push(scale.to_string());
+        }
+
+        args.push(
             file.to_str()
                 .ok_or_else(|| anyhow::anyhow!("Invalid original file path: {}", file.display()))?
                 .to_string(),
-        ];
+        );
+
+        Ok(args)
+    }
+
+    /// Run OrcaSlicer on a file, scaling the model by `scale`.
+    /// Progress is sent to `sink` as OrcaSlicer prints it, if given.
+    async fn run(
+        &self,
+        file: &std::path::Path,
+        gcode_path: &std::path::Path,
+        scale: f64,
+        sink: Option<&tokio::sync::mpsc::Sender<SliceProgress>>,
+    ) -> Result<std::process::Output> {
+        let format = InputFormat::detect(file)?;
+        tracing::debug!("Slicing {} as {:?}", file.display(), format);
+
+        let args = self.args(file, gcode_path, scale)?;
+
+        if let Some(parent) = gcode_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
+
+        self.ensure_supported_version().await?;
 
         // Find the orcaslicer executable path.
-        let orca_slicer_path = find_orca_slicer()?;
+        let orca_slicer_path = find_orca_slicer(self.binary.as_deref())?;
 
-        let output = Command::new(orca_slicer_path)
+        let mut child = Command::new(orca_slicer_path)
             .args(&args)
-            .output()
-            .await
+            .stdout(std::process::Stdio::piped())
+            .stderr(std::process::Stdio::piped())
+            .spawn()
             .context("Failed to execute orca-slicer command")?;
 
-        // Make sure the command was successful.
-        if !output.status.success() {
-            let stdout = std::str::from_utf8(&output.stdout)?;
-            let stderr = std::str::from_utf8(&output.stderr)?;
-            anyhow::bail!("Failed to : {:?}\nstdout:\n{}stderr:{}", output, stdout, stderr);
+        // Read stderr alongside stdout, so OrcaSlicer can't block on a full stderr pipe.
+        let mut stderr_pipe = child.stderr.take().context("orca-slicer stderr not captured")?;
+        let stderr = tokio::spawn(async move {
+            let mut stderr = Vec::new();
+            stderr_pipe.read_to_end(&mut stderr).await.map(|_| stderr)
+        });
+
+        let stdout_pipe = child.stdout.take().context("orca-slicer stdout not captured")?;
+        let mut stdout = Vec::new();
+        let finished = tokio::time::timeout(self.timeout, async {
+            let mut lines = tokio::io::BufReader::new(stdout_pipe).lines();
+            while let Some(line) = lines.next_line().await? {
+                if let (Some(sink), Some(progress)) = (sink, parse_progress(&line)) {
+                    // The caller may have stopped listening, that's fine.
+                    let _ = sink.send(progress).await;
+                }
+
+                stdout.extend_from_slice(line.as_bytes());
+                stdout.push(b'\n');
+            }
+
+            child.wait().await
+        })
+        .await;
+
+        let status = match finished {
+            Ok(status) => status?,
+            Err(_) => {
+                // Killing waits on the process too, so it doesn't linger as a zombie.
+                child.kill().await.context("Failed to kill orca-slicer")?;
+                anyhow::bail!(
+                    "orca-slicer timed out after {:?} slicing {}",
+                    self.timeout,
+                    file.display()
+                );
+            }
+        };
+
+        Ok(std::process::Output {
+            status,
+            stdout,
+            stderr: stderr.await??,
+        })
+    }
+}
+
+/// OrcaSlicer options that control where the output goes, which we need to own.
+const RESERVED_ARGS: [&str; 4] = ["--export-3mf", "--export-gcode", "--outputdir", "--slice"];
+
+/// Drop the reserved options, and their values, from user supplied arguments.
+fn filter_extra_args(extra_args: &[String]) -> Vec<String> {
+    let mut filtered = Vec::with_capacity(extra_args.len());
+    let mut args = extra_args.iter();
+    while let Some(arg) = args.next() {
+        let name = arg.split_once('=').map_or(arg.as_str(), |(name, _)| name);
+        if RESERVED_ARGS.contains(&name) {
+            tracing::warn!("Ignoring slicer argument {}, it is set by machine-api", arg);
+            // The value is a separate argument unless it was given with `=`.
+            if !arg.contains('=') {
+                args.next();
+            }
+            continue;
         }
 
-        // Make sure the G-code file was created.
-        if !gcode_path.exists() {
-            anyhow::bail!("Failed to create G-code file");
+        filtered.push(arg.clone());
+    }
+
+    filtered
+}
+
+/// The kinds of file OrcaSlicer can slice.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum InputFormat {
+    /// An STL mesh, ASCII or binary.
+    Stl,
+    /// A 3MF model or project.
+    ThreeMf,
+    /// A STEP CAD file. OrcaSlicer tessellates these itself when loading them, so they are
+    /// passed the same way as a mesh.
+    Step,
+}
+
+impl InputFormat {
+    /// Detect the format of a file from its extension, checking the contents match so a
+    /// misnamed file fails here rather than somewhere inside OrcaSlicer.
+    fn detect(file: &Path) -> Result<Self> {
+        let extension = file
+            .extension()
+            .and_then(|ext| ext.to_str())
+            .map(|ext| ext.to_lowercase())
+            .unwrap_or_default();
+
+        let format = match extension.as_str() {
+            "stl" => InputFormat::Stl,
+            "3mf" => InputFormat::ThreeMf,
+            "step" | "stp" => InputFormat::Step,
+            _ => anyhow::bail!(
+                "Unsupported file type {:?} for {}, expected .stl, .3mf, .step or .stp",
+                extension,
+                file.display()
+            ),
+        };
+
+        let mut header = [0u8; 84];
+        let mut reader = std::fs::File::open(file).with_context(|| format!("Failed to open {}", file.display()))?;
+        let len = reader.read(&mut header)?;
+        let header = &header[..len];
+
+        let valid = match format {
+            // Binary STLs have no magic, just an 80 byte header and the triangle count.
+            InputFormat::Stl => header.starts_with(b"solid") || header.len() == 84,
+            InputFormat::ThreeMf => header.starts_with(b"PK\x03\x04"),
+            InputFormat::Step => header.starts_with(b"ISO-10303-21"),
+        };
+        if !valid {
+            anyhow::bail!("{} is not a valid {:?} file", file.display(), format);
         }
 
-        Ok(gcode_path.to_path_buf())
+        Ok(format)
     }
 }
 
-// Find the orcaslicer executable path on macOS.
-#[cfg(target_os = "macos")]
-fn find_orca_slicer() -> anyhow::Result<PathBuf> {
-    let app_path = std::path::PathBuf::from("/Applications/OrcaSlicer.app/Contents/MacOS/OrcaSlicer");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("OrcaSlicer not found")
+/// Parse the version out of OrcaSlicer's `--help` output, which starts like `OrcaSlicer-2.1.1:`.
+/// Versions with more than three parts, like `1.9.1.3`, are cut down to three.
+fn parse_version(help: &str) -> Option<semver::Version> {
+    let (_, rest) = help.split_once("OrcaSlicer-")?;
+    let version: String = rest.chars().take_while(|c| c.is_ascii_digit() || *c == '.').collect();
+
+    let mut parts = version.split('.').map(|part| part.parse::<u64>().ok());
+    let major = parts.next()??;
+    let minor = parts.next().flatten().unwrap_or_default();
+    let patch = parts.next().flatten().unwrap_or_default();
+
+    Some(semver::Version::new(major, minor, patch))
+}
+
+/// Parse a progress line OrcaSlicer prints while slicing, like
+/// `default_status_callback: percent=40, warning_step=-1, message=Generating perimeters`.
+fn parse_progress(line: &str) -> Option<SliceProgress> {
+    let (_, status) = line.split_once("percent=")?;
+
+    let percent = status
+        .split(',')
+        .next()
+        .and_then(|percent| percent.trim().parse::<i64>().ok())
+        .filter(|percent| *percent >= 0)
+        .map(|percent| percent.min(100) as u8);
+    let stage = status
+        .split_once("message=")
+        .map(|(_, message)| message.trim().to_string())
+        .unwrap_or_default();
+
+    Some(SliceProgress { stage, percent })
+}
+
+/// Make sure OrcaSlicer succeeded and created the G-code file.
+fn check_output(output: &std::process::Output, gcode_path: &std::path::Path) -> Result<()> {
+    // Make sure the command was successful.
+    if !output.status.success() {
+        let stdout = std::str::from_utf8(&output.stdout)?;
+        let stderr = std::str::from_utf8(&output.stderr)?;
+        anyhow::bail!("Failed to : {:?}\nstdout:\n{}stderr:{}", output, stdout, stderr);
+    }
+
+    // Make sure the G-code file was created.
+    if !gcode_path.exists() {
+        anyhow::bail!("Failed to create G-code file");
     }
+
+    Ok(())
 }
 
-// Find the orcaslicer executable path on Windows.
-#[cfg(target_os = "windows")]
-fn find_orca_slicer() -> anyhow::Result<PathBuf> {
-    let app_path = std::path::PathBuf::from("C:\\Program Files\\OrcaSlicer\\orca-slicer.exe");
-    if app_path.exists() {
-        Ok(app_path)
+/// The errors OrcaSlicer gives when the model doesn't fit in the build volume, lowercased.
+const BUILD_VOLUME_ERRORS: [&str; 4] = [
+    "no object is fully inside the plate",
+    "found no objects in print volume to slice",
+    "some objects are located over the boundary of the heated bed",
+    "objects partly inside",
+];
+
+/// Check if OrcaSlicer failed because the model doesn't fit in the build volume.
+fn is_outside_build_volume(output: &std::process::Output) -> bool {
+    let text = format!(
+        "{}{}",
+        String::from_utf8_lossy(&output.stdout),
+        String::from_utf8_lossy(&output.stderr)
+    )
+    .to_lowercase();
+
+    BUILD_VOLUME_ERRORS.iter().any(|error| text.contains(error))
+}
+
+/// The slicer config files an OrcaSlicer config directory needs, and the key in an OrcaSlicer
+/// project's settings that names the preset each is made from.
+const CONFIG_FILES: [(&str, &str); 3] = [
+    ("process", "print_settings_id"),
+    ("machine", "printer_settings_id"),
+    ("filament", "filament_settings_id"),
+];
+
+/// Extract the process, machine and filament configs from an OrcaSlicer project 3mf into the
+/// directory layout [`OrcaSlicer`] expects.
+pub fn extract_slicer_config(project_3mf: &Path, out_dir: &Path) -> Result<()> {
+    let mut archive = zip::ZipArchive::new(std::fs::File::open(project_3mf)?)
+        .with_context(|| format!("Invalid OrcaSlicer project: {}", project_3mf.display()))?;
+
+    let mut read_json = |name: &str| -> Result<Option<serde_json::Map<String, serde_json::Value>>> {
+        let mut file = match archive.by_name(name) {
+            Ok(file) => file,
+            Err(zip::result::ZipError::FileNotFound) => return Ok(None),
+            Err(e) => return Err(e.into()),
+        };
+
+        let mut contents = String::new();
+        file.read_to_string(&mut contents)?;
+        Ok(Some(
+            serde_json::from_str(&contents).with_context(|| format!("Invalid {}", name))?,
+        ))
+    };
+
+    let project_settings = read_json("Metadata/project_settings.config")?;
+
+    std::fs::create_dir_all(out_dir)?;
+
+    for (kind, id_key) in CONFIG_FILES {
+        // Projects made from user presets embed them directly, prefer those when they exist.
+        let config = match read_json(&format!("Metadata/{}_settings_1.config", kind))? {
+            Some(config) => config,
+            None => {
+                // Otherwise the project settings hold every setting flattened together, which
+                // OrcaSlicer will load as any of the config types.
+                let mut config = project_settings
+                    .clone()
+                    .ok_or_else(|| anyhow::anyhow!("No slicer settings found in {}", project_3mf.display()))?;
+
+                let name = match config.get(id_key) {
+                    Some(serde_json::Value::Array(names)) => names.first().cloned(),
+                    name => name.cloned(),
+                }
+                .unwrap_or_else(|| kind.into());
+
+                config.insert("type".into(), kind.into());
+                config.insert("name".into(), name);
+                config.insert("from".into(), "project".into());
+                config
+            }
+        };
+
+        let path = out_dir.join(format!("{}.json", kind));
+        std::fs::write(&path, serde_json::to_string_pretty(&config)?)
+            .with_context(|| format!("Failed to write {}", path.display()))?;
+    }
+
+    Ok(())
+}
+
+/// Get the paths to the config files in a slicer config directory, making sure they all exist.
+/// OrcaSlicer's own error for a missing config is buried in its output and doesn't say which.
+/// The filament configs are `filaments`, relative to the directory, joined with `;` the way
+/// OrcaSlicer takes them, or just `filament.json` if none are given.
+fn config_files(config: &Path, filaments: &[PathBuf]) -> Result<[String; 3]> {
+    // Make sure the config path is a directory.
+    if !config.is_dir() {
+        anyhow::bail!("Invalid slicer config path: {}, must be a directory", config.display());
+    }
+
+    let config_file = |name: &Path| -> Result<String> {
+        let file = config.join(name);
+        if !file.is_file() {
+            anyhow::bail!(
+                "Missing slicer config {}, expected it in {}",
+                name.display(),
+                config.display()
+            );
+        }
+
+        Ok(file
+            .to_str()
+            .ok_or_else(|| anyhow::anyhow!("Invalid slicer config path: {}", file.display()))?
+            .to_string())
+    };
+
+    let [(process, _), (machine, _), (filament, _)] = CONFIG_FILES;
+    let default_filaments = [PathBuf::from(format!("{}.json", filament))];
+    let filaments = if filaments.is_empty() {
+        &default_filaments[..]
     } else {
-        anyhow::bail!("OrcaSlicer not found")
+        filaments
+    };
+
+    Ok([
+        config_file(Path::new(&format!("{}.json", process)))?,
+        config_file(Path::new(&format!("{}.json", machine)))?,
+        filaments
+            .iter()
+            .map(|filament| config_file(filament))
+            .collect::<Result<Vec<_>>>()?
+            .join(";"),
+    ])
+}
+
+/// Find the orcaslicer executable.
+/// An explicit path wins, then the `ORCA_SLICER_PATH` environment variable, then the default
+/// install location for the OS, then `orca-slicer` on the `PATH`.
+fn find_orca_slicer(explicit: Option<&Path>) -> anyhow::Result<PathBuf> {
+    if let Some(path) = explicit {
+        if !path.exists() {
+            anyhow::bail!("OrcaSlicer not found at the configured path {}", path.display());
+        }
+        return Ok(path.to_path_buf());
+    }
+
+    if let Some(path) = std::env::var_os(ORCA_SLICER_PATH_ENV) {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            anyhow::bail!(
+                "OrcaSlicer not found at {} (from {})",
+                path.display(),
+                ORCA_SLICER_PATH_ENV
+            );
+        }
+        return Ok(path);
+    }
+
+    let default_path = default_orca_slicer_path();
+    if default_path.exists() {
+        return Ok(default_path);
     }
+
+    which::which("orca-slicer").map_err(|_| {
+        anyhow::anyhow!(
+            "OrcaSlicer not found at {} or on the PATH, set {} to its location",
+            default_path.display(),
+            ORCA_SLICER_PATH_ENV
+        )
+    })
 }
 
-// Find the orcaslicer executable path on Linux.
+// The default orcaslicer executable path on macOS.
+#[cfg(target_os = "macos")]
+fn default_orca_slicer_path() -> PathBuf {
+    PathBuf::from("/Applications/OrcaSlicer.app/Contents/MacOS/OrcaSlicer")
+}
+
+// The default orcaslicer executable path on Windows.
+#[cfg(target_os = "windows")]
+fn default_orca_slicer_path() -> PathBuf {
+    PathBuf::from("C:\\Program Files\\OrcaSlicer\\orca-slicer.exe")
+}
+
+// The default orcaslicer executable path on Linux.
 #[cfg(target_os = "linux")]
-fn find_orca_slicer() -> anyhow::Result<PathBuf> {
-    let app_path = std::path::PathBuf::from("/usr/bin/orca-slicer");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("OrcaSlicer not found")
+fn default_orca_slicer_path() -> PathBuf {
+    PathBuf::from("/usr/bin/orca-slicer")
+}
+
+#[cfg(test)]
+mod tests {
+    use std::io::Write;
+
+    use super::*;
+
+    #[cfg(unix)]
+    #[test]
+    fn test_is_outside_build_volume() {
+        use std::os::unix::process::ExitStatusExt;
+
+        let output = |stderr: &str| std::process::Output {
+            status: std::process::ExitStatus::from_raw(1 << 8),
+            stdout: vec![],
+            stderr: stderr.as_bytes().to_vec(),
+        };
+
+        assert!(is_outside_build_volume(&output(
+            "Nothing to be sliced, either the print is empty or no object is fully inside the plate"
+        )));
+        assert!(is_outside_build_volume(&output(
+            "run found error, return -50, exit: Some objects are located over the boundary of the heated bed."
+        )));
+        assert!(!is_outside_build_volume(&output("Invalid config file")));
+        // Other errors that happen to mention something being outside aren't the model not fitting.
+        assert!(!is_outside_build_volume(&output(
+            "Invalid value: support_threshold_angle is outside of its valid range"
+        )));
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_slice_timeout() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        let config = dir.path().join("config");
+        std::fs::create_dir(&config).unwrap();
+        for (kind, _) in CONFIG_FILES {
+            std::fs::write(config.join(format!("{}.json", kind)), "{}").unwrap();
+        }
+
+        // A slicer that hangs, leaving its pid behind so we can check it was killed.
+        let pid_file = dir.path().join("pid");
+        let binary = dir.path().join("orca-slicer");
+        std::fs::write(
+            &binary,
+            format!(
+                "#!/bin/sh\nif [ \"$1\" = \"--help\" ]; then echo 'OrcaSlicer-2.1.1:'; exit 0; fi\necho $$ > {}\nexec sleep 30\n",
+                pid_file.display()
+            ),
+        )
+        .unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let model = dir.path().join("model.stl");
+        std::fs::write(&model, "solid cube\nendsolid cube\n").unwrap();
+
+        let slicer = OrcaSlicer::new(config, Some(binary)).with_timeout(std::time::Duration::from_millis(500));
+        let err = slicer.slice_to(&model, &dir.path().join("out.3mf")).await.unwrap_err();
+        assert!(err.to_string().contains("timed out"), "{}", err);
+
+        let pid = std::fs::read_to_string(&pid_file).unwrap();
+        let alive = std::process::Command::new("kill")
+            .args(["-0", pid.trim()])
+            .status()
+            .unwrap();
+        assert!(!alive.success(), "orca-slicer {} is still running", pid.trim());
+    }
+
+    #[test]
+    fn test_input_format_detect() {
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        let file = |name: &str, contents: &[u8]| {
+            let path = dir.path().join(name);
+            std::fs::write(&path, contents).unwrap();
+            path
+        };
+
+        let ascii_stl = file("ascii.stl", b"solid cube\nendsolid cube\n");
+        assert_eq!(InputFormat::detect(&ascii_stl).unwrap(), InputFormat::Stl);
+        let binary_stl = file("binary.STL", &[0u8; 84]);
+        assert_eq!(InputFormat::detect(&binary_stl).unwrap(), InputFormat::Stl);
+        let three_mf = file("model.3mf", b"PK\x03\x04rest of the zip");
+        assert_eq!(InputFormat::detect(&three_mf).unwrap(), InputFormat::ThreeMf);
+        let step = file("part.step", b"ISO-10303-21;\nHEADER;\n");
+        assert_eq!(InputFormat::detect(&step).unwrap(), InputFormat::Step);
+        let stp = file("part.stp", b"ISO-10303-21;\nHEADER;\n");
+        assert_eq!(InputFormat::detect(&stp).unwrap(), InputFormat::Step);
+
+        let obj = file("model.obj", b"v 0 0 0\n");
+        let err = InputFormat::detect(&obj).unwrap_err().to_string();
+        assert!(err.contains("Unsupported file type"), "{}", err);
+
+        let fake_step = file("fake.step", b"solid cube\n");
+        assert!(InputFormat::detect(&fake_step).is_err());
+        let short_stl = file("short.stl", &[0u8; 10]);
+        assert!(InputFormat::detect(&short_stl).is_err());
+    }
+
+    #[test]
+    fn test_extra_args() {
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        for (kind, _) in CONFIG_FILES {
+            std::fs::write(dir.path().join(format!("{}.json", kind)), "{}").unwrap();
+        }
+
+        let slicer = OrcaSlicer::new(dir.path().to_path_buf(), None).with_extra_args(
+            [
+                "--arrange",
+                "1",
+                "--export-3mf",
+                "/elsewhere.3mf",
+                "--outputdir=/elsewhere",
+                "--rotate",
+                "90",
+            ]
+            .map(String::from)
+            .to_vec(),
+        );
+        let args = slicer.args(Path::new("model.stl"), Path::new("out.3mf"), 1.0).unwrap();
+
+        let slice = args.iter().position(|arg| arg == "--slice").unwrap();
+        assert_eq!(args[slice - 4..slice], ["--arrange", "1", "--rotate", "90"]);
+        assert_eq!(args.iter().filter(|arg| arg.as_str() == "--export-3mf").count(), 1);
+        assert!(!args.iter().any(|arg| arg.contains("elsewhere")));
+        assert_eq!(args.last().unwrap(), "model.stl");
+    }
+
+    #[test]
+    fn test_output_format() {
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        for (kind, _) in CONFIG_FILES {
+            std::fs::write(dir.path().join(format!("{}.json", kind)), "{}").unwrap();
+        }
+
+        let slicer = OrcaSlicer::new(dir.path().to_path_buf(), None);
+        assert!(slicer.temp_output_path().extension().is_some_and(|ext| ext == "3mf"));
+        let args = slicer.args(Path::new("model.stl"), Path::new("out.3mf"), 1.0).unwrap();
+        assert!(args.iter().any(|arg| arg == "--export-3mf"));
+
+        let slicer = slicer.with_output_format(OutputFormat::Gcode);
+        assert!(slicer.temp_output_path().extension().is_some_and(|ext| ext == "gcode"));
+        let args = slicer
+            .args(Path::new("model.stl"), Path::new("out.gcode"), 1.0)
+            .unwrap();
+        assert!(args.iter().any(|arg| arg == "--export-gcode"));
+        assert!(!args.iter().any(|arg| arg == "--export-3mf"));
+    }
+
+    #[test]
+    fn test_parse_version() {
+        assert_eq!(
+            parse_version("OrcaSlicer-2.1.1:\nUsage: orca-slicer [ OPTIONS ] [ file.3mf/file.stl ... ]\n"),
+            Some(semver::Version::new(2, 1, 1))
+        );
+        assert_eq!(
+            parse_version("OrcaSlicer-1.9.1.3:\n"),
+            Some(semver::Version::new(1, 9, 1))
+        );
+        assert_eq!(parse_version("OrcaSlicer-2.2:\n"), Some(semver::Version::new(2, 2, 0)));
+        assert_eq!(parse_version("Usage: orca-slicer\n"), None);
+    }
+
+    #[cfg(unix)]
+    #[tokio::test]
+    async fn test_version_too_old() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        let binary = dir.path().join("orca-slicer");
+        std::fs::write(&binary, "#!/bin/sh\necho 'OrcaSlicer-1.5.0:'\n").unwrap();
+        std::fs::set_permissions(&binary, std::fs::Permissions::from_mode(0o755)).unwrap();
+
+        let slicer = OrcaSlicer::new(dir.path().to_path_buf(), Some(binary));
+        assert_eq!(slicer.version().await.unwrap(), semver::Version::new(1, 5, 0));
+
+        let err = slicer.ensure_supported_version().await.unwrap_err().to_string();
+        assert!(err.contains("upgrade"), "{}", err);
+    }
+
+    #[test]
+    fn test_parse_progress() {
+        assert_eq!(
+            parse_progress(
+                "[2024-10-01 10:00:00.000000] [0x1] [info]   default_status_callback: percent=40, warning_step=-1, message=Generating perimeters"
+            ),
+            Some(SliceProgress {
+                stage: "Generating perimeters".to_string(),
+                percent: Some(40),
+            })
+        );
+        assert_eq!(
+            parse_progress("default_status_callback: percent=-1, warning_step=-1, message=Slicing"),
+            Some(SliceProgress {
+                stage: "Slicing".to_string(),
+                percent: None,
+            })
+        );
+        assert_eq!(parse_progress("Loading model"), None);
+    }
+
+    #[test]
+    fn test_config_files_missing() {
+        for (missing, _) in CONFIG_FILES {
+            let dir = tempdir::TempDir::new("orca").unwrap();
+            for (kind, _) in CONFIG_FILES.iter().filter(|(kind, _)| *kind != missing) {
+                std::fs::write(dir.path().join(format!("{}.json", kind)), "{}").unwrap();
+            }
+
+            let err = config_files(dir.path(), &[]).unwrap_err().to_string();
+            assert!(err.contains(&format!("{}.json", missing)), "{}", err);
+            assert!(err.contains(&dir.path().display().to_string()), "{}", err);
+        }
+    }
+
+    #[test]
+    fn test_config_files() {
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        for (kind, _) in CONFIG_FILES {
+            std::fs::write(dir.path().join(format!("{}.json", kind)), "{}").unwrap();
+        }
+
+        let [process, machine, filament] = config_files(dir.path(), &[]).unwrap();
+        assert!(process.ends_with("process.json"));
+        assert!(machine.ends_with("machine.json"));
+        assert!(filament.ends_with("filament.json"));
+
+        assert!(config_files(&dir.path().join("process.json"), &[]).is_err());
+    }
+
+    #[test]
+    fn test_config_files_multiple_filaments() {
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        for name in ["process.json", "machine.json", "pla-white.json", "pla-black.json"] {
+            std::fs::write(dir.path().join(name), "{}").unwrap();
+        }
+
+        let filaments = [PathBuf::from("pla-white.json"), PathBuf::from("pla-black.json")];
+        let [_, _, filament] = config_files(dir.path(), &filaments).unwrap();
+        assert_eq!(
+            filament,
+            format!(
+                "{};{}",
+                dir.path().join("pla-white.json").display(),
+                dir.path().join("pla-black.json").display()
+            )
+        );
+
+        let missing = [PathBuf::from("pla-white.json"), PathBuf::from("petg.json")];
+        let err = config_files(dir.path(), &missing).unwrap_err().to_string();
+        assert!(err.contains("petg.json"), "{}", err);
+    }
+
+    #[test]
+    fn test_find_orca_slicer_explicit() {
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        let binary = dir.path().join("orca-slicer");
+        std::fs::write(&binary, "").unwrap();
+
+        assert_eq!(find_orca_slicer(Some(&binary)).unwrap(), binary);
+        assert!(find_orca_slicer(Some(&dir.path().join("missing"))).is_err());
+    }
+
+    #[test]
+    fn test_extract_slicer_config() {
+        let dir = tempdir::TempDir::new("orca").unwrap();
+        let project = dir.path().join("project.3mf");
+
+        let mut zip = zip::ZipWriter::new(std::fs::File::create(&project).unwrap());
+        zip.start_file(
+            "Metadata/project_settings.config",
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        zip.write_all(
+            br#"{
+                "print_settings_id": "0.20mm Standard @BBL X1C",
+                "printer_settings_id": "Bambu Lab X1 Carbon 0.4 nozzle",
+                "filament_settings_id": ["Bambu PLA Basic @BBL X1C"],
+                "layer_height": "0.2"
+            }"#,
+        )
+        .unwrap();
+        zip.start_file(
+            "Metadata/filament_settings_1.config",
+            zip::write::SimpleFileOptions::default(),
+        )
+        .unwrap();
+        zip.write_all(br#"{"type": "filament", "name": "My PLA"}"#).unwrap();
+        zip.finish().unwrap();
+
+        let out_dir = dir.path().join("config");
+        extract_slicer_config(&project, &out_dir).unwrap();
+
+        let read = |name: &str| -> serde_json::Value {
+            serde_json::from_str(&std::fs::read_to_string(out_dir.join(name)).unwrap()).unwrap()
+        };
+
+        let process = read("process.json");
+        assert_eq!(process["type"], "process");
+        assert_eq!(process["name"], "0.20mm Standard @BBL X1C");
+        assert_eq!(process["layer_height"], "0.2");
+
+        let machine = read("machine.json");
+        assert_eq!(machine["type"], "machine");
+        assert_eq!(machine["name"], "Bambu Lab X1 Carbon 0.4 nozzle");
+
+        let filament = read("filament.json");
+        assert_eq!(filament["name"], "My PLA");
     }
 }
@@ -0,0 +1,163 @@
+//! Limit how many files are sliced at once.
+
+use std::sync::{
+    atomic::{AtomicUsize, Ordering},
+    Arc,
+};
+
+use anyhow::Result;
+
+use crate::slicer::{SliceOptions, SliceOutput, SliceProgress, SlicedOutput, Slicer};
+
+/// A limit on how many slices run at once, shared by the slicers wrapped with it.
+/// Slicing is CPU and memory hungry, so a burst of print requests would otherwise start a slicer
+/// process for each of them.
+#[derive(Debug, Clone)]
+pub struct SlicerPool {
+    permits: Arc<tokio::sync::Semaphore>,
+    /// How many slices are waiting for a permit.
+    queued: Arc<AtomicUsize>,
+}
+
+impl SlicerPool {
+    /// Create a pool running at most `max_concurrent` slices at once.
+    pub fn new(max_concurrent: usize) -> Self {
+        Self {
+            permits: Arc::new(tokio::sync::Semaphore::new(max_concurrent.max(1))),
+            queued: Arc::new(AtomicUsize::new(0)),
+        }
+    }
+
+    /// Get how many slices are waiting for another to finish.
+    pub fn queue_depth(&self) -> usize {
+        self.queued.load(Ordering::Relaxed)
+    }
+
+    /// Wrap a slicer so its slices count against the pool.
+    pub fn slicer(&self, slicer: Box<dyn Slicer>) -> PooledSlicer {
+        PooledSlicer {
+            pool: self.clone(),
+            slicer,
+        }
+    }
+
+    /// Wait for a free permit to slice with.
+    async fn acquire(&self) -> Result<tokio::sync::OwnedSemaphorePermit> {
+        let _queued = Queued::new(&self.queued);
+        if self.permits.available_permits() == 0 {
+            tracing::debug!(queue_depth = self.queue_depth(), "Waiting for a free slicer");
+        }
+
+        Ok(self.permits.clone().acquire_owned().await?)
+    }
+}
+
+/// Counts a slice as queued until dropped, so a slice cancelled while waiting isn't left
+/// counted.
+struct Queued<'a>(&'a AtomicUsize);
+
+impl<'a> Queued<'a> {
+    fn new(queued: &'a AtomicUsize) -> Self {
+        queued.fetch_add(1, Ordering::Relaxed);
+        Self(queued)
+    }
+}
+
+impl Drop for Queued<'_> {
+    fn drop(&mut self) {
+        self.0.fetch_sub(1, Ordering::Relaxed);
+    }
+}
+
+impl Default for SlicerPool {
+    /// One slice at a time per physical core.
+    fn default() -> Self {
+        Self::new(num_cpus::get_physical())
+    }
+}
+
+/// A slicer that waits its turn in a [`SlicerPool`].
+pub struct PooledSlicer {
+    pool: SlicerPool,
+    slicer: Box<dyn Slicer>,
+}
+
+#[async_trait::async_trait]
+impl Slicer for PooledSlicer {
+    async fn slice(&self, file: &std::path::Path) -> Result<std::path::PathBuf> {
+        let _permit = self.pool.acquire().await?;
+        self.slicer.slice(file).await
+    }
+
+    async fn slice_to(&self, file: &std::path::Path, output: &std::path::Path) -> Result<()> {
+        let _permit = self.pool.acquire().await?;
+        self.slicer.slice_to(file, output).await
+    }
+
+    async fn slice_with_options(&self, file: &std::path::Path, options: &SliceOptions) -> Result<SliceOutput> {
+        let _permit = self.pool.acquire().await?;
+        self.slicer.slice_with_options(file, options).await
+    }
+
+    async fn slice_with_progress(
+        &self,
+        file: &std::path::Path,
+        sink: tokio::sync::mpsc::Sender<SliceProgress>,
+    ) -> Result<std::path::PathBuf> {
+        let _permit = self.pool.acquire().await?;
+        self.slicer.slice_with_progress(file, sink).await
+    }
+
+    async fn slice_detailed(&self, file: &std::path::Path) -> Result<SlicedOutput> {
+        let _permit = self.pool.acquire().await?;
+        self.slicer.slice_detailed(file).await
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::slicer::mock::MockSlicer;
+
+    #[tokio::test]
+    async fn test_slicer_pool_queues() {
+        let dir = tempdir::TempDir::new("pool").unwrap();
+        let model = dir.path().join("model.stl");
+        std::fs::write(&model, "solid cube\nendsolid cube\n").unwrap();
+
+        let slow = || Box::new(MockSlicer::new().with_delay(std::time::Duration::from_millis(200)));
+        let pool = SlicerPool::new(1);
+        let first = Arc::new(pool.slicer(slow()));
+        let second = Arc::new(pool.slicer(slow()));
+
+        let running = tokio::spawn({
+            let (first, output) = (first.clone(), dir.path().join("first.3mf"));
+            let model = model.clone();
+            async move { first.slice_to(&model, &output).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+
+        let queued = tokio::spawn({
+            let (second, output) = (second.clone(), dir.path().join("second.3mf"));
+            let model = model.clone();
+            async move { second.slice_to(&model, &output).await }
+        });
+        tokio::time::sleep(std::time::Duration::from_millis(50)).await;
+        assert_eq!(pool.queue_depth(), 1);
+
+        running.await.unwrap().unwrap();
+        queued.await.unwrap().unwrap();
+        assert_eq!(pool.queue_depth(), 0);
+    }
+
+    #[tokio::test]
+    async fn test_slicer_pool_cancelled_while_queued() {
+        let pool = SlicerPool::new(1);
+        let _running = pool.acquire().await.unwrap();
+
+        // Given up on while waiting for the running slice.
+        let waited = tokio::time::timeout(std::time::Duration::from_millis(50), pool.acquire()).await;
+        assert!(waited.is_err());
+        assert_eq!(pool.queue_depth(), 0);
+    }
+}
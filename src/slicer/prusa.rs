@@ -19,7 +19,17 @@ impl PrusaSlicer {
 impl Slicer for PrusaSlicer {
     async fn slice(&self, file: &std::path::Path) -> Result<std::path::PathBuf> {
         let uid = uuid::Uuid::new_v4();
-        let gcode_path = std::env::temp_dir().join(format!("{}.3mf", uid));
+        let gcode_path = std::env::temp_dir().join(format!("{}.gcode", uid));
+
+        self.slice_to(file, &gcode_path).await?;
+
+        Ok(gcode_path)
+    }
+
+    async fn slice_to(&self, file: &std::path::Path, gcode_path: &std::path::Path) -> Result<()> {
+        if let Some(parent) = gcode_path.parent() {
+            tokio::fs::create_dir_all(parent).await?;
+        }
 
         let args: Vec<String> = vec![
             "--load".to_string(),
@@ -57,39 +67,57 @@ impl Slicer for PrusaSlicer {
             anyhow::bail!("Failed to create G-code file");
         }
 
-        Ok(gcode_path.to_path_buf())
+        Ok(())
     }
 }
 
-// Find the prusaslicer executable path on macOS.
-#[cfg(target_os = "macos")]
+/// The environment variable to point at the PrusaSlicer executable with.
+const PRUSA_SLICER_PATH_ENV: &str = "PRUSA_SLICER_PATH";
+
+/// Find the prusaslicer executable.
+/// The `PRUSA_SLICER_PATH` environment variable wins, then the default install location for the
+/// OS, then `prusa-slicer` on the `PATH`.
 fn find_prusa_slicer() -> anyhow::Result<PathBuf> {
-    let app_path = std::path::PathBuf::from("/Applications/PrusaSlicer.app/Contents/MacOS/PrusaSlicer");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("PrusaSlicer not found")
+    if let Some(path) = std::env::var_os(PRUSA_SLICER_PATH_ENV) {
+        let path = PathBuf::from(path);
+        if !path.exists() {
+            anyhow::bail!(
+                "PrusaSlicer not found at {} (from {})",
+                path.display(),
+                PRUSA_SLICER_PATH_ENV
+            );
+        }
+        return Ok(path);
+    }
+
+    let default_path = default_prusa_slicer_path();
+    if default_path.exists() {
+        return Ok(default_path);
     }
+
+    which::which("prusa-slicer").map_err(|_| {
+        anyhow::anyhow!(
+            "PrusaSlicer not found at {} or on the PATH, set {} to its location",
+            default_path.display(),
+            PRUSA_SLICER_PATH_ENV
+        )
+    })
+}
+
+// The default prusaslicer executable path on macOS.
+#[cfg(target_os = "macos")]
+fn default_prusa_slicer_path() -> PathBuf {
+    PathBuf::from("/Applications/PrusaSlicer.app/Contents/MacOS/PrusaSlicer")
 }
 
-// Find the prusaslicer executable path on Windows.
+// The default prusaslicer executable path on Windows.
 #[cfg(target_os = "windows")]
-fn find_prusa_slicer() -> anyhow::Result<PathBuf> {
-    let app_path = std::path::PathBuf::from("C:\\Program Files\\PrusaSlicer\\PrusaSlicer.exe");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("PrusaSlicer not found")
-    }
+fn default_prusa_slicer_path() -> PathBuf {
+    PathBuf::from("C:\\Program Files\\PrusaSlicer\\PrusaSlicer.exe")
 }
 
-// Find the prusaslicer executable path on Linux.
+// The default prusaslicer executable path on Linux.
 #[cfg(target_os = "linux")]
-fn find_prusa_slicer() -> anyhow::Result<PathBuf> {
-    let app_path = std::path::PathBuf::from("/usr/bin/prusa-slicer");
-    if app_path.exists() {
-        Ok(app_path)
-    } else {
-        anyhow::bail!("PrusaSlicer not found")
-    }
+fn default_prusa_slicer_path() -> PathBuf {
+    PathBuf::from("/usr/bin/prusa-slicer")
 }
@@ -6,31 +6,38 @@ use pretty_assertions::assert_eq;
 use test_context::{test_context, AsyncTestContext};
 use testresult::TestResult;
 
-use crate::config::Config;
+use crate::{config::Config, network_printer::NetworkPrinters};
 
 struct ServerContext {
     config: crate::Server,
     server: dropshot::HttpServer<Arc<crate::server::context::Context>>,
+    context: Arc<crate::server::context::Context>,
     client: reqwest::Client,
 }
 
 impl ServerContext {
     pub async fn new() -> Result<Self> {
+        Self::with_config(Default::default()).await
+    }
+
+    /// Start a server with the printers in `config_file`.
+    pub async fn with_config(config_file: Config) -> Result<Self> {
         // Find an unused port.
         let port = portpicker::pick_unused_port().ok_or_else(|| anyhow::anyhow!("no port available"))?;
         let config = crate::Server {
             address: format!("127.0.0.1:{}", port),
+            printer_cache: None,
         };
 
         // Create the server in debug mode.
-        let config_file: Config = Default::default();
-        let (server, _context) = crate::server::create_server(
+        let (server, context) = crate::server::create_server(
             &config,
             &crate::Opts {
                 debug: true,
                 json: false,
                 subcmd: crate::SubCommand::Server(config.clone()),
                 config: Default::default(),
+                bambu_config: None,
             },
             &config_file,
         )
@@ -42,6 +49,7 @@ impl ServerContext {
         Ok(ServerContext {
             config,
             server,
+            context,
             client: reqwest::Client::new(),
         })
     }
@@ -157,3 +165,40 @@ async fn test_ping(ctx: &mut ServerContext) -> TestResult {
 
     Ok(())
 }
+
+#[tokio::test]
+async fn test_printer_without_serial() -> TestResult {
+    // Nothing answers at the printer's address, but it's still added from the config.
+    let printer_port = portpicker::pick_unused_port().ok_or_else(|| anyhow::anyhow!("no port available"))?;
+    let config = Config::from_str(&format!(
+        r#"
+        [prusalink]
+        machines = [
+            {{ id = "workshop-mk4", url = "http://127.0.0.1:{}", api_key = "abcdef123456" }},
+        ]
+        "#,
+        printer_port
+    ))?;
+    let ctx = ServerContext::with_config(config).await?;
+    ctx.context.network_printers.discover().await?;
+
+    // PrusaLink printers have no serial, so they're found by name or address instead.
+    for id in ["workshop-mk4", "127.0.0.1"] {
+        let response = ctx
+            .client
+            .get(ctx.get_url(&format!("printers/{}/capabilities", id)))
+            .send()
+            .await?;
+        assert_eq!(response.status(), reqwest::StatusCode::OK, "not found by {}", id);
+    }
+
+    let response = ctx
+        .client
+        .get(ctx.get_url("printers/garage-mk4/capabilities"))
+        .send()
+        .await?;
+    assert_eq!(response.status(), reqwest::StatusCode::NOT_FOUND);
+
+    ctx.stop().await?;
+    Ok(())
+}
@@ -124,8 +124,8 @@ impl UsbPrinter {
     }
 
     pub async fn slice_and_print(&mut self, file: &std::path::Path) -> Result<Message> {
-        let gcode = self.slice(file).await?;
-        self.print(&gcode).await
+        let gcode = crate::slicer::SlicedOutput::new(self.slice(file).await?);
+        self.print(gcode.path()).await
     }
 
     pub fn status(&self) -> Result<Message> {